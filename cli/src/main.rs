@@ -2,6 +2,16 @@
 
 use clap::{Parser, Subcommand};
 use anyhow::Result;
+use quantum_core::config::StorageConfig;
+use quantum_core::storage::{
+    check_integrity, migration, BlockImporter, BlockStore, ChainFileReader, ChainFileWriter,
+    Database, PruningMode, SnapshotDir, SnapshotStore, StateHistory, SCHEMA_VERSION,
+};
+use quantum_core::types::{Address, Balance};
+use quantum_core::vm::executor::TransactionExecutor;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[command(name = "quantum-cli")]
@@ -28,6 +38,21 @@ enum Commands {
         #[command(subcommand)]
         action: TxAction,
     },
+    /// State snapshot operations
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+    /// Block export and import
+    Chain {
+        #[command(subcommand)]
+        action: ChainAction,
+    },
+    /// Database maintenance
+    Db {
+        #[command(subcommand)]
+        action: DbAction,
+    },
 }
 
 #[derive(Subcommand)]
@@ -47,7 +72,7 @@ enum AccountAction {
     /// Get account balance
     Balance {
         /// Account address
-        address: String,
+        address: Address,
     },
 }
 
@@ -56,12 +81,99 @@ enum TxAction {
     /// Send transaction
     Send {
         /// Recipient address
-        to: String,
+        to: Address,
         /// Amount to send
-        amount: u128,
+        amount: Balance,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotAction {
+    /// Copy a node's periodic snapshot to a directory
+    Export {
+        /// Node data directory
+        #[arg(long, default_value = "./data")]
+        data_dir: String,
+        /// Block number of the snapshot (latest if omitted)
+        #[arg(long)]
+        number: Option<u64>,
+        /// Output directory
+        output: String,
+    },
+    /// Start an empty chain from an exported snapshot directory
+    Import {
+        /// Node data directory
+        #[arg(long, default_value = "./data")]
+        data_dir: String,
+        /// Snapshot directory
+        input: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ChainAction {
+    /// Write canonical blocks to a chain file
+    Export {
+        /// Node data directory
+        #[arg(long, default_value = "./data")]
+        data_dir: String,
+        /// First block to export
+        #[arg(long, default_value_t = 0)]
+        from: u64,
+        /// Last block to export (head if omitted)
+        #[arg(long)]
+        to: Option<u64>,
+        /// Output file
+        output: String,
+    },
+    /// Validate and import blocks from a chain file
+    Import {
+        /// Node data directory
+        #[arg(long, default_value = "./data")]
+        data_dir: String,
+        /// Chain file
+        input: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbAction {
+    /// Check schema compatibility and database integrity
+    Check {
+        /// Node data directory
+        #[arg(long, default_value = "./data")]
+        data_dir: String,
+    },
+    /// Upgrade the database to the current schema version
+    Migrate {
+        /// Node data directory
+        #[arg(long, default_value = "./data")]
+        data_dir: String,
+    },
+    /// Print the balance, code and storage of an account
+    Storage {
+        /// Node data directory
+        #[arg(long, default_value = "./data")]
+        data_dir: String,
+        /// Canonical block to read the state of (head if omitted)
+        #[arg(long)]
+        number: Option<u64>,
+        /// Account address
+        address: Address,
     },
 }
 
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn storage_config(data_dir: String) -> StorageConfig {
+    StorageConfig {
+        db_path: data_dir,
+        ..StorageConfig::default()
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
@@ -95,5 +207,169 @@ async fn main() -> Result<()> {
                 Ok(())
             }
         },
+        Commands::Snapshot { action } => match action {
+            SnapshotAction::Export { data_dir, number, output } => {
+                let config = storage_config(data_dir);
+                let snapshots = SnapshotStore::for_node(&config);
+                let number = match number {
+                    Some(number) => number,
+                    None => snapshots
+                        .latest()?
+                        .ok_or_else(|| anyhow::anyhow!("No snapshots in {}", config.db_path))?,
+                };
+                let output_dir = SnapshotDir::new(&output);
+                let manifest = snapshots.snapshot(number).copy_to(&output_dir)?;
+                // The importing node starts its chain at this block
+                let blocks = BlockStore::new(Arc::new(Database::open(&config)?));
+                let block = blocks.block_by_number(manifest.block_number)?.ok_or_else(|| {
+                    anyhow::anyhow!("Block {} is not stored", manifest.block_number)
+                })?;
+                let genesis_hash = blocks
+                    .genesis_hash()?
+                    .ok_or_else(|| anyhow::anyhow!("No genesis block in {}", config.db_path))?;
+                output_dir.write_block(&block, genesis_hash)?;
+                println!(
+                    "Exported snapshot of block {} ({} chunks, state root {}) to {}",
+                    manifest.block_number,
+                    manifest.chunk_hashes.len(),
+                    manifest.state_root,
+                    output
+                );
+                Ok(())
+            }
+            SnapshotAction::Import { data_dir, input } => {
+                let db = Arc::new(Database::open(&storage_config(data_dir))?);
+                let snapshot = SnapshotDir::new(&input);
+                let hash = snapshot.restore_chain(db)?;
+                let manifest = snapshot.manifest()?;
+                println!(
+                    "Imported snapshot of block {} ({}) with state root {}",
+                    manifest.block_number, hash, manifest.state_root
+                );
+                Ok(())
+            }
+        },
+        Commands::Chain { action } => match action {
+            ChainAction::Export { data_dir, from, to, output } => {
+                let db = Arc::new(Database::open(&storage_config(data_dir))?);
+                let blocks = BlockStore::new(db);
+                let to = match to {
+                    Some(to) => to,
+                    None => blocks
+                        .head()?
+                        .ok_or_else(|| anyhow::anyhow!("No blocks to export"))?
+                        .number,
+                };
+
+                let mut writer = ChainFileWriter::new(BufWriter::new(File::create(&output)?))?;
+                for number in from..=to {
+                    let block = blocks
+                        .block_by_number(number)?
+                        .ok_or_else(|| anyhow::anyhow!("Missing canonical block {}", number))?;
+                    writer.write_block(&block)?;
+                }
+                let count = writer.blocks();
+                writer.finish()?;
+                println!("Exported {} blocks ({}..={}) to {}", count, from, to, output);
+                Ok(())
+            }
+            ChainAction::Import { data_dir, input } => {
+                let config = storage_config(data_dir);
+                let db = Arc::new(Database::open(&config)?);
+                let mut importer = BlockImporter::new(db, TransactionExecutor::new())
+                    .with_pruning(PruningMode::from_config(&config));
+                if let Some(snapshots) = SnapshotStore::from_config(&config) {
+                    importer = importer.with_snapshots(snapshots);
+                }
+
+                let mut count = 0u64;
+                for block in ChainFileReader::new(BufReader::new(File::open(&input)?))? {
+                    let block = block?;
+                    let number = block.header.number;
+                    importer
+                        .import_block(&block)
+                        .map_err(|e| anyhow::anyhow!("Block {}: {}", number, e))?;
+                    count += 1;
+                }
+                let head = importer.blocks().head()?.map_or(0, |header| header.number);
+                println!("Imported {} blocks, head is now block {}", count, head);
+                Ok(())
+            }
+        },
+        Commands::Db { action } => match action {
+            DbAction::Check { data_dir } => {
+                let config = storage_config(data_dir);
+                let db = Arc::new(Database::open_unchecked(&config)?);
+                match db.schema_version()? {
+                    None => println!("Schema: empty database"),
+                    Some(SCHEMA_VERSION) => println!("Schema: version {} (current)", SCHEMA_VERSION),
+                    Some(found) => {
+                        let path = migration::migration_path(migration::MIGRATIONS, found, SCHEMA_VERSION)?;
+                        println!("Schema: version {}, upgraded on next open by:", found);
+                        for step in path {
+                            println!("  {} -> {}: {}", step.from, step.from + 1, step.description);
+                        }
+                        return Ok(());
+                    }
+                }
+
+                let report = check_integrity(db, PruningMode::from_config(&config))?;
+                match report.head {
+                    Some(head) => println!("Chain: {} canonical blocks, head {}", report.blocks, head),
+                    None => println!("Chain: empty"),
+                }
+                println!(
+                    "State: {} accounts, {} storage slots",
+                    report.accounts, report.storage_slots
+                );
+                for issue in &report.issues {
+                    println!("  {}", issue);
+                }
+                if !report.is_ok() {
+                    anyhow::bail!("Found {} integrity issues", report.issues.len());
+                }
+                println!("No integrity issues found");
+                Ok(())
+            }
+            DbAction::Migrate { data_dir } => {
+                let config = storage_config(data_dir);
+                let found = Database::open_unchecked(&config)?.schema_version()?;
+                Database::open(&config)?;
+                match found {
+                    Some(found) if found < SCHEMA_VERSION => {
+                        println!("Migrated schema version {} to {}", found, SCHEMA_VERSION)
+                    }
+                    _ => println!("Schema version {} is current", SCHEMA_VERSION),
+                }
+                Ok(())
+            }
+            DbAction::Storage { data_dir, number, address } => {
+                let db = Arc::new(Database::open(&storage_config(data_dir))?);
+                let number = match number {
+                    Some(number) => number,
+                    None => BlockStore::new(db.clone())
+                        .head()?
+                        .ok_or_else(|| anyhow::anyhow!("No canonical head"))?
+                        .number,
+                };
+                let state = StateHistory::new(db).at_block(number)?;
+                let account = state
+                    .get_account(&address)?
+                    .ok_or_else(|| anyhow::anyhow!("No account {} at block {}", address, number))?;
+
+                println!("Account {} at block {}", address, number);
+                println!("Balance {}, nonce {}", account.balance, account.nonce);
+                if let Some(code_hash) = account.code_hash {
+                    let size = state.get_code(&code_hash)?.map_or(0, |code| code.len());
+                    println!("Code {} ({} bytes)", code_hash, size);
+                }
+                println!("Storage root {}", account.storage_root);
+                for slot in state.storage_trie(&address)?.iter() {
+                    let (key, value) = slot?;
+                    println!("  0x{} = 0x{}", hex(&key), hex(&value));
+                }
+                Ok(())
+            }
+        },
     }
 }
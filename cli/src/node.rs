@@ -3,8 +3,11 @@
 use quantum_core::prelude::*;
 use quantum_core::config::ChainConfig;
 use quantum_core::consensus::HybridConsensus;
+use quantum_core::vm::executor::TransactionExecutor;
 use clap::Parser;
 use anyhow::Result;
+use std::path::Path;
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[command(name = "quantum-node")]
@@ -14,6 +17,14 @@ struct Args {
     #[arg(long, default_value = "testnet")]
     chain: String,
     
+    /// Chain configuration file (JSON); defaults are used if omitted
+    #[arg(long)]
+    config: Option<String>,
+    
+    /// Data directory (overrides the configured database path)
+    #[arg(long)]
+    data_dir: Option<String>,
+    
     /// Run as validator
     #[arg(long)]
     validator: bool,
@@ -26,9 +37,9 @@ struct Args {
     #[arg(long, default_value = "9933")]
     rpc_port: u16,
     
-    /// P2P port
-    #[arg(long, default_value = "30333")]
-    p2p_port: u16,
+    /// P2P port (overrides the configured listen address)
+    #[arg(long)]
+    p2p_port: Option<u16>,
 }
 
 #[tokio::main]
@@ -41,24 +52,106 @@ async fn main() -> Result<()> {
     tracing::info!("Chain: {}", args.chain);
     tracing::info!("Validator mode: {}", args.validator);
     tracing::info!("RPC port: {}", args.rpc_port);
-    tracing::info!("P2P port: {}", args.p2p_port);
     
     // Load configuration
-    let config = ChainConfig::default();
+    let mut config = match &args.config {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => ChainConfig::default(),
+    };
+    if let Some(data_dir) = args.data_dir {
+        config.storage.db_path = data_dir;
+    }
+    if let Some(port) = args.p2p_port {
+        config.network.listen_addr = format!("/ip4/0.0.0.0/tcp/{}", port);
+    }
+    tracing::info!("P2P address: {}", config.network.listen_addr);
+    
+    // Open the chain
+    let db = Arc::new(Database::open(&config.storage)?);
     
     // Initialize consensus
-    let consensus = HybridConsensus::new(config.consensus);
+    let consensus = HybridConsensus::new(config.consensus.clone()).with_store(db.clone());
     
     tracing::info!("✅ Consensus engine initialized");
     
-    // TODO: Start networking
+    let blocks = BlockStore::new(db.clone());
+    let genesis_hash = blocks.genesis_hash()?.ok_or_else(|| {
+        anyhow::anyhow!("No genesis block in {}; import a chain first", config.storage.db_path)
+    })?;
+    let best = blocks.head()?.map_or(0, |header| header.number);
+    let finalized = blocks.finalized()?.unwrap_or(0);
+    tracing::info!("✅ Chain opened at block {} (finalized {})", best, finalized);
+    
+    // Start networking
+    let keypair = load_or_generate_keypair(&Path::new(&config.storage.db_path).join(NODE_KEY_FILE))?;
+    let (network, mut events) = P2PNetwork::new(&config, keypair, genesis_hash)?;
+    let snapshots = SnapshotStore::for_node(&config.storage);
+    let network = network
+        // Stateless checks until a mempool validates against state
+        .with_gossip_validator(Arc::new(StatelessValidator))
+        // Also reports the current head in every status sent to peers
+        .with_sync_provider(Arc::new(SyncServer::new(blocks, snapshots)));
+    tracing::info!("✅ Network started, peer ID {}", network.local_peer_id());
+    let handle = network.handle();
+    tokio::spawn(network.run());
+    
+    // Catch up with peers that are ahead, one sync at a time
+    let validators = consensus.get_validators().await?;
+    let chain = BlockStore::new(db.clone());
+    let (sync_requests, mut sync_peers) = tokio::sync::mpsc::unbounded_channel::<PeerId>();
+    if validators.is_empty() {
+        // Without a validator set no finality proof can be checked
+        tracing::warn!("No validators configured; block sync is disabled");
+        drop(sync_peers);
+    } else {
+        let importer = BlockImporter::new(db, TransactionExecutor::new())
+            .with_validators(validators.clone());
+        let mut sync = ChainSync::new(handle.clone(), importer, validators);
+        tokio::spawn(async move {
+            while let Some(peer) = sync_peers.recv().await {
+                match sync.sync(&[peer]).await {
+                    Ok(head) => {
+                        tracing::info!("Synced to block {} from {}", head, peer);
+                        let finalized = sync.importer().blocks().finalized().ok().flatten();
+                        let _ = handle.set_head(head, finalized.unwrap_or(0));
+                    }
+                    Err(e) => tracing::warn!("Sync from {} failed: {}", peer, e),
+                }
+            }
+        });
+    }
+    
     // TODO: Start RPC server
     // TODO: Start block production (if validator)
     
     tracing::info!("🎉 Node is running!");
     
+    // Dropped when sync is disabled
+    let sync_if_ahead = |peer: PeerId, best: BlockNumber| -> Result<()> {
+        if best > chain.head()?.map_or(0, |header| header.number) {
+            let _ = sync_requests.send(peer);
+        }
+        Ok(())
+    };
+    
     // Keep running
-    tokio::signal::ctrl_c().await?;
+    loop {
+        tokio::select! {
+            Some(event) = events.recv() => match event {
+                NetworkEvent::PeerConnected(peer, status) => {
+                    tracing::info!("Peer connected: {} (best block {})", peer, status.best);
+                    sync_if_ahead(peer, status.best)?;
+                }
+                NetworkEvent::PeerStatus(peer, status) => sync_if_ahead(peer, status.best)?,
+                NetworkEvent::PeerDisconnected(peer) => tracing::info!("Peer disconnected: {}", peer),
+                other => tracing::debug!("Network event: {:?}", other),
+            },
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                break;
+            }
+        }
+    }
     
     tracing::info!("👋 Shutting down...");
     
@@ -1,5 +1,6 @@
 //! Configuration for the blockchain
 
+use crate::types::{Balance, PublicKey};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
@@ -44,7 +45,7 @@ pub struct ConsensusConfig {
     pub epoch_length: u64,
     
     /// Minimum stake required to be a validator
-    pub min_validator_stake: u128,
+    pub min_validator_stake: Balance,
     
     /// Maximum number of validators
     pub max_validators: usize,
@@ -57,6 +58,20 @@ pub struct ConsensusConfig {
     
     /// Enable DAG structure
     pub enable_dag: bool,
+    
+    /// Validators active from genesis
+    #[serde(default)]
+    pub validators: Vec<GenesisValidator>,
+}
+
+/// Validator in the initial validator set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisValidator {
+    /// Validator public key
+    pub public_key: PublicKey,
+    
+    /// Staked amount
+    pub stake: Balance,
 }
 
 impl Default for ConsensusConfig {
@@ -64,11 +79,12 @@ impl Default for ConsensusConfig {
         Self {
             block_time: Duration::from_secs(2),
             epoch_length: 100,
-            min_validator_stake: 32_000_000_000_000_000_000, // 32 tokens
+            min_validator_stake: Balance::from_u128(32_000_000_000_000_000_000), // 32 tokens
             max_validators: 1000,
             slashing_percentage: 5,
             enable_poh: true,
             enable_dag: true,
+            validators: vec![],
         }
     }
 }
@@ -156,6 +172,12 @@ pub struct StorageConfig {
     
     /// Snapshot interval (blocks)
     pub snapshot_interval: u64,
+    
+    /// Block cache size shared by all column families (MiB)
+    pub block_cache_size_mb: usize,
+    
+    /// Write buffer (memtable) size per column family (MiB)
+    pub write_buffer_size_mb: usize,
 }
 
 impl Default for StorageConfig {
@@ -166,6 +188,8 @@ impl Default for StorageConfig {
             pruning_history: 256,
             enable_snapshots: true,
             snapshot_interval: 1000,
+            block_cache_size_mb: 256,
+            write_buffer_size_mb: 64,
         }
     }
 }
@@ -1,16 +1,23 @@
 //! Asynchronous Byzantine Fault Tolerance implementation
 
 use super::*;
+use super::finality::FinalityProof;
+use parking_lot::RwLock;
+use std::collections::HashMap;
 
 /// aBFT consensus engine for instant finality
 pub struct AsyncBFT {
     config: Arc<ConsensusConfig>,
+    votes: RwLock<HashMap<(BlockNumber, BlockHash), FinalityProof>>,
 }
 
 impl AsyncBFT {
     /// Create new aBFT engine
     pub fn new(config: Arc<ConsensusConfig>) -> Self {
-        Self { config }
+        Self {
+            config,
+            votes: RwLock::new(HashMap::new()),
+        }
     }
     
     /// Validate block with aBFT consensus
@@ -25,11 +32,78 @@ impl AsyncBFT {
         Ok(())
     }
     
-    /// Finalize block (instant finality)
-    pub async fn finalize(&self, _block: &Block) -> ConsensusResult<()> {
-        // TODO: Implement aBFT finalization
-        // Requires 2/3+ validator signatures
+    /// Collect commit votes: validator signatures over a block, carried as a
+    /// partially signed finality proof
+    pub fn add_votes(&self, votes: &FinalityProof, validators: &[Validator]) -> ConsensusResult<()> {
+        let message = votes.signing_message();
+        for (key, signature) in &votes.signatures {
+            if !validators.iter().any(|v| v.is_active && v.public_key == *key) {
+                return Err(ConsensusError::ValidatorNotFound);
+            }
+            if !key.verify(&message, signature) {
+                return Err(ConsensusError::InvalidSignature);
+            }
+        }
         
+        let mut pending = self.votes.write();
+        let proof = pending
+            .entry((votes.number, votes.block_hash))
+            .or_insert_with(|| FinalityProof::new(votes.number, votes.block_hash));
+        for (key, signature) in &votes.signatures {
+            if !proof.signatures.iter().any(|(signer, _)| signer == key) {
+                proof.signatures.push((*key, signature.clone()));
+            }
+        }
         Ok(())
     }
+    
+    /// Finalize block (instant finality)
+    ///
+    /// Requires votes from validators holding more than two thirds of the
+    /// stake; they are returned as the block's finality proof.
+    pub async fn finalize(&self, block: &Block, validators: &[Validator]) -> ConsensusResult<FinalityProof> {
+        let key = (block.header.number, block.hash());
+        let proof = self.votes.read().get(&key).cloned()
+            .unwrap_or_else(|| FinalityProof::new(key.0, key.1));
+        proof.verify(validators)?;
+        
+        // Votes at or below a final block can no longer finalize anything
+        self.votes.write().retain(|(number, _), _| *number > key.0);
+        Ok(proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{empty_block, validator_keys, validator_set};
+    
+    #[tokio::test]
+    async fn test_finalize_needs_two_thirds_of_votes() {
+        let keys = validator_keys();
+        let validators = validator_set(&keys);
+        let abft = AsyncBFT::new(Arc::new(ConsensusConfig::default()));
+        let block = empty_block(None, BlockHash([0; 32]));
+        
+        let mut votes = FinalityProof::new(0, block.hash());
+        votes.sign(&keys[0]);
+        votes.sign(&keys[1]);
+        abft.add_votes(&votes, &validators).unwrap();
+        assert!(abft.finalize(&block, &validators).await.is_err());
+        
+        // Repeated votes do not count twice
+        abft.add_votes(&votes, &validators).unwrap();
+        assert!(abft.finalize(&block, &validators).await.is_err());
+        
+        let mut outsider = FinalityProof::new(0, block.hash());
+        outsider.sign(&ed25519_dalek::SigningKey::from_bytes(&[9; 32]));
+        assert!(abft.add_votes(&outsider, &validators).is_err());
+        
+        let mut last = FinalityProof::new(0, block.hash());
+        last.sign(&keys[2]);
+        abft.add_votes(&last, &validators).unwrap();
+        let proof = abft.finalize(&block, &validators).await.unwrap();
+        assert_eq!(proof.signatures.len(), 3);
+        proof.verify(&validators).unwrap();
+    }
 }
@@ -127,6 +127,10 @@ mod tests {
                 transactions_root: BlockHash([0; 32]),
                 receipts_root: BlockHash([0; 32]),
                 proposer: Address([0; 20]),
+                logs_bloom: Bloom::default(),
+                gas_limit: 30_000_000,
+                gas_used: 0,
+                base_fee_per_gas: Balance::ZERO,
                 extra_data: vec![],
             },
             transactions: vec![],
@@ -0,0 +1,139 @@
+//! Finality proofs
+//!
+//! A block is final once validators holding more than two thirds of the
+//! active stake have signed its hash. The signatures are collected into a
+//! [`FinalityProof`] that syncing nodes check against the validator set
+//! instead of trusting whichever peer served the headers.
+
+use super::*;
+use ed25519_dalek::{Signer, SigningKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Domain tag mixed into every finality signature
+const FINALITY_DOMAIN: &[u8] = b"quantumchain/finality";
+
+/// Validator signatures finalizing a block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinalityProof {
+    /// Finalized block number
+    pub number: BlockNumber,
+    /// Finalized block hash
+    pub block_hash: BlockHash,
+    /// Signer keys and their signatures over the block
+    pub signatures: Vec<(PublicKey, Signature)>,
+}
+
+impl FinalityProof {
+    /// Unsigned proof for a block
+    pub fn new(number: BlockNumber, block_hash: BlockHash) -> Self {
+        Self {
+            number,
+            block_hash,
+            signatures: vec![],
+        }
+    }
+
+    /// Message signed by validators
+    pub fn signing_message(&self) -> Vec<u8> {
+        let mut message = FINALITY_DOMAIN.to_vec();
+        message.extend_from_slice(&self.number.to_be_bytes());
+        message.extend_from_slice(&self.block_hash.0);
+        message
+    }
+
+    /// Add a validator signature
+    pub fn sign(&mut self, key: &SigningKey) {
+        let signature = key.sign(&self.signing_message());
+        self.signatures.push((
+            PublicKey(key.verifying_key().to_bytes()),
+            Signature(signature.to_bytes()),
+        ));
+    }
+
+    /// Check that distinct active validators holding more than two thirds of
+    /// the active stake signed the block
+    pub fn verify(&self, validators: &[Validator]) -> ConsensusResult<()> {
+        let active: Vec<_> = validators.iter().filter(|v| v.is_active).collect();
+        let total: Balance = active.iter().map(|v| v.stake).sum();
+        let message = self.signing_message();
+
+        let mut signers = HashSet::new();
+        let mut signed = Balance::ZERO;
+        for (key, signature) in &self.signatures {
+            let validator = active
+                .iter()
+                .find(|v| v.public_key == *key)
+                .ok_or(ConsensusError::ValidatorNotFound)?;
+            if !signers.insert(key.0) || !key.verify(&message, signature) {
+                return Err(ConsensusError::InvalidSignature);
+            }
+            signed += validator.stake;
+        }
+
+        let three = Balance::from(3u64);
+        let two = Balance::from(2u64);
+        if total.is_zero() || signed.saturating_mul(three) <= total.saturating_mul(two) {
+            return Err(ConsensusError::InsufficientStake);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(key: &SigningKey, stake: u64) -> Validator {
+        let public_key = PublicKey(key.verifying_key().to_bytes());
+        Validator {
+            address: public_key.to_address(),
+            stake: Balance::from(stake),
+            public_key,
+            reputation: 0,
+            is_active: true,
+        }
+    }
+
+    #[test]
+    fn test_supermajority_required() {
+        let keys: Vec<_> = (1..=4u8)
+            .map(|i| SigningKey::from_bytes(&[i; 32]))
+            .collect();
+        let validators: Vec<_> = keys.iter().map(|key| validator(key, 100)).collect();
+
+        let mut proof = FinalityProof::new(10, BlockHash([7; 32]));
+        for key in &keys[..2] {
+            proof.sign(key);
+        }
+        assert!(matches!(
+            proof.verify(&validators),
+            Err(ConsensusError::InsufficientStake)
+        ));
+
+        // Signing twice does not count twice
+        let mut duplicated = proof.clone();
+        duplicated.sign(&keys[0]);
+        assert!(matches!(
+            duplicated.verify(&validators),
+            Err(ConsensusError::InvalidSignature)
+        ));
+
+        proof.sign(&keys[2]);
+        proof.verify(&validators).unwrap();
+
+        // Signatures are bound to the block
+        let mut moved = proof.clone();
+        moved.block_hash = BlockHash([8; 32]);
+        assert!(matches!(
+            moved.verify(&validators),
+            Err(ConsensusError::InvalidSignature)
+        ));
+        let outsider = SigningKey::from_bytes(&[9; 32]);
+        proof.sign(&outsider);
+        assert!(matches!(
+            proof.verify(&validators),
+            Err(ConsensusError::ValidatorNotFound)
+        ));
+    }
+}
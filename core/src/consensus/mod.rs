@@ -4,6 +4,9 @@
 
 use crate::types::*;
 use crate::config::ConsensusConfig;
+use crate::crypto::merkle;
+use crate::storage::{BlockStore, KeyValueStore, StateManager};
+use crate::vm::executor::{BlockContext, ExecutionError, TransactionExecutor};
 use async_trait::async_trait;
 use std::sync::Arc;
 use thiserror::Error;
@@ -13,6 +16,7 @@ pub mod abft;
 pub mod poh;
 pub mod dag;
 pub mod validator;
+pub mod finality;
 
 /// Consensus errors
 #[derive(Error, Debug)]
@@ -31,6 +35,9 @@ pub enum ConsensusError {
     
     #[error("Consensus timeout")]
     Timeout,
+    
+    #[error("State error: {0}")]
+    State(String),
 }
 
 /// Result type for consensus operations
@@ -39,13 +46,16 @@ pub type ConsensusResult<T> = Result<T, ConsensusError>;
 /// Consensus engine trait
 #[async_trait]
 pub trait ConsensusEngine: Send + Sync {
-    /// Propose a new block
-    async fn propose_block(&self, transactions: Vec<Transaction>) -> ConsensusResult<Block>;
+    /// Propose a new block on top of a parent
+    ///
+    /// Transactions that fail validation or do not fit in the gas limit are
+    /// left out of the block.
+    async fn propose_block(&self, parent: &BlockHeader, transactions: Vec<Transaction>) -> ConsensusResult<Block>;
     
-    /// Validate a block
-    async fn validate_block(&self, block: &Block) -> ConsensusResult<()>;
+    /// Validate a block against its parent
+    async fn validate_block(&self, parent: &BlockHeader, block: &Block) -> ConsensusResult<()>;
     
-    /// Finalize a block
+    /// Finalize a block, storing its finality proof
     async fn finalize_block(&self, block: &Block) -> ConsensusResult<()>;
     
     /// Get current validators
@@ -55,6 +65,71 @@ pub trait ConsensusEngine: Send + Sync {
     async fn is_proposer(&self) -> ConsensusResult<bool>;
 }
 
+/// Check that a header follows its parent
+///
+/// The number must be the parent's plus one and the timestamp later than the
+/// parent's. The gas limit may move by at most 1/1024 of the parent's, the
+/// gas used must fit in the gas limit and the base fee must be the one the
+/// parent's usage implies.
+pub fn validate_header(parent: &BlockHeader, header: &BlockHeader) -> ConsensusResult<()> {
+    if header.parent_hash != parent.hash() || header.number != parent.number + 1 {
+        return Err(ConsensusError::InvalidBlock("Block does not follow its parent".to_string()));
+    }
+    
+    if header.timestamp <= parent.timestamp {
+        return Err(ConsensusError::InvalidBlock("Timestamp not after parent".to_string()));
+    }
+    
+    let max_change = parent.gas_limit / GAS_LIMIT_BOUND_DIVISOR;
+    if header.gas_limit.abs_diff(parent.gas_limit) > max_change {
+        return Err(ConsensusError::InvalidBlock(format!(
+            "Gas limit {} too far from parent gas limit {}",
+            header.gas_limit, parent.gas_limit
+        )));
+    }
+    
+    if header.gas_used > header.gas_limit {
+        return Err(ConsensusError::InvalidBlock("Gas used exceeds gas limit".to_string()));
+    }
+    
+    let expected_base_fee = parent.next_base_fee();
+    if header.base_fee_per_gas != expected_base_fee {
+        return Err(ConsensusError::InvalidBlock(format!(
+            "Base fee {} does not match expected {}",
+            header.base_fee_per_gas, expected_base_fee
+        )));
+    }
+    
+    Ok(())
+}
+
+/// Check the signatures of key-authorized transactions
+///
+/// Contract-validated transactions are checked against state during execution.
+pub fn validate_signatures(block: &Block) -> ConsensusResult<()> {
+    if block.transactions.iter()
+        .filter(|tx| tx.has_key_authorization())
+        .all(Transaction::verify_signature)
+    {
+        Ok(())
+    } else {
+        Err(ConsensusError::InvalidSignature)
+    }
+}
+
+/// Check that a proposer is an active member of a validator set
+pub fn validate_proposer(validators: &[Validator], proposer: &Address) -> ConsensusResult<()> {
+    let validator = validators.iter()
+        .find(|v| v.address == *proposer)
+        .ok_or(ConsensusError::ValidatorNotFound)?;
+    
+    if !validator.is_active {
+        return Err(ConsensusError::InvalidBlock("Inactive validator".to_string()));
+    }
+    
+    Ok(())
+}
+
 /// Validator information
 #[derive(Debug, Clone)]
 pub struct Validator {
@@ -81,6 +156,8 @@ pub struct HybridConsensus {
     abft: abft::AsyncBFT,
     poh: Option<poh::ProofOfHistory>,
     dag: Option<dag::DagStructure>,
+    store: Option<Arc<dyn KeyValueStore>>,
+    executor: TransactionExecutor,
 }
 
 impl HybridConsensus {
@@ -89,6 +166,18 @@ impl HybridConsensus {
         let config = Arc::new(config);
         
         let pos = pos::ProofOfStake::new(config.clone());
+        for genesis in &config.validators {
+            let validator = Validator {
+                address: genesis.public_key.to_address(),
+                stake: genesis.stake,
+                public_key: genesis.public_key,
+                reputation: 0,
+                is_active: true,
+            };
+            if let Err(e) = pos.add_validator(validator) {
+                tracing::warn!("Skipping genesis validator {}: {}", genesis.public_key, e);
+            }
+        }
         let abft = abft::AsyncBFT::new(config.clone());
         
         let poh = if config.enable_poh {
@@ -109,18 +198,42 @@ impl HybridConsensus {
             abft,
             poh,
             dag,
+            store: None,
+            executor: TransactionExecutor::new(),
         }
     }
+    
+    /// Read the state that proposed blocks execute on from a store
+    pub fn with_store(mut self, store: Arc<dyn KeyValueStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+    
+    /// Execute proposed blocks with a configured executor
+    pub fn with_executor(mut self, executor: TransactionExecutor) -> Self {
+        self.executor = executor;
+        self
+    }
+    
+    /// Record validator commit votes for a block
+    pub async fn add_votes(&self, votes: &finality::FinalityProof) -> ConsensusResult<()> {
+        let validators = self.pos.get_validators().await?;
+        self.abft.add_votes(votes, &validators)
+    }
+    
+    fn store(&self) -> ConsensusResult<Arc<dyn KeyValueStore>> {
+        self.store.clone().ok_or_else(|| ConsensusError::State("No state store configured".to_string()))
+    }
 }
 
 #[async_trait]
 impl ConsensusEngine for HybridConsensus {
-    async fn propose_block(&self, transactions: Vec<Transaction>) -> ConsensusResult<Block> {
+    async fn propose_block(&self, parent: &BlockHeader, transactions: Vec<Transaction>) -> ConsensusResult<Block> {
         // 1. Check if we are the proposer (PoS selection)
         let proposer = self.pos.select_proposer().await?;
         
-        // 2. Add PoH timestamp if enabled
-        let timestamp = if let Some(poh) = &self.poh {
+        // 2. Add PoH timestamp if enabled, always after the parent
+        let now = if let Some(poh) = &self.poh {
             poh.get_timestamp().await
         } else {
             std::time::SystemTime::now()
@@ -128,41 +241,96 @@ impl ConsensusEngine for HybridConsensus {
                 .unwrap()
                 .as_secs()
         };
+        let timestamp = now.max(parent.timestamp + 1);
         
-        // 3. Create block header
+        // 3. Execute the transactions on the parent state
+        let mut state = StateManager::at_root(self.store()?, parent.state_root);
+        let context = BlockContext {
+            number: parent.number + 1,
+            base_fee: parent.next_base_fee(),
+            proposer,
+        };
+        let mut included = Vec::with_capacity(transactions.len());
+        let mut receipts = Vec::with_capacity(transactions.len());
+        let mut gas_used: Gas = 0;
+        let mut logs_bloom = Bloom::default();
+        for tx in transactions {
+            if gas_used.saturating_add(tx.gas_limit) > parent.gas_limit {
+                continue;
+            }
+            match self.executor.execute(&mut state, &tx, &context) {
+                Ok(receipt) => {
+                    gas_used += receipt.gas_used;
+                    logs_bloom.accrue_bloom(&receipt.logs_bloom);
+                    receipts.push(receipt);
+                    included.push(tx);
+                }
+                Err(ExecutionError::State(message)) => return Err(ConsensusError::State(message)),
+                Err(error) => tracing::debug!("Leaving out transaction {}: {}", tx.hash(), error),
+            }
+        }
+        // The state is persisted when the block is imported
+        let (state_root, _) = state.prepare_commit()
+            .map_err(|error| ConsensusError::State(error.to_string()))?;
+        
+        // 4. Create block header
         let header = BlockHeader {
-            number: 0, // TODO: Get from chain state
-            parent_hash: BlockHash([0; 32]), // TODO: Get from chain state
+            number: context.number,
+            parent_hash: parent.hash(),
             timestamp,
-            state_root: BlockHash([0; 32]), // TODO: Calculate
-            transactions_root: BlockHash([0; 32]), // TODO: Calculate
-            receipts_root: BlockHash([0; 32]), // TODO: Calculate
+            state_root,
+            transactions_root: merkle::transactions_root(&included),
+            receipts_root: merkle::receipts_root(&receipts),
             proposer,
+            logs_bloom,
+            gas_limit: parent.gas_limit,
+            gas_used,
+            base_fee_per_gas: context.base_fee,
             extra_data: vec![],
         };
         
-        // 4. Create block
+        // 5. Create block
         let block = Block {
             header,
-            transactions,
+            transactions: included,
         };
         
         Ok(block)
     }
     
-    async fn validate_block(&self, block: &Block) -> ConsensusResult<()> {
+    async fn validate_block(&self, parent: &BlockHeader, block: &Block) -> ConsensusResult<()> {
         // 1. Validate block structure
+        validate_header(parent, &block.header)?;
+        
         if block.transactions.is_empty() {
             return Err(ConsensusError::InvalidBlock("Empty block".to_string()));
         }
         
-        // 2. Validate proposer
+        if block.header.transactions_root != merkle::transactions_root(&block.transactions) {
+            return Err(ConsensusError::InvalidBlock("Transactions root mismatch".to_string()));
+        }
+        
+        // 2. Authenticate key-signed transactions
+        validate_signatures(block)?;
+        
+        // 3. Check fee caps against the block base fee
+        let base_fee = block.header.base_fee_per_gas;
+        for tx in &block.transactions {
+            if tx.max_priority_fee_per_gas() > tx.max_fee_per_gas() {
+                return Err(ConsensusError::InvalidBlock("Transaction priority fee above max fee".to_string()));
+            }
+            if tx.effective_gas_price(base_fee).is_none() {
+                return Err(ConsensusError::InvalidBlock("Transaction fee below base fee".to_string()));
+            }
+        }
+        
+        // 4. Validate proposer
         self.pos.validate_proposer(&block.header.proposer).await?;
         
-        // 3. Validate with aBFT
+        // 5. Validate with aBFT
         self.abft.validate(block).await?;
         
-        // 4. Validate PoH if enabled
+        // 6. Validate PoH if enabled
         if let Some(poh) = &self.poh {
             poh.validate_timestamp(block.header.timestamp).await?;
         }
@@ -172,7 +340,13 @@ impl ConsensusEngine for HybridConsensus {
     
     async fn finalize_block(&self, block: &Block) -> ConsensusResult<()> {
         // Use aBFT for instant finality
-        self.abft.finalize(block).await?;
+        let validators = self.pos.get_validators().await?;
+        let proof = self.abft.finalize(block, &validators).await?;
+        
+        // Keep the proof so syncing peers can check the block against it
+        BlockStore::new(self.store()?)
+            .insert_finality_proof(&proof)
+            .map_err(|error| ConsensusError::State(error.to_string()))?;
         Ok(())
     }
     
@@ -188,6 +362,10 @@ impl ConsensusEngine for HybridConsensus {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::{BlockImporter, MemoryStore};
+    use crate::test_utils;
+    use crate::vm::executor::address_topic;
+    use ed25519_dalek::SigningKey;
 
     #[tokio::test]
     async fn test_consensus_creation() {
@@ -196,4 +374,101 @@ mod tests {
         assert!(consensus.poh.is_some());
         assert!(consensus.dag.is_some());
     }
+
+    #[test]
+    fn test_validate_header() {
+        let parent = BlockHeader {
+            number: 7,
+            parent_hash: BlockHash([1; 32]),
+            timestamp: 1000,
+            state_root: BlockHash([0; 32]),
+            transactions_root: BlockHash([0; 32]),
+            receipts_root: BlockHash([0; 32]),
+            proposer: Address([0; 20]),
+            logs_bloom: Bloom::default(),
+            gas_limit: 30_000_000,
+            gas_used: 30_000_000,
+            base_fee_per_gas: Balance::from(1_000_000_000u64),
+            extra_data: vec![],
+        };
+        let child = BlockHeader {
+            number: 8,
+            parent_hash: parent.hash(),
+            timestamp: 1001,
+            gas_used: 21_000,
+            base_fee_per_gas: parent.next_base_fee(),
+            ..parent.clone()
+        };
+        validate_header(&parent, &child).unwrap();
+        
+        let wrong_number = BlockHeader { number: 9, ..child.clone() };
+        assert!(validate_header(&parent, &wrong_number).is_err());
+        
+        let stale_time = BlockHeader { timestamp: 1000, ..child.clone() };
+        assert!(validate_header(&parent, &stale_time).is_err());
+        
+        let stale_fee = BlockHeader { base_fee_per_gas: parent.base_fee_per_gas, ..child.clone() };
+        assert!(validate_header(&parent, &stale_fee).is_err());
+        
+        let raised_limit = BlockHeader { gas_limit: 40_000_000, ..child.clone() };
+        assert!(validate_header(&parent, &raised_limit).is_err());
+        let nudged_limit = BlockHeader { gas_limit: 30_020_000, ..child.clone() };
+        validate_header(&parent, &nudged_limit).unwrap();
+        
+        let overfull = BlockHeader { gas_used: 30_000_001, ..child };
+        assert!(validate_header(&parent, &overfull).is_err());
+    }
+
+    fn transfer(key: &SigningKey, nonce: Nonce) -> Transaction {
+        let mut tx = Transaction {
+            from: PublicKey(key.verifying_key().to_bytes()).to_address(),
+            to: Some(Address([2; 20])),
+            value: Balance::from(100u64),
+            data: vec![],
+            gas_limit: 21_000,
+            kind: TxKind::Legacy { gas_price: Balance::from(20u64) },
+            nonce,
+            paymaster: None,
+            authorization: Authorization::Contract { data: vec![] },
+        };
+        tx.sign(key);
+        tx
+    }
+
+    #[tokio::test]
+    async fn test_propose_block_executes_transactions() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let sender = PublicKey(key.verifying_key().to_bytes()).to_address();
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut state = StateManager::new(store.clone());
+        let account = Account { balance: Balance::from(1_000_000_000u64), ..Default::default() };
+        state.set_account(sender, account);
+        let genesis = test_utils::empty_block(None, state.commit(0).unwrap());
+        let importer = BlockImporter::new(store.clone(), TransactionExecutor::new());
+        importer.import_block(&genesis).unwrap();
+
+        let config = ConsensusConfig::default();
+        let validator = Validator {
+            address: Address([0xee; 20]),
+            stake: config.min_validator_stake,
+            public_key: PublicKey([3; 32]),
+            reputation: 0,
+            is_active: true,
+        };
+        let consensus = HybridConsensus::new(config).with_store(store);
+        consensus.pos.add_validator(validator).unwrap();
+
+        // The transaction with a nonce gap is left out
+        let transactions = vec![transfer(&key, 0), transfer(&key, 5), transfer(&key, 1)];
+        let block = consensus.propose_block(&genesis.header, transactions.clone()).await.unwrap();
+        let included: Vec<_> = block.transactions.iter().map(|tx| tx.hash()).collect();
+        assert_eq!(included, vec![transactions[0].hash(), transactions[2].hash()]);
+        assert_eq!(block.header.gas_used, 42_000);
+        assert!(block.header.timestamp > genesis.header.timestamp);
+        assert!(block.header.logs_bloom.contains_input(&address_topic(&sender)));
+
+        // The header commitments match re-execution
+        importer.import_block(&block).unwrap();
+        assert_eq!(importer.blocks().head().unwrap().unwrap().hash(), block.hash());
+    }
 }
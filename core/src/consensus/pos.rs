@@ -31,12 +31,12 @@ impl ProofOfStake {
         
         // Simple weighted random selection based on stake
         // TODO: Implement proper VRF (Algorand-style)
-        let total_stake: u128 = validators.values()
+        let total_stake: Balance = validators.values()
             .filter(|v| v.is_active)
             .map(|v| v.stake)
             .sum();
         
-        if total_stake == 0 {
+        if total_stake.is_zero() {
             return Err(ConsensusError::InsufficientStake);
         }
         
@@ -110,8 +110,11 @@ impl ProofOfStake {
         let validator = validators.get_mut(address)
             .ok_or(ConsensusError::ValidatorNotFound)?;
         
-        // Reduce stake by slashing percentage
-        let slash_amount = validator.stake * self.config.slashing_percentage as u128 / 100;
+        // Reduce stake by slashing percentage (divide first so large stakes cannot overflow)
+        let percentage = Balance::from(self.config.slashing_percentage);
+        let hundred = Balance::from(100u64);
+        let slash_amount = validator.stake / hundred * percentage
+            + validator.stake % hundred * percentage / hundred;
         validator.stake = validator.stake.saturating_sub(slash_amount);
         
         // Deactivate if stake too low
@@ -143,7 +146,7 @@ mod tests {
         
         let validator = Validator {
             address: Address([1; 20]),
-            stake: 32_000_000_000_000_000_000,
+            stake: Balance::from_u128(32_000_000_000_000_000_000),
             public_key: PublicKey([2; 32]),
             reputation: 100,
             is_active: true,
@@ -159,7 +162,7 @@ mod tests {
         
         let validator = Validator {
             address: Address([1; 20]),
-            stake: 1_000, // Too low
+            stake: Balance::from(1_000u64), // Too low
             public_key: PublicKey([2; 32]),
             reputation: 100,
             is_active: true,
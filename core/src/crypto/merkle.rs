@@ -0,0 +1,236 @@
+//! Binary Merkle trees over blake3
+//!
+//! Leaves and inner nodes are hashed with distinct prefixes so a leaf can
+//! never be passed off as an inner node. A node without a sibling is promoted
+//! to the next level unchanged instead of being paired with itself, which
+//! keeps trees of different sizes from sharing a root.
+
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Root of a tree with no leaves
+pub const EMPTY_ROOT: [u8; 32] = [0; 32];
+
+/// Hash of a leaf's data
+pub fn hash_leaf(data: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// Hash of an inner node
+pub fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Merkle tree with all levels kept for proof generation
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    /// Levels from the leaf hashes up to the root
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    /// Build a tree over the given leaf data
+    pub fn new<T: AsRef<[u8]>>(leaves: &[T]) -> Self {
+        let mut levels = vec![leaves.iter().map(|l| hash_leaf(l.as_ref())).collect::<Vec<_>>()];
+
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Self { levels }
+    }
+
+    /// Number of leaves
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Check whether the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Root hash ([`EMPTY_ROOT`] for an empty tree)
+    pub fn root(&self) -> [u8; 32] {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or(EMPTY_ROOT)
+    }
+
+    /// Inclusion proof for the leaf at `index`
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let mut siblings = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = position ^ 1;
+            if sibling < level.len() {
+                siblings.push(level[sibling]);
+            }
+            position /= 2;
+        }
+
+        Some(MerkleProof {
+            index,
+            leaf_count: self.len(),
+            siblings,
+        })
+    }
+}
+
+/// Inclusion proof for a single leaf
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Position of the leaf
+    pub index: usize,
+    /// Number of leaves in the tree
+    pub leaf_count: usize,
+    /// Sibling hashes from the leaf level upwards (promoted levels are skipped)
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    /// Check that `leaf` is at `self.index` in the tree with the given root
+    pub fn verify(&self, root: &[u8; 32], leaf: &[u8]) -> bool {
+        if self.index >= self.leaf_count {
+            return false;
+        }
+
+        let mut hash = hash_leaf(leaf);
+        let mut siblings = self.siblings.iter();
+        let mut position = self.index;
+        let mut width = self.leaf_count;
+
+        while width > 1 {
+            if position % 2 == 1 {
+                let Some(sibling) = siblings.next() else { return false };
+                hash = hash_node(sibling, &hash);
+            } else if position + 1 < width {
+                let Some(sibling) = siblings.next() else { return false };
+                hash = hash_node(&hash, sibling);
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+
+        siblings.next().is_none() && hash == *root
+    }
+}
+
+/// Leaf data committed for a transaction (its encoding, so that the
+/// authorization is committed too)
+pub fn transaction_leaf(tx: &Transaction) -> Vec<u8> {
+    bincode::serialize(tx).unwrap()
+}
+
+/// Leaf data committed for a receipt (its encoding)
+pub fn receipt_leaf(receipt: &Receipt) -> Vec<u8> {
+    bincode::serialize(receipt).unwrap()
+}
+
+/// Root committed in `BlockHeader::transactions_root`
+pub fn transactions_root(transactions: &[Transaction]) -> BlockHash {
+    let leaves: Vec<_> = transactions.iter().map(transaction_leaf).collect();
+    BlockHash(MerkleTree::new(&leaves).root())
+}
+
+/// Root committed in `BlockHeader::receipts_root`
+pub fn receipts_root(receipts: &[Receipt]) -> BlockHash {
+    let leaves: Vec<_> = receipts.iter().map(receipt_leaf).collect();
+    BlockHash(MerkleTree::new(&leaves).root())
+}
+
+/// Inclusion proof for the transaction at `index`
+pub fn prove_transaction(transactions: &[Transaction], index: usize) -> Option<MerkleProof> {
+    let leaves: Vec<_> = transactions.iter().map(transaction_leaf).collect();
+    MerkleTree::new(&leaves).proof(index)
+}
+
+/// Inclusion proof for the receipt at `index`
+pub fn prove_receipt(receipts: &[Receipt], index: usize) -> Option<MerkleProof> {
+    let leaves: Vec<_> = receipts.iter().map(receipt_leaf).collect();
+    MerkleTree::new(&leaves).proof(index)
+}
+
+/// Verify a transaction against a header's transactions root
+pub fn verify_transaction(root: &BlockHash, tx: &Transaction, proof: &MerkleProof) -> bool {
+    proof.verify(&root.0, &transaction_leaf(tx))
+}
+
+/// Verify a receipt against a header's receipts root
+pub fn verify_receipt(root: &BlockHash, receipt: &Receipt, proof: &MerkleProof) -> bool {
+    proof.verify(&root.0, &receipt_leaf(receipt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proofs_for_all_sizes() {
+        for size in 1..=9usize {
+            let leaves: Vec<_> = (0..size).map(|i| vec![i as u8]).collect();
+            let tree = MerkleTree::new(&leaves);
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert!(proof.verify(&tree.root(), leaf), "size {size} index {index}");
+                assert!(!proof.verify(&tree.root(), b"other"));
+            }
+            assert!(tree.proof(size).is_none());
+        }
+    }
+
+    #[test]
+    fn test_roots_differ() {
+        let three = MerkleTree::new(&[b"a", b"b", b"c"]);
+        let four = MerkleTree::new(&[b"a", b"b", b"c", b"c"]);
+        assert_ne!(three.root(), four.root());
+
+        // A leaf cannot be confused with an inner node
+        let pair = MerkleTree::new(&[b"a", b"b"]);
+        let forged = [hash_leaf(b"a"), hash_leaf(b"b")].concat();
+        assert_ne!(MerkleTree::new(&[forged]).root(), pair.root());
+
+        assert_eq!(MerkleTree::new::<&[u8]>(&[]).root(), EMPTY_ROOT);
+    }
+
+    #[test]
+    fn test_tampered_proof() {
+        let leaves = [b"a", b"b", b"c", b"d", b"e"];
+        let tree = MerkleTree::new(&leaves);
+        let mut proof = tree.proof(4).unwrap();
+
+        proof.index = 3;
+        assert!(!proof.verify(&tree.root(), b"e"));
+
+        let mut proof = tree.proof(1).unwrap();
+        proof.siblings.push([0; 32]);
+        assert!(!proof.verify(&tree.root(), b"b"));
+    }
+}
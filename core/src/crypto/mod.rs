@@ -3,3 +3,4 @@
 // Placeholder for future crypto implementations
 pub mod zk_snarks;
 pub mod quantum_resistant;
+pub mod merkle;
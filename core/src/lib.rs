@@ -23,6 +23,9 @@ pub mod privacy;
 pub mod rollups;
 pub mod sharding;
 
+#[cfg(test)]
+mod test_utils;
+
 /// Re-export commonly used types
 pub mod prelude {
     pub use crate::consensus::*;
@@ -1,3 +1,239 @@
 //! Gossip protocol
+//!
+//! Transactions, block announcements, consensus messages and misbehaviour
+//! evidence are propagated with gossipsub on one topic each, namespaced by
+//! chain ID so that different networks never mix. Messages are held back
+//! until a [`GossipValidator`] has checked them: accepted messages are
+//! forwarded and delivered to the node, rejected ones are dropped and count
+//! against the peer that relayed them in the gossipsub peer score.
 
-// TODO: Implement optimized gossip for block and transaction propagation
+use crate::consensus;
+use crate::crypto::merkle::transactions_root;
+use crate::types::*;
+use libp2p::gossipsub::{self, IdentTopic, MessageAcceptance, MessageAuthenticity, TopicHash};
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Largest accepted gossip message
+pub const MAX_GOSSIP_SIZE: usize = 10 * 1024 * 1024;
+
+/// Score penalty per invalid message, squared by the number of offences
+const INVALID_MESSAGE_WEIGHT: f64 = -10.0;
+
+/// Gossip topic kinds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GossipTopic {
+    /// Pending transactions
+    Transactions,
+    /// Newly produced blocks
+    Blocks,
+    /// Consensus votes and proposals
+    Consensus,
+    /// Proof of validator misbehaviour
+    Evidence,
+}
+
+impl GossipTopic {
+    /// All topics, subscribed to on startup
+    pub const ALL: [GossipTopic; 4] = [
+        GossipTopic::Transactions,
+        GossipTopic::Blocks,
+        GossipTopic::Consensus,
+        GossipTopic::Evidence,
+    ];
+
+    /// Short name used in the topic string
+    pub fn name(&self) -> &'static str {
+        match self {
+            GossipTopic::Transactions => "txs",
+            GossipTopic::Blocks => "blocks",
+            GossipTopic::Consensus => "consensus",
+            GossipTopic::Evidence => "evidence",
+        }
+    }
+
+    /// Gossipsub topic on a given chain
+    pub fn topic(&self, chain_id: u64) -> IdentTopic {
+        IdentTopic::new(format!("/quantumchain/{}/{}/1", chain_id, self.name()))
+    }
+
+    /// Topic kind of a gossipsub topic hash on a given chain
+    pub fn from_hash(chain_id: u64, hash: &TopicHash) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|topic| topic.topic(chain_id).hash() == *hash)
+    }
+}
+
+/// Message carried on a gossip topic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GossipMessage {
+    /// Transaction for the mempool
+    Transaction(Box<Transaction>),
+    /// Block announcement
+    Block(Box<Block>),
+    /// Encoded consensus message
+    Consensus(Vec<u8>),
+    /// Encoded misbehaviour evidence
+    Evidence(Vec<u8>),
+}
+
+impl GossipMessage {
+    /// Topic the message is published on
+    pub fn topic(&self) -> GossipTopic {
+        match self {
+            GossipMessage::Transaction(_) => GossipTopic::Transactions,
+            GossipMessage::Block(_) => GossipTopic::Blocks,
+            GossipMessage::Consensus(_) => GossipTopic::Consensus,
+            GossipMessage::Evidence(_) => GossipTopic::Evidence,
+        }
+    }
+
+    /// Wire encoding
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    /// Decode a message received on `topic`
+    ///
+    /// Fails if the bytes are malformed or the message belongs on another topic.
+    pub fn decode(topic: GossipTopic, data: &[u8]) -> Option<Self> {
+        bincode::deserialize::<Self>(data)
+            .ok()
+            .filter(|message| message.topic() == topic)
+    }
+}
+
+/// Outcome of validating a gossip message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// Valid: forward it and deliver it to the node
+    Accept,
+    /// Not useful (e.g. already known) but not the sender's fault: drop it
+    Ignore,
+    /// Invalid: drop it and penalize the sender
+    Reject,
+}
+
+impl From<Validation> for MessageAcceptance {
+    fn from(validation: Validation) -> Self {
+        match validation {
+            Validation::Accept => MessageAcceptance::Accept,
+            Validation::Ignore => MessageAcceptance::Ignore,
+            Validation::Reject => MessageAcceptance::Reject,
+        }
+    }
+}
+
+/// Checks gossip messages before they are forwarded
+///
+/// Implemented by the node on top of its mempool and consensus engine. It is
+/// called on the network task, so it must not block.
+pub trait GossipValidator: Send + Sync {
+    /// Validate a message relayed by `source`
+    fn validate(&self, source: &PeerId, message: &GossipMessage) -> Validation;
+}
+
+/// Accepts every well-formed message
+pub struct AcceptAll;
+
+impl GossipValidator for AcceptAll {
+    fn validate(&self, _source: &PeerId, _message: &GossipMessage) -> Validation {
+        Validation::Accept
+    }
+}
+
+/// Checks everything that can be checked without state
+///
+/// Rejects transactions with a bad key signature and blocks whose
+/// transactions root or key signatures do not match. Contract-authorized
+/// transactions and consensus messages are accepted.
+pub struct StatelessValidator;
+
+impl GossipValidator for StatelessValidator {
+    fn validate(&self, _source: &PeerId, message: &GossipMessage) -> Validation {
+        let valid = match message {
+            GossipMessage::Transaction(tx) => !tx.has_key_authorization() || tx.verify_signature(),
+            GossipMessage::Block(block) => {
+                block.header.transactions_root == transactions_root(&block.transactions)
+                    && consensus::validate_signatures(block).is_ok()
+            }
+            GossipMessage::Consensus(_) | GossipMessage::Evidence(_) => true,
+        };
+        if valid {
+            Validation::Accept
+        } else {
+            Validation::Reject
+        }
+    }
+}
+
+/// Gossipsub behaviour subscribed to every topic of `chain_id`
+pub(crate) fn behaviour(keypair: &Keypair, chain_id: u64) -> Result<gossipsub::Behaviour, String> {
+    let config = gossipsub::ConfigBuilder::default()
+        .heartbeat_interval(Duration::from_secs(1))
+        .validation_mode(gossipsub::ValidationMode::Strict)
+        .validate_messages()
+        .max_transmit_size(MAX_GOSSIP_SIZE)
+        .message_id_fn(|message| {
+            gossipsub::MessageId::from(blake3::hash(&message.data).as_bytes().to_vec())
+        })
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut gossipsub =
+        gossipsub::Behaviour::new(MessageAuthenticity::Signed(keypair.clone()), config)?;
+
+    let mut params = gossipsub::PeerScoreParams::default();
+    for topic in GossipTopic::ALL {
+        // Score peers on first deliveries and invalid messages only; quiet
+        // topics such as evidence must not cost peers for delivering too little
+        let topic_params = gossipsub::TopicScoreParams {
+            topic_weight: 1.0,
+            time_in_mesh_weight: 0.0,
+            mesh_message_deliveries_weight: 0.0,
+            mesh_failure_penalty_weight: 0.0,
+            invalid_message_deliveries_weight: INVALID_MESSAGE_WEIGHT,
+            invalid_message_deliveries_decay: 0.9,
+            ..Default::default()
+        };
+        params
+            .topics
+            .insert(topic.topic(chain_id).hash(), topic_params);
+    }
+    gossipsub.with_peer_score(params, gossipsub::PeerScoreThresholds::default())?;
+
+    for topic in GossipTopic::ALL {
+        gossipsub
+            .subscribe(&topic.topic(chain_id))
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(gossipsub)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topics_and_encoding() {
+        let topic = GossipTopic::Consensus.topic(7);
+        assert_eq!(topic.to_string(), "/quantumchain/7/consensus/1");
+        assert_eq!(
+            GossipTopic::from_hash(7, &topic.hash()),
+            Some(GossipTopic::Consensus)
+        );
+        assert_eq!(GossipTopic::from_hash(8, &topic.hash()), None);
+
+        let message = GossipMessage::Evidence(vec![1, 2, 3]);
+        let data = message.encode();
+        assert!(matches!(
+            GossipMessage::decode(GossipTopic::Evidence, &data),
+            Some(GossipMessage::Evidence(evidence)) if evidence == vec![1, 2, 3]
+        ));
+        // Messages sent on the wrong topic or garbage are rejected
+        assert!(GossipMessage::decode(GossipTopic::Consensus, &data).is_none());
+        assert!(GossipMessage::decode(GossipTopic::Evidence, &[0xff; 3]).is_none());
+    }
+}
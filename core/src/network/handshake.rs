@@ -0,0 +1,225 @@
+//! Peer handshake
+//!
+//! As soon as a connection is established both sides send their [`Status`]
+//! over a dedicated request/response protocol and answer with their own:
+//! protocol version, chain ID, genesis hash, best and finalized heights and
+//! the services they offer. A peer on another protocol version, chain or
+//! genesis is disconnected straight away. Only peers that completed the
+//! handshake are reported as connected and may send sync requests, so sync
+//! never starts with an incompatible peer.
+
+use super::sync::{read_message, write_message};
+use crate::types::*;
+use async_trait::async_trait;
+use libp2p::futures::{AsyncRead, AsyncWrite};
+use libp2p::{request_response, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::io;
+use thiserror::Error;
+
+/// Handshake protocol name
+pub const HANDSHAKE_PROTOCOL: &str = "/quantumchain/status/1";
+
+/// Service offered to peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Capability {
+    /// Serves canonical headers and bodies
+    Blocks,
+    /// Serves state snapshots
+    Snapshots,
+}
+
+/// Chain status exchanged in the handshake
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Status {
+    /// Wire protocol version
+    pub protocol_version: u32,
+    /// Chain ID
+    pub chain_id: u64,
+    /// Hash of the genesis block
+    pub genesis_hash: BlockHash,
+    /// Best block number
+    pub best: BlockNumber,
+    /// Highest finalized block number
+    pub finalized: BlockNumber,
+    /// Services offered
+    pub capabilities: Vec<Capability>,
+}
+
+impl Status {
+    /// Status of a node at genesis on the current protocol version
+    pub fn new(chain_id: u64, genesis_hash: BlockHash) -> Self {
+        Self {
+            protocol_version: crate::PROTOCOL_VERSION,
+            chain_id,
+            genesis_hash,
+            best: 0,
+            finalized: 0,
+            capabilities: vec![],
+        }
+    }
+
+    /// Check whether a peer offers a service
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Check that a peer's status is consistent and on our protocol, chain
+    /// and genesis
+    pub fn check(&self, remote: &Status) -> Result<(), HandshakeError> {
+        if remote.protocol_version != self.protocol_version {
+            return Err(HandshakeError::ProtocolVersion {
+                local: self.protocol_version,
+                remote: remote.protocol_version,
+            });
+        }
+        if remote.chain_id != self.chain_id {
+            return Err(HandshakeError::ChainId {
+                local: self.chain_id,
+                remote: remote.chain_id,
+            });
+        }
+        if remote.genesis_hash != self.genesis_hash {
+            return Err(HandshakeError::Genesis {
+                local: self.genesis_hash,
+                remote: remote.genesis_hash,
+            });
+        }
+        if remote.finalized > remote.best {
+            return Err(HandshakeError::Heights {
+                best: remote.best,
+                finalized: remote.finalized,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Reasons a peer is disconnected during the handshake
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    #[error("Unsupported protocol version {remote} (expected {local})")]
+    ProtocolVersion { local: u32, remote: u32 },
+
+    #[error("Peer is on chain {remote}, not {local}")]
+    ChainId { local: u64, remote: u64 },
+
+    #[error("Genesis {remote} differs from ours ({local})")]
+    Genesis { local: BlockHash, remote: BlockHash },
+
+    #[error("Finalized block {finalized} is above best block {best}")]
+    Heights {
+        best: BlockNumber,
+        finalized: BlockNumber,
+    },
+
+    #[error("No handshake: {0}")]
+    Failed(String),
+}
+
+/// Length-prefixed bincode encoding of statuses
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeCodec;
+
+#[async_trait]
+impl request_response::Codec for HandshakeCodec {
+    type Protocol = StreamProtocol;
+    type Request = Status;
+    type Response = Status;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Status>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Status>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        status: Status,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &status).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        status: Status,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &status).await
+    }
+}
+
+/// Request/response behaviour for the handshake protocol
+pub(crate) fn behaviour() -> request_response::Behaviour<HandshakeCodec> {
+    request_response::Behaviour::new(
+        [(
+            StreamProtocol::new(HANDSHAKE_PROTOCOL),
+            request_response::ProtocolSupport::Full,
+        )],
+        request_response::Config::default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_check() {
+        let local = Status::new(1, BlockHash([1; 32]));
+        let mut remote = Status {
+            best: 100,
+            finalized: 90,
+            capabilities: vec![Capability::Blocks],
+            ..local.clone()
+        };
+        local.check(&remote).unwrap();
+        assert!(remote.supports(Capability::Blocks));
+        assert!(!remote.supports(Capability::Snapshots));
+
+        remote.finalized = 101;
+        assert!(matches!(
+            local.check(&remote),
+            Err(HandshakeError::Heights { .. })
+        ));
+
+        let other_chain = Status::new(2, BlockHash([1; 32]));
+        assert_eq!(
+            local.check(&other_chain),
+            Err(HandshakeError::ChainId {
+                local: 1,
+                remote: 2
+            })
+        );
+        let other_genesis = Status::new(1, BlockHash([2; 32]));
+        assert!(matches!(
+            local.check(&other_genesis),
+            Err(HandshakeError::Genesis { .. })
+        ));
+        let newer = Status {
+            protocol_version: crate::PROTOCOL_VERSION + 1,
+            ..local.clone()
+        };
+        assert!(matches!(
+            local.check(&newer),
+            Err(HandshakeError::ProtocolVersion { .. })
+        ));
+    }
+}
@@ -1,8 +1,48 @@
 //! Networking module - P2P communication
 
+use thiserror::Error;
+
 pub mod p2p;
 pub mod sync;
 pub mod gossip;
+pub mod state_sync;
+pub mod handshake;
 
-// Placeholder modules
 pub use p2p::*;
+pub use gossip::{
+    AcceptAll, GossipMessage, GossipTopic, GossipValidator, StatelessValidator, Validation,
+};
+pub use sync::{
+    ChainSync, SyncConfig, SyncError, SyncProvider, SyncRequest, SyncResponse, SyncResult,
+    SyncServer, SyncTransport,
+};
+pub use state_sync::SnapshotSync;
+pub use handshake::{Capability, HandshakeError, Status};
+
+/// Network errors
+#[derive(Error, Debug)]
+pub enum NetworkError {
+    #[error("Invalid address {0}")]
+    InvalidAddress(String),
+
+    #[error("Transport error: {0}")]
+    Transport(String),
+
+    #[error("Publish failed: {0}")]
+    Publish(String),
+
+    #[error("Request failed: {0}")]
+    Request(String),
+
+    #[error("Peer {0} has not completed the handshake")]
+    NoHandshake(libp2p::PeerId),
+
+    #[error("Node key error: {0}")]
+    NodeKey(String),
+
+    #[error("Network task has stopped")]
+    Stopped,
+}
+
+/// Result type for network operations
+pub type NetworkResult<T> = Result<T, NetworkError>;
@@ -1,20 +1,987 @@
 //! P2P networking layer
+//!
+//! [`P2PNetwork`] owns the libp2p swarm and runs it on its own task. Peers
+//! are found through the configured bootstrap nodes and Kademlia, identify
+//! feeds their listen addresses back into the routing table, and connection
+//! limits cap the number of established peers at `max_peers`. Every new
+//! peer goes through the handshake (see [`super::handshake`]) before it is
+//! reported to the node. The status exchange is repeated periodically and
+//! whenever the local head moves, so that peers' heads stay current. Gossip messages are validated on the network task
+//! (see [`super::gossip`]) and sync requests are answered from a
+//! [`SyncProvider`]. The node talks to the task through a [`NetworkHandle`]
+//! and receives [`NetworkEvent`]s on a channel.
 
-use crate::types::*;
+use super::gossip::{
+    self, GossipMessage, GossipTopic, GossipValidator, StatelessValidator, Validation,
+};
+use super::handshake::{self, HandshakeCodec, HandshakeError, Status};
+use super::sync::{self, SyncCodec, SyncProvider, SyncRequest, SyncResponse};
+use super::{NetworkError, NetworkResult};
+use crate::config::ChainConfig;
+use crate::types::{BlockHash, BlockNumber};
+use libp2p::futures::StreamExt;
+use libp2p::kad::store::MemoryStore;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
+use libp2p::{
+    connection_limits, gossipsub, identify, kad, noise, request_response, tcp, yamux, Swarm,
+    SwarmBuilder,
+};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+pub use libp2p::identity::Keypair;
+pub use libp2p::{Multiaddr, PeerId};
+
+/// Protocol name announced through identify
+pub const IDENTIFY_PROTOCOL: &str = "/quantumchain/id/1.0.0";
+
+/// Kademlia protocol name, distinct from the public IPFS DHT
+pub const KAD_PROTOCOL: &str = "/quantumchain/kad/1.0.0";
+
+/// Node key file in the data directory
+pub const NODE_KEY_FILE: &str = "node_key";
+
+/// Capacity of the event channel
+const EVENT_BUFFER: usize = 1024;
+
+/// How often the routing table is refreshed
+const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How long an idle connection is kept open
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a peer that failed the handshake stays connected
+const REJECT_GRACE: Duration = Duration::from_secs(1);
+
+/// How often connected peers are sent our status again
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Events reported to the node
+#[derive(Debug, Clone)]
+pub enum NetworkEvent {
+    /// Listening on a new local address
+    Listening(Multiaddr),
+    /// A peer completed the handshake
+    PeerConnected(PeerId, Status),
+    /// A connected peer announced a new head
+    PeerStatus(PeerId, Status),
+    /// A peer failed the handshake and was disconnected
+    PeerRejected(PeerId, HandshakeError),
+    /// Last connection to a peer that completed the handshake was closed
+    PeerDisconnected(PeerId),
+    /// A peer was added to the routing table
+    PeerDiscovered(PeerId),
+    /// A gossip message passed validation
+    Gossip {
+        /// Peer that relayed the message
+        source: PeerId,
+        /// The message
+        message: GossipMessage,
+    },
+}
+
+/// Requests from a [`NetworkHandle`] to the network task
+#[derive(Debug)]
+enum Command {
+    Dial(Multiaddr),
+    Disconnect(PeerId),
+    Peers(oneshot::Sender<Vec<PeerId>>),
+    Publish(GossipMessage, oneshot::Sender<NetworkResult<()>>),
+    PeerScore(PeerId, oneshot::Sender<Option<f64>>),
+    SetHead(BlockNumber, BlockNumber),
+    Request(
+        PeerId,
+        SyncRequest,
+        oneshot::Sender<NetworkResult<SyncResponse>>,
+    ),
+}
+
+#[derive(NetworkBehaviour)]
+struct Behaviour {
+    limits: connection_limits::Behaviour,
+    identify: identify::Behaviour,
+    kad: kad::Behaviour<MemoryStore>,
+    gossipsub: gossipsub::Behaviour,
+    handshake: request_response::Behaviour<HandshakeCodec>,
+    sync: request_response::Behaviour<SyncCodec>,
+}
+
+/// Cloneable handle for controlling a running [`P2PNetwork`]
+#[derive(Clone)]
+pub struct NetworkHandle {
+    local_peer_id: PeerId,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl NetworkHandle {
+    /// Our own peer ID
+    pub fn local_peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+
+    /// Connect to an address
+    pub fn dial(&self, address: Multiaddr) -> NetworkResult<()> {
+        self.send(Command::Dial(address))
+    }
+
+    /// Close all connections to a peer
+    pub fn disconnect(&self, peer: PeerId) -> NetworkResult<()> {
+        self.send(Command::Disconnect(peer))
+    }
+
+    /// Connected peers that completed the handshake
+    pub async fn peers(&self) -> NetworkResult<Vec<PeerId>> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::Peers(sender))?;
+        receiver.await.map_err(|_| NetworkError::Stopped)
+    }
+
+    /// Publish a message on its gossip topic
+    pub async fn publish(&self, message: GossipMessage) -> NetworkResult<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::Publish(message, sender))?;
+        receiver.await.map_err(|_| NetworkError::Stopped)?
+    }
+
+    /// Gossip score of a connected peer (negative after invalid messages)
+    pub async fn peer_score(&self, peer: PeerId) -> NetworkResult<Option<f64>> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::PeerScore(peer, sender))?;
+        receiver.await.map_err(|_| NetworkError::Stopped)
+    }
+
+    /// Report the local best and finalized block numbers and announce them
+    /// to connected peers
+    ///
+    /// A sync provider that tracks a chain takes precedence, since its head
+    /// is read again for every status sent.
+    pub fn set_head(&self, best: BlockNumber, finalized: BlockNumber) -> NetworkResult<()> {
+        self.send(Command::SetHead(best, finalized))
+    }
+
+    /// Send a sync request to a peer and wait for its response
+    pub async fn request(&self, peer: PeerId, request: SyncRequest) -> NetworkResult<SyncResponse> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::Request(peer, request, sender))?;
+        receiver.await.map_err(|_| NetworkError::Stopped)?
+    }
+
+    fn send(&self, command: Command) -> NetworkResult<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| NetworkError::Stopped)
+    }
+}
 
 /// P2P network manager
 pub struct P2PNetwork {
-    // TODO: Implement with libp2p
+    swarm: Swarm<Behaviour>,
+    chain_id: u64,
+    status: Status,
+    /// Connected peers whose handshake is still running
+    handshaking: HashSet<PeerId>,
+    /// Peers that completed the handshake
+    peers: HashMap<PeerId, Status>,
+    validator: Arc<dyn GossipValidator>,
+    provider: Option<Arc<dyn SyncProvider>>,
+    requests:
+        HashMap<request_response::OutboundRequestId, oneshot::Sender<NetworkResult<SyncResponse>>>,
+    handle: NetworkHandle,
+    commands: mpsc::UnboundedReceiver<Command>,
+    events: mpsc::Sender<NetworkEvent>,
 }
 
 impl P2PNetwork {
-    pub fn new() -> Self {
-        Self {}
+    /// Build the swarm, start listening and dial the bootstrap nodes
+    ///
+    /// Only peers on `genesis_hash` complete the handshake. Returns the
+    /// network together with the receiving end of its event channel. Nothing
+    /// happens until [`run`](Self::run) is polled.
+    pub fn new(
+        chain: &ChainConfig,
+        keypair: Keypair,
+        genesis_hash: BlockHash,
+    ) -> NetworkResult<(Self, mpsc::Receiver<NetworkEvent>)> {
+        let config = &chain.network;
+        let listen_addr = parse_addr(&config.listen_addr)?;
+        let bootstrap = config
+            .bootstrap_nodes
+            .iter()
+            .map(|node| parse_addr(node))
+            .collect::<NetworkResult<Vec<_>>>()?;
+
+        let max_peers = u32::try_from(config.max_peers).unwrap_or(u32::MAX);
+        let gossipsub =
+            gossip::behaviour(&keypair, chain.chain_id).map_err(NetworkError::Transport)?;
+        let mut swarm = SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default().nodelay(true),
+                noise::Config::new,
+                yamux::Config::default,
+            )
+            .map_err(|e| NetworkError::Transport(e.to_string()))?
+            .with_quic()
+            .with_behaviour(|key| {
+                let peer_id = key.public().to_peer_id();
+                let mut kad_config = kad::Config::default();
+                kad_config.set_protocol_names(vec![libp2p::StreamProtocol::new(KAD_PROTOCOL)]);
+                let mut kad =
+                    kad::Behaviour::with_config(peer_id, MemoryStore::new(peer_id), kad_config);
+                kad.set_mode(Some(kad::Mode::Server));
+                let limits = connection_limits::ConnectionLimits::default()
+                    .with_max_established(Some(max_peers))
+                    .with_max_established_per_peer(Some(1));
+                Behaviour {
+                    limits: connection_limits::Behaviour::new(limits),
+                    identify: identify::Behaviour::new(
+                        identify::Config::new(IDENTIFY_PROTOCOL.to_string(), key.public())
+                            .with_agent_version(format!("quantumchain/{}", crate::VERSION)),
+                    ),
+                    kad,
+                    gossipsub,
+                    handshake: handshake::behaviour(),
+                    sync: sync::behaviour(),
+                }
+            })
+            .map_err(|e| NetworkError::Transport(e.to_string()))?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(IDLE_TIMEOUT))
+            .build();
+
+        swarm
+            .listen_on(listen_addr.clone())
+            .map_err(|e| NetworkError::Transport(e.to_string()))?;
+        if config.enable_quic {
+            let quic_addr = quic_addr(&listen_addr)
+                .ok_or_else(|| NetworkError::InvalidAddress(config.listen_addr.clone()))?;
+            swarm
+                .listen_on(quic_addr)
+                .map_err(|e| NetworkError::Transport(e.to_string()))?;
+        }
+
+        for address in bootstrap {
+            if let Some(peer) = peer_id(&address) {
+                swarm
+                    .behaviour_mut()
+                    .kad
+                    .add_address(&peer, address.clone());
+            }
+            if let Err(e) = swarm.dial(address.clone()) {
+                tracing::warn!("Failed to dial bootstrap node {}: {}", address, e);
+            }
+        }
+
+        let (command_sender, commands) = mpsc::unbounded_channel();
+        let (events, event_receiver) = mpsc::channel(EVENT_BUFFER);
+        let handle = NetworkHandle {
+            local_peer_id: *swarm.local_peer_id(),
+            commands: command_sender,
+        };
+        let network = Self {
+            swarm,
+            chain_id: chain.chain_id,
+            status: Status::new(chain.chain_id, genesis_hash),
+            handshaking: HashSet::new(),
+            peers: HashMap::new(),
+            validator: Arc::new(StatelessValidator),
+            provider: None,
+            requests: HashMap::new(),
+            handle,
+            commands,
+            events,
+        };
+        Ok((network, event_receiver))
+    }
+
+    /// Check incoming gossip with `validator` (a [`StatelessValidator`] by
+    /// default)
+    pub fn with_gossip_validator(mut self, validator: Arc<dyn GossipValidator>) -> Self {
+        self.validator = validator;
+        self
+    }
+
+    /// Answer sync requests from `provider` (requests are refused otherwise)
+    pub fn with_sync_provider(mut self, provider: Arc<dyn SyncProvider>) -> Self {
+        self.status.capabilities = provider.capabilities();
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Our own peer ID
+    pub fn local_peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    /// Handle for controlling the network once it is running
+    pub fn handle(&self) -> NetworkHandle {
+        self.handle.clone()
+    }
+
+    /// Drive the swarm until the event receiver is dropped
+    pub async fn run(mut self) {
+        let mut bootstrap = tokio::time::interval(BOOTSTRAP_INTERVAL);
+        let mut refresh = tokio::time::interval(STATUS_INTERVAL);
+        loop {
+            tokio::select! {
+                event = self.swarm.select_next_some() => {
+                    if let Some(event) = self.on_swarm_event(event) {
+                        if self.events.send(event).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Some(command) = self.commands.recv() => self.on_command(command),
+                _ = bootstrap.tick() => {
+                    // Fails only while the routing table is still empty
+                    let _ = self.swarm.behaviour_mut().kad.bootstrap();
+                }
+                _ = refresh.tick() => self.announce_status(),
+            }
+        }
+    }
+
+    fn on_command(&mut self, command: Command) {
+        match command {
+            Command::Dial(address) => {
+                if let Err(e) = self.swarm.dial(address.clone()) {
+                    tracing::debug!("Failed to dial {}: {}", address, e);
+                }
+            }
+            Command::Disconnect(peer) => {
+                let _ = self.swarm.disconnect_peer_id(peer);
+            }
+            Command::Peers(reply) => {
+                let _ = reply.send(self.peers.keys().copied().collect());
+            }
+            Command::Publish(message, reply) => {
+                let topic = message.topic().topic(self.chain_id);
+                let result = self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .publish(topic, message.encode())
+                    .map(|_| ())
+                    .map_err(|e| NetworkError::Publish(e.to_string()));
+                let _ = reply.send(result);
+            }
+            Command::PeerScore(peer, reply) => {
+                let _ = reply.send(self.swarm.behaviour().gossipsub.peer_score(&peer));
+            }
+            Command::SetHead(best, finalized) => {
+                self.status.best = best;
+                self.status.finalized = finalized;
+                self.announce_status();
+            }
+            Command::Request(peer, request, reply) => {
+                if !self.peers.contains_key(&peer) {
+                    let _ = reply.send(Err(NetworkError::NoHandshake(peer)));
+                    return;
+                }
+                let id = self.swarm.behaviour_mut().sync.send_request(&peer, request);
+                self.requests.insert(id, reply);
+            }
+        }
+    }
+
+    fn on_swarm_event(&mut self, event: SwarmEvent<BehaviourEvent>) -> Option<NetworkEvent> {
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                tracing::info!("Listening on {}", address);
+                Some(NetworkEvent::Listening(address))
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                num_established,
+                ..
+            } if num_established.get() == 1 => {
+                self.handshaking.insert(peer_id);
+                let status = self.local_status();
+                self.swarm
+                    .behaviour_mut()
+                    .handshake
+                    .send_request(&peer_id, status);
+                None
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                self.handshaking.remove(&peer_id);
+                self.peers
+                    .remove(&peer_id)
+                    .map(|_| NetworkEvent::PeerDisconnected(peer_id))
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+            })) => {
+                let kad = &mut self.swarm.behaviour_mut().kad;
+                for address in info.listen_addrs {
+                    kad.add_address(&peer_id, address);
+                }
+                None
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kad(kad::Event::RoutingUpdated {
+                peer,
+                is_new_peer: true,
+                ..
+            })) => Some(NetworkEvent::PeerDiscovered(peer)),
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            })) => self.on_gossip(propagation_source, message_id, message),
+            SwarmEvent::Behaviour(BehaviourEvent::Handshake(event)) => self.on_handshake(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Sync(event)) => {
+                self.on_sync(event);
+                None
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                tracing::debug!("Outgoing connection to {:?} failed: {}", peer_id, error);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn on_handshake(
+        &mut self,
+        event: request_response::Event<Status, Status>,
+    ) -> Option<NetworkEvent> {
+        use request_response::{Event, Message};
+        match event {
+            Event::Message {
+                peer,
+                message:
+                    Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                // Answered even when rejecting, so that the peer learns why
+                let status = self.local_status();
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .handshake
+                    .send_response(channel, status);
+                self.on_status(peer, request)
+            }
+            Event::Message {
+                peer,
+                message: Message::Response { response, .. },
+            } => self.on_status(peer, response),
+            Event::OutboundFailure { peer, error, .. } => {
+                self.reject(peer, HandshakeError::Failed(error.to_string()))
+            }
+            Event::InboundFailure { peer, error, .. } => {
+                tracing::debug!("Handshake from {} failed: {}", peer, error);
+                None
+            }
+            Event::ResponseSent { .. } => None,
+        }
+    }
+
+    /// Our status, with the head read from the provider if it tracks one
+    fn local_status(&mut self) -> Status {
+        if let Some((best, finalized)) = self.provider.as_ref().and_then(|p| p.chain_head()) {
+            self.status.best = best;
+            self.status.finalized = finalized;
+        }
+        self.status.clone()
+    }
+
+    /// Send our status to every connected peer, which answers with its own
+    fn announce_status(&mut self) {
+        let status = self.local_status();
+        let peers: Vec<_> = self.peers.keys().copied().collect();
+        for peer in peers {
+            self.swarm
+                .behaviour_mut()
+                .handshake
+                .send_request(&peer, status.clone());
+        }
+    }
+
+    /// Accept or reject a peer by its status; the status arrives twice, as
+    /// the peer's request and as its response, and only the first counts
+    ///
+    /// Later statuses from a connected peer update its head.
+    fn on_status(&mut self, peer: PeerId, status: Status) -> Option<NetworkEvent> {
+        if let Some(known) = self.peers.get_mut(&peer) {
+            let moved = (known.best, known.finalized) != (status.best, status.finalized);
+            if self.status.check(&status).is_err() || !moved {
+                return None;
+            }
+            *known = status.clone();
+            return Some(NetworkEvent::PeerStatus(peer, status));
+        }
+        if !self.handshaking.contains(&peer) {
+            return None;
+        }
+        if let Err(e) = self.status.check(&status) {
+            return self.reject(peer, e);
+        }
+        self.handshaking.remove(&peer);
+        self.peers.insert(peer, status.clone());
+        tracing::debug!(
+            "Handshake with {} complete, best block {}",
+            peer,
+            status.best
+        );
+        Some(NetworkEvent::PeerConnected(peer, status))
+    }
+
+    fn reject(&mut self, peer: PeerId, reason: HandshakeError) -> Option<NetworkEvent> {
+        if !self.handshaking.remove(&peer) {
+            return None;
+        }
+        tracing::info!("Disconnecting incompatible peer {}: {}", peer, reason);
+        self.swarm.behaviour_mut().kad.remove_peer(&peer);
+
+        // Closing right away could cut off our own status on its way out
+        let handle = self.handle.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(REJECT_GRACE).await;
+            let _ = handle.disconnect(peer);
+        });
+        Some(NetworkEvent::PeerRejected(peer, reason))
+    }
+
+    fn on_sync(&mut self, event: request_response::Event<SyncRequest, SyncResponse>) {
+        use request_response::{Event, Message};
+        match event {
+            Event::Message {
+                peer,
+                message:
+                    Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                // Dropping the channel refuses the request: without a
+                // provider or before the peer completed the handshake
+                if !self.peers.contains_key(&peer) {
+                    tracing::debug!("Refusing sync request from {} before handshake", peer);
+                    return;
+                }
+                if let Some(provider) = &self.provider {
+                    let response = provider.respond(&request);
+                    let _ = self
+                        .swarm
+                        .behaviour_mut()
+                        .sync
+                        .send_response(channel, response);
+                }
+            }
+            Event::Message {
+                message:
+                    Message::Response {
+                        request_id,
+                        response,
+                    },
+                ..
+            } => {
+                if let Some(reply) = self.requests.remove(&request_id) {
+                    let _ = reply.send(Ok(response));
+                }
+            }
+            Event::OutboundFailure {
+                request_id, error, ..
+            } => {
+                if let Some(reply) = self.requests.remove(&request_id) {
+                    let _ = reply.send(Err(NetworkError::Request(error.to_string())));
+                }
+            }
+            Event::InboundFailure { peer, error, .. } => {
+                tracing::debug!("Sync request from {} failed: {}", peer, error);
+            }
+            Event::ResponseSent { .. } => {}
+        }
+    }
+
+    fn on_gossip(
+        &mut self,
+        source: PeerId,
+        id: gossipsub::MessageId,
+        message: gossipsub::Message,
+    ) -> Option<NetworkEvent> {
+        let decoded = GossipTopic::from_hash(self.chain_id, &message.topic)
+            .and_then(|topic| GossipMessage::decode(topic, &message.data));
+        let validation = match &decoded {
+            // Only peers that completed the handshake are listened to
+            _ if !self.peers.contains_key(&source) => Validation::Ignore,
+            Some(decoded) => self.validator.validate(&source, decoded),
+            None => Validation::Reject,
+        };
+        if validation == Validation::Reject {
+            tracing::debug!("Rejected gossip message from {}", source);
+        }
+
+        // Reporting the result forwards accepted messages and penalizes the
+        // source of rejected ones; failing to forward for lack of peers is fine
+        let _ = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .report_message_validation_result(&id, &source, validation.into());
+        match validation {
+            Validation::Accept => decoded.map(|message| NetworkEvent::Gossip { source, message }),
+            _ => None,
+        }
+    }
+}
+
+/// Load the node key from `path`, generating and saving one on first start
+///
+/// The peer ID is derived from this key, so keeping it keeps the peer ID
+/// stable across restarts.
+pub fn load_or_generate_keypair(path: &Path) -> NetworkResult<Keypair> {
+    let key_error =
+        |e: &dyn std::fmt::Display| NetworkError::NodeKey(format!("{}: {}", path.display(), e));
+    match std::fs::read(path) {
+        Ok(bytes) => Keypair::from_protobuf_encoding(&bytes).map_err(|e| key_error(&e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let keypair = Keypair::generate_ed25519();
+            let encoded = keypair.to_protobuf_encoding().map_err(|e| key_error(&e))?;
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|e| key_error(&e))?;
+            }
+            std::fs::write(path, encoded).map_err(|e| key_error(&e))?;
+            Ok(keypair)
+        }
+        Err(e) => Err(key_error(&e)),
     }
 }
 
-impl Default for P2PNetwork {
-    fn default() -> Self {
-        Self::new()
+fn parse_addr(address: &str) -> NetworkResult<Multiaddr> {
+    address
+        .parse()
+        .map_err(|_| NetworkError::InvalidAddress(address.to_string()))
+}
+
+/// Peer ID at the end of a `/p2p/...` address
+fn peer_id(address: &Multiaddr) -> Option<PeerId> {
+    match address.iter().last()? {
+        Protocol::P2p(peer) => Some(peer),
+        _ => None,
+    }
+}
+
+/// QUIC address on the same host and port as a TCP listen address
+fn quic_addr(tcp: &Multiaddr) -> Option<Multiaddr> {
+    let mut quic = Multiaddr::empty();
+    let mut has_tcp = false;
+    for protocol in tcp.iter() {
+        match protocol {
+            Protocol::Tcp(port) => {
+                quic.push(Protocol::Udp(port));
+                quic.push(Protocol::QuicV1);
+                has_tcp = true;
+            }
+            other => quic.push(other),
+        }
+    }
+    has_tcp.then_some(quic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NetworkConfig;
+    use crate::network::Capability;
+    use crate::storage::{BlockStore, MemoryStore};
+    use crate::test_utils::empty_block;
+    use crate::types::*;
+    use ed25519_dalek::SigningKey;
+
+    fn local_config(bootstrap_nodes: Vec<String>) -> ChainConfig {
+        ChainConfig {
+            network: NetworkConfig {
+                listen_addr: "/ip4/127.0.0.1/tcp/0".to_string(),
+                bootstrap_nodes,
+                enable_quic: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    const GENESIS: BlockHash = BlockHash([0; 32]);
+
+    /// Start a node on [`GENESIS`] and return its handle, events and listen
+    /// address
+    async fn spawn_node(
+        bootstrap: Option<&Multiaddr>,
+        configure: impl FnOnce(P2PNetwork) -> P2PNetwork,
+    ) -> (NetworkHandle, mpsc::Receiver<NetworkEvent>, Multiaddr) {
+        spawn_node_on(GENESIS, bootstrap, configure).await
+    }
+
+    async fn spawn_node_on(
+        genesis_hash: BlockHash,
+        bootstrap: Option<&Multiaddr>,
+        configure: impl FnOnce(P2PNetwork) -> P2PNetwork,
+    ) -> (NetworkHandle, mpsc::Receiver<NetworkEvent>, Multiaddr) {
+        let bootstrap_nodes = bootstrap.iter().map(|a| a.to_string()).collect();
+        let (network, mut events) = P2PNetwork::new(
+            &local_config(bootstrap_nodes),
+            Keypair::generate_ed25519(),
+            genesis_hash,
+        )
+        .unwrap();
+        let network = configure(network);
+        let handle = network.handle();
+        tokio::spawn(network.run());
+        let address = wait_for(&mut events, |event| match event {
+            NetworkEvent::Listening(listen) => Some(listen),
+            _ => None,
+        })
+        .await
+        .with(Protocol::P2p(handle.local_peer_id()));
+        (handle, events, address)
+    }
+
+    async fn wait_for<T>(
+        events: &mut mpsc::Receiver<NetworkEvent>,
+        mut select: impl FnMut(NetworkEvent) -> Option<T>,
+    ) -> T {
+        let wait = async {
+            while let Some(event) = events.recv().await {
+                if let Some(found) = select(event) {
+                    return found;
+                }
+            }
+            panic!("event channel closed");
+        };
+        tokio::time::timeout(Duration::from_secs(10), wait)
+            .await
+            .unwrap()
+    }
+
+    fn transaction(nonce: u64) -> Transaction {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut tx = Transaction {
+            from: PublicKey(key.verifying_key().to_bytes()).to_address(),
+            to: Some(Address([2; 20])),
+            value: Balance::from(100u64),
+            data: vec![],
+            gas_limit: 21_000,
+            kind: TxKind::Legacy {
+                gas_price: Balance::from(20u64),
+            },
+            nonce,
+            paymaster: None,
+            authorization: Authorization::Contract { data: vec![] },
+        };
+        tx.sign(&key);
+        tx
+    }
+
+    #[test]
+    fn test_addresses() {
+        let tcp: Multiaddr = "/ip4/0.0.0.0/tcp/30333".parse().unwrap();
+        assert_eq!(
+            quic_addr(&tcp).unwrap(),
+            "/ip4/0.0.0.0/udp/30333/quic-v1"
+                .parse::<Multiaddr>()
+                .unwrap()
+        );
+        assert!(quic_addr(&"/ip4/0.0.0.0/udp/1".parse().unwrap()).is_none());
+
+        let peer = Keypair::generate_ed25519().public().to_peer_id();
+        assert_eq!(peer_id(&tcp.with(Protocol::P2p(peer))), Some(peer));
+        assert!(matches!(
+            P2PNetwork::new(
+                &local_config(vec!["not an address".to_string()]),
+                Keypair::generate_ed25519(),
+                GENESIS
+            ),
+            Err(NetworkError::InvalidAddress(_))
+        ));
+    }
+
+    #[test]
+    fn test_node_key_persists() {
+        let path = std::env::temp_dir()
+            .join(format!("quantumchain-node-key-{}", std::process::id()))
+            .join(NODE_KEY_FILE);
+        let _ = std::fs::remove_file(&path);
+
+        let first = load_or_generate_keypair(&path).unwrap();
+        let second = load_or_generate_keypair(&path).unwrap();
+        assert_eq!(first.public().to_peer_id(), second.public().to_peer_id());
+
+        std::fs::write(&path, b"garbage").unwrap();
+        assert!(matches!(
+            load_or_generate_keypair(&path),
+            Err(NetworkError::NodeKey(_))
+        ));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_connects() {
+        let (first, _first_events, address) = spawn_node(None, |network| network).await;
+        let (second, mut second_events, _) = spawn_node(Some(&address), |network| network).await;
+
+        let first_id = first.local_peer_id();
+        wait_for(&mut second_events, |event| {
+            matches!(event, NetworkEvent::PeerConnected(peer, _) if peer == first_id).then_some(())
+        })
+        .await;
+        assert_eq!(second.peers().await.unwrap(), vec![first_id]);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_other_genesis() {
+        let (first, _first_events, address) = spawn_node(None, |network| {
+            network.with_sync_provider(Arc::new(BlockStore::new(Arc::new(MemoryStore::new()))))
+        })
+        .await;
+        first.set_head(12, 10).unwrap();
+        let first_id = first.local_peer_id();
+
+        let (forked, mut forked_events, _) =
+            spawn_node_on(BlockHash([1; 32]), Some(&address), |network| network).await;
+        let reason = wait_for(&mut forked_events, |event| match event {
+            NetworkEvent::PeerRejected(peer, reason) if peer == first_id => Some(reason),
+            NetworkEvent::PeerConnected(..) => panic!("connected to another genesis"),
+            _ => None,
+        })
+        .await;
+        assert!(matches!(reason, HandshakeError::Genesis { .. }));
+        assert!(forked.peers().await.unwrap().is_empty());
+        assert!(matches!(
+            forked.request(first_id, SyncRequest::Bodies(vec![])).await,
+            Err(NetworkError::NoHandshake(_))
+        ));
+
+        let (_, mut events, _) = spawn_node(Some(&address), |network| network).await;
+        let status = wait_for(&mut events, |event| match event {
+            NetworkEvent::PeerConnected(peer, status) if peer == first_id => Some(status),
+            _ => None,
+        })
+        .await;
+        assert_eq!((status.best, status.finalized), (12, 10));
+        assert!(status.supports(Capability::Blocks));
+    }
+
+    #[tokio::test]
+    async fn test_status_follows_the_head() {
+        // The head is read from the provider's chain at every handshake
+        let blocks = BlockStore::new(Arc::new(MemoryStore::new()));
+        let mut parent = None;
+        for _ in 0..4 {
+            let block = empty_block(parent.as_ref(), BlockHash([0; 32]));
+            blocks.insert_block(&block, &[]).unwrap();
+            blocks.set_head(block.hash()).unwrap();
+            parent = Some(block.header);
+        }
+        let (server, mut server_events, address) =
+            spawn_node(None, |network| network.with_sync_provider(Arc::new(blocks))).await;
+        let (client, mut client_events, _) = spawn_node(Some(&address), |network| network).await;
+        let server_id = server.local_peer_id();
+        let status = wait_for(&mut client_events, |event| match event {
+            NetworkEvent::PeerConnected(peer, status) if peer == server_id => Some(status),
+            _ => None,
+        })
+        .await;
+        assert_eq!((status.best, status.finalized), (3, 0));
+
+        // A moved head is announced to connected peers
+        let client_id = client.local_peer_id();
+        wait_for(&mut server_events, |event| {
+            matches!(event, NetworkEvent::PeerConnected(peer, _) if peer == client_id).then_some(())
+        })
+        .await;
+        client.set_head(9, 5).unwrap();
+        let status = wait_for(&mut server_events, |event| match event {
+            NetworkEvent::PeerStatus(peer, status) if peer == client_id => Some(status),
+            _ => None,
+        })
+        .await;
+        assert_eq!((status.best, status.finalized), (9, 5));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_gossip_penalized() {
+        let (sender, _sender_events, address) = spawn_node(None, |network| network).await;
+        let (receiver, mut receiver_events, _) =
+            spawn_node(Some(&address), |network| network).await;
+
+        // Publishing fails until the receiver's subscriptions have arrived
+        let valid = transaction(0);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while sender
+                .publish(GossipMessage::Transaction(Box::new(valid.clone())))
+                .await
+                .is_err()
+            {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let received = wait_for(&mut receiver_events, |event| match event {
+            NetworkEvent::Gossip {
+                message: GossipMessage::Transaction(tx),
+                source,
+            } => Some((tx, source)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(received.0.hash(), valid.hash());
+        assert_eq!(received.1, sender.local_peer_id());
+
+        let mut forged = transaction(1);
+        forged.value = Balance::from(1_000_000u64);
+        sender
+            .publish(GossipMessage::Transaction(Box::new(forged)))
+            .await
+            .unwrap();
+        let penalized = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let score = receiver.peer_score(sender.local_peer_id()).await.unwrap();
+                if score.is_some_and(|score| score < 0.0) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        assert!(penalized.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_sync_requests() {
+        let blocks = BlockStore::new(Arc::new(MemoryStore::new()));
+        let (server, _server_events, address) =
+            spawn_node(None, |network| network.with_sync_provider(Arc::new(blocks))).await;
+        let (client, mut client_events, _) = spawn_node(Some(&address), |network| network).await;
+        let server_id = server.local_peer_id();
+        wait_for(&mut client_events, |event| {
+            matches!(event, NetworkEvent::PeerConnected(peer, _) if peer == server_id).then_some(())
+        })
+        .await;
+
+        let response = client
+            .request(server_id, SyncRequest::Headers { start: 1, max: 10 })
+            .await
+            .unwrap();
+        assert!(matches!(
+            response,
+            SyncResponse::Headers { headers, finality: None } if headers.is_empty()
+        ));
+
+        // Nodes without a provider refuse to serve
+        assert!(matches!(
+            server
+                .request(client.local_peer_id(), SyncRequest::Bodies(vec![]))
+                .await,
+            Err(NetworkError::Request(_))
+        ));
     }
 }
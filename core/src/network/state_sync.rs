@@ -0,0 +1,559 @@
+//! Snapshot-based state sync
+//!
+//! Instead of re-executing the chain from genesis, a new node can start from
+//! a trusted checkpoint: the number and hash of a recent block, taken from
+//! configuration or a finality proof. The node fetches the checkpoint block
+//! to learn its state root, asks its peers for their snapshot manifests at
+//! that height and downloads the chunks in parallel from the peers whose
+//! manifest rebuilds that root. Every chunk is checked against the manifest
+//! as it arrives, and the rebuilt state against the root at the end. The
+//! checkpoint block then becomes the base of the local chain, and
+//! [`ChainSync`] continues with block sync from there.
+
+use super::sync::{
+    schedule, ChainSync, SyncConfig, SyncError, SyncRequest, SyncResponse, SyncResult,
+    SyncTransport,
+};
+use super::PeerId;
+use crate::consensus::Validator;
+use crate::crypto::merkle::transactions_root;
+use crate::storage::{
+    BlockImporter, BlockStore, KeyValueStore, SnapshotManifest, SnapshotRestore, StorageError,
+    SNAPSHOT_VERSION,
+};
+use crate::types::*;
+use libp2p::futures::future::join_all;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+/// Snapshot chunk still to be downloaded
+struct PendingChunk {
+    index: usize,
+    /// Peers that failed to serve it
+    tried: Vec<PeerId>,
+}
+
+/// Restores state from peer snapshots at a trusted checkpoint
+pub struct SnapshotSync<T: SyncTransport> {
+    transport: T,
+    store: Arc<dyn KeyValueStore>,
+    genesis_hash: BlockHash,
+    config: SyncConfig,
+    banned: HashSet<PeerId>,
+}
+
+impl<T: SyncTransport> SnapshotSync<T> {
+    /// Restore into `store` (which must hold no blocks) over `transport`,
+    /// for the chain starting at `genesis_hash`
+    pub fn new(transport: T, store: Arc<dyn KeyValueStore>, genesis_hash: BlockHash) -> Self {
+        Self {
+            transport,
+            store,
+            genesis_hash,
+            config: SyncConfig::default(),
+            banned: HashSet::new(),
+        }
+    }
+
+    /// Override the default retries
+    pub fn with_config(mut self, config: SyncConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Peers dropped for serving invalid data
+    pub fn banned(&self) -> &HashSet<PeerId> {
+        &self.banned
+    }
+
+    /// Continue with block sync over the same transport
+    pub fn into_chain_sync(
+        self,
+        importer: BlockImporter,
+        validators: Vec<Validator>,
+    ) -> ChainSync<T> {
+        ChainSync::new(self.transport, importer, validators).with_config(self.config)
+    }
+
+    /// Restore the state of block `number` with hash `hash` from `peers`
+    ///
+    /// Returns the checkpoint number, which is the local head afterwards.
+    pub async fn sync(
+        &mut self,
+        number: BlockNumber,
+        hash: BlockHash,
+        peers: &[PeerId],
+    ) -> SyncResult<BlockNumber> {
+        let blocks = BlockStore::new(self.store.clone());
+        if blocks.head_hash()?.is_some() {
+            return Err(SyncError::ChainNotEmpty);
+        }
+
+        let checkpoint = self.download_checkpoint(number, hash, peers).await?;
+        for (manifest, sources) in self.discover(&checkpoint.header, peers).await? {
+            tracing::info!(
+                "Restoring state of block {} from {} chunks served by {} peers",
+                number,
+                manifest.chunk_hashes.len(),
+                sources.len()
+            );
+            match self.download_chunks(manifest, &sources).await {
+                Ok(()) => {
+                    blocks.set_base(&checkpoint, self.genesis_hash)?;
+                    return Ok(number);
+                }
+                Err(SyncError::Storage(StorageError::InvalidSnapshot(reason))) => {
+                    for peer in sources {
+                        self.ban(peer, &format!("invalid snapshot: {}", reason));
+                    }
+                }
+                Err(e @ (SyncError::ChunkUnavailable(_) | SyncError::NoPeers)) => {
+                    tracing::debug!("Snapshot of block {} unavailable: {}", number, e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(SyncError::NoSnapshot(number))
+    }
+
+    fn usable(&self, peers: &[PeerId]) -> SyncResult<Vec<PeerId>> {
+        let peers: Vec<_> = peers
+            .iter()
+            .filter(|peer| !self.banned.contains(peer))
+            .copied()
+            .collect();
+        if peers.is_empty() {
+            return Err(SyncError::NoPeers);
+        }
+        Ok(peers)
+    }
+
+    /// Fetch the checkpoint header and body from the first peer serving them
+    async fn download_checkpoint(
+        &mut self,
+        number: BlockNumber,
+        hash: BlockHash,
+        peers: &[PeerId],
+    ) -> SyncResult<Block> {
+        for peer in self.usable(peers)? {
+            let request = SyncRequest::Headers {
+                start: number,
+                max: 1,
+            };
+            let header = match self.transport.request(peer, request).await {
+                Ok(SyncResponse::Headers { headers, .. }) => match headers.into_iter().next() {
+                    Some(header) if header.hash() == hash => header,
+                    Some(_) => {
+                        self.ban(peer, "different block at the checkpoint height");
+                        continue;
+                    }
+                    None => continue,
+                },
+                Ok(_) => {
+                    self.ban(peer, "wrong response type");
+                    continue;
+                }
+                Err(e) => {
+                    tracing::debug!("Checkpoint request to {} failed: {}", peer, e);
+                    continue;
+                }
+            };
+
+            match self
+                .transport
+                .request(peer, SyncRequest::Bodies(vec![hash]))
+                .await
+            {
+                Ok(SyncResponse::Bodies(bodies)) => match bodies.into_iter().next() {
+                    Some(transactions)
+                        if transactions_root(&transactions) == header.transactions_root =>
+                    {
+                        return Ok(Block {
+                            header,
+                            transactions,
+                        });
+                    }
+                    Some(_) => self.ban(peer, "body does not match header"),
+                    None => {}
+                },
+                Ok(_) => self.ban(peer, "wrong response type"),
+                Err(e) => tracing::debug!("Checkpoint request to {} failed: {}", peer, e),
+            }
+        }
+        Err(SyncError::CheckpointUnavailable(number))
+    }
+
+    /// Ask every peer for its manifest at the checkpoint and collect those
+    /// matching the checkpoint state root, the most widely served first
+    async fn discover(
+        &mut self,
+        checkpoint: &BlockHeader,
+        peers: &[PeerId],
+    ) -> SyncResult<Vec<(SnapshotManifest, Vec<PeerId>)>> {
+        let peers = self.usable(peers)?;
+        let transport = &self.transport;
+        let requests = peers.iter().map(|peer| {
+            let request = SyncRequest::SnapshotManifest(Some(checkpoint.number));
+            async move { (*peer, transport.request(*peer, request).await) }
+        });
+        let responses = join_all(requests).await;
+
+        let mut offers: Vec<(SnapshotManifest, Vec<PeerId>)> = vec![];
+        for (peer, response) in responses {
+            let manifest = match response {
+                Ok(SyncResponse::SnapshotManifest(Some(manifest))) => manifest,
+                Ok(SyncResponse::SnapshotManifest(None)) => continue,
+                Ok(_) => {
+                    self.ban(peer, "wrong response type");
+                    continue;
+                }
+                Err(e) => {
+                    tracing::debug!("Manifest request to {} failed: {}", peer, e);
+                    continue;
+                }
+            };
+            if manifest.block_number != checkpoint.number
+                || manifest.state_root != checkpoint.state_root
+            {
+                self.ban(peer, "snapshot does not match the checkpoint");
+                continue;
+            }
+            if manifest.version != SNAPSHOT_VERSION {
+                continue;
+            }
+            match offers.iter_mut().find(|(offered, _)| *offered == manifest) {
+                Some((_, sources)) => sources.push(peer),
+                None => offers.push((manifest, vec![peer])),
+            }
+        }
+
+        if offers.is_empty() {
+            return Err(SyncError::NoSnapshot(checkpoint.number));
+        }
+        offers.sort_by_key(|(_, sources)| Reverse(sources.len()));
+        Ok(offers)
+    }
+
+    /// Fetch chunks from `sources` in parallel and apply them in order
+    ///
+    /// A chunk is given up on once every source failed to serve it.
+    async fn download_chunks(
+        &mut self,
+        manifest: SnapshotManifest,
+        sources: &[PeerId],
+    ) -> SyncResult<()> {
+        let number = manifest.block_number;
+        let mut pending: VecDeque<_> = (0..manifest.chunk_hashes.len())
+            .map(|index| PendingChunk {
+                index,
+                tried: vec![],
+            })
+            .collect();
+        let mut restore = SnapshotRestore::new(self.store.clone(), manifest)?;
+        // Verified chunks waiting for the ones before them
+        let mut received = BTreeMap::new();
+
+        while !restore.is_complete() {
+            let peers = self.usable(sources)?;
+            let wave = schedule(&mut pending, &peers, |chunk| &chunk.tried);
+
+            let transport = &self.transport;
+            let requests = wave.iter().map(|(peer, chunk)| {
+                let request = SyncRequest::SnapshotChunk {
+                    number,
+                    index: chunk.index as u32,
+                };
+                transport.request(*peer, request)
+            });
+            let responses = join_all(requests).await;
+
+            for ((peer, mut chunk), response) in wave.into_iter().zip(responses) {
+                match response {
+                    Ok(SyncResponse::SnapshotChunk(Some(bytes))) => {
+                        if restore.manifest().verify_chunk(chunk.index, &bytes) {
+                            received.insert(chunk.index, bytes);
+                            continue;
+                        }
+                        self.ban(peer, "chunk does not match manifest");
+                    }
+                    Ok(SyncResponse::SnapshotChunk(None)) => {}
+                    Ok(_) => self.ban(peer, "wrong response type"),
+                    Err(e) => tracing::debug!("Chunk request to {} failed: {}", peer, e),
+                }
+                chunk.tried.push(peer);
+                if chunk.tried.len() >= sources.len() {
+                    return Err(SyncError::ChunkUnavailable(chunk.index));
+                }
+                pending.push_back(chunk);
+            }
+
+            while let Some(bytes) = received.remove(&restore.next_chunk()) {
+                restore.apply_chunk(&bytes)?;
+            }
+            tracing::debug!(
+                "Applied {}/{} snapshot chunks",
+                restore.next_chunk(),
+                restore.manifest().chunk_hashes.len()
+            );
+        }
+
+        restore.finish()?;
+        Ok(())
+    }
+
+    fn ban(&mut self, peer: PeerId, reason: &str) {
+        tracing::warn!("Dropping snapshot peer {}: {}", peer, reason);
+        self.banned.insert(peer);
+        self.transport.penalize(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{SyncProvider, SyncServer};
+    use crate::storage::{
+        check_integrity, create_chunks, MemoryStore, PruningMode, SnapshotEntry, SnapshotStore,
+        StateManager,
+    };
+    use crate::test_utils::{
+        empty_block, finalize_head, peer, validator_keys, validator_set, LocalTransport,
+    };
+    use crate::vm::executor::TransactionExecutor;
+
+    /// Serves blocks and an in-memory snapshot
+    struct Snapshotting {
+        blocks: Arc<BlockStore>,
+        manifest: SnapshotManifest,
+        chunks: Vec<Vec<u8>>,
+    }
+
+    impl SyncProvider for Snapshotting {
+        fn respond(&self, request: &SyncRequest) -> SyncResponse {
+            match request {
+                SyncRequest::SnapshotManifest(_) => {
+                    SyncResponse::SnapshotManifest(Some(self.manifest.clone()))
+                }
+                SyncRequest::SnapshotChunk { index, .. } => {
+                    SyncResponse::SnapshotChunk(self.chunks.get(*index as usize).cloned())
+                }
+                request => self.blocks.respond(request),
+            }
+        }
+    }
+
+    /// Chain of `length` empty blocks on a genesis state with many accounts
+    fn serving_chain(length: u64) -> Arc<dyn KeyValueStore> {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut state = StateManager::new(store.clone());
+        for i in 0..40u8 {
+            let account = Account {
+                balance: Balance::from(1_000u64 + i as u64),
+                ..Default::default()
+            };
+            state.set_account(Address([i; 20]), account);
+        }
+        let root = state.commit(0).unwrap();
+
+        let importer = BlockImporter::new(store.clone(), TransactionExecutor::new());
+        let mut parent: Option<BlockHeader> = None;
+        for _ in 0..=length {
+            let block = empty_block(parent.as_ref(), root);
+            importer.import_block(&block).unwrap();
+            finalize_head(importer.blocks());
+            parent = Some(block.header);
+        }
+        store
+    }
+
+    /// Snapshot of the state at `number`, split into `parts` chunks
+    fn chunked_snapshot(
+        store: &Arc<dyn KeyValueStore>,
+        number: BlockNumber,
+        parts: usize,
+    ) -> (SnapshotManifest, Vec<Vec<u8>>) {
+        let root = BlockStore::new(store.clone())
+            .state_root(number)
+            .unwrap()
+            .unwrap();
+        let mut entries: Vec<SnapshotEntry> = vec![];
+        create_chunks(&**store, root, number, |_, chunk| {
+            entries.extend(bincode::deserialize::<Vec<SnapshotEntry>>(chunk).unwrap());
+            Ok(())
+        })
+        .unwrap();
+
+        let chunks: Vec<Vec<u8>> = entries
+            .chunks(entries.len().div_ceil(parts))
+            .map(|part| bincode::serialize(part).unwrap())
+            .collect();
+        let manifest = SnapshotManifest {
+            version: SNAPSHOT_VERSION,
+            block_number: number,
+            state_root: root,
+            chunk_hashes: chunks.iter().map(|c| *blake3::hash(c).as_bytes()).collect(),
+        };
+        (manifest, chunks)
+    }
+
+    #[test]
+    fn test_server_serves_snapshots() {
+        let store = serving_chain(10);
+        let dir =
+            std::env::temp_dir().join(format!("quantumchain-sync-server-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let snapshots = SnapshotStore::new(&dir, 5);
+        let blocks = BlockStore::new(store.clone());
+        for number in [5, 10] {
+            let root = blocks.state_root(number).unwrap().unwrap();
+            snapshots.on_block(&*store, root, number).unwrap();
+        }
+
+        let server = SyncServer::new(BlockStore::new(store.clone()), snapshots);
+        let manifest = match server.respond(&SyncRequest::SnapshotManifest(None)) {
+            SyncResponse::SnapshotManifest(Some(manifest)) => manifest,
+            response => panic!("unexpected response {:?}", response),
+        };
+        assert_eq!(manifest.block_number, 10);
+        assert!(matches!(
+            server.respond(&SyncRequest::SnapshotManifest(Some(7))),
+            SyncResponse::SnapshotManifest(None)
+        ));
+
+        let chunk = SyncRequest::SnapshotChunk {
+            number: 10,
+            index: 0,
+        };
+        assert!(matches!(
+            server.respond(&chunk),
+            SyncResponse::SnapshotChunk(Some(bytes)) if manifest.verify_chunk(0, &bytes)
+        ));
+        let missing = SyncRequest::SnapshotChunk {
+            number: 10,
+            index: manifest.chunk_hashes.len() as u32,
+        };
+        assert!(matches!(
+            server.respond(&missing),
+            SyncResponse::SnapshotChunk(None)
+        ));
+        // Block requests are still served
+        assert!(matches!(
+            server.respond(&SyncRequest::Headers { start: 3, max: 2 }),
+            SyncResponse::Headers { headers, .. } if headers.len() == 2
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_sync_then_block_sync() {
+        let source = serving_chain(20);
+        let blocks = Arc::new(BlockStore::new(source.clone()));
+        let genesis = blocks.canonical_hash(0).unwrap().unwrap();
+        let checkpoint = blocks.canonical_hash(10).unwrap().unwrap();
+        let (manifest, chunks) = chunked_snapshot(&source, 10, 4);
+        assert_eq!(chunks.len(), 4);
+
+        let (first, second, corrupt, forked) = (peer(), peer(), peer(), peer());
+        let mut transport = LocalTransport::default();
+        for honest in [first, second] {
+            let provider = Snapshotting {
+                blocks: blocks.clone(),
+                manifest: manifest.clone(),
+                chunks: chunks.clone(),
+            };
+            transport.providers.insert(honest, Arc::new(provider));
+        }
+        let mut corrupted = chunks.clone();
+        for chunk in &mut corrupted {
+            chunk[0] ^= 1;
+        }
+        let provider = Snapshotting {
+            blocks: blocks.clone(),
+            manifest: manifest.clone(),
+            chunks: corrupted,
+        };
+        transport.providers.insert(corrupt, Arc::new(provider));
+        let provider = Snapshotting {
+            blocks: blocks.clone(),
+            manifest: SnapshotManifest {
+                state_root: BlockHash([9; 32]),
+                ..manifest.clone()
+            },
+            chunks: chunks.clone(),
+        };
+        transport.providers.insert(forked, Arc::new(provider));
+
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut sync = SnapshotSync::new(transport, store.clone(), genesis);
+        let peers = [corrupt, forked, first, second];
+        assert_eq!(sync.sync(10, checkpoint, &peers).await.unwrap(), 10);
+        assert_eq!(*sync.banned(), HashSet::from([corrupt, forked]));
+
+        let restored = BlockStore::new(store.clone());
+        assert_eq!(restored.base().unwrap(), 10);
+        assert_eq!(restored.head_hash().unwrap(), Some(checkpoint));
+        // The node opens the chain by its genesis hash, though block 0 is not stored
+        assert_eq!(restored.canonical_hash(0).unwrap(), None);
+        assert_eq!(restored.genesis_hash().unwrap(), Some(genesis));
+        let state = StateManager::at_root(store.clone(), manifest.state_root);
+        let account = state.get_account(&Address([7; 20])).unwrap().unwrap();
+        assert_eq!(account.balance, Balance::from(1_007u64));
+
+        // A second restore into the same store is refused
+        assert!(matches!(
+            sync.sync(10, checkpoint, &peers).await,
+            Err(SyncError::ChainNotEmpty)
+        ));
+
+        // Block sync picks up from the checkpoint
+        let importer = BlockImporter::new(store.clone(), TransactionExecutor::new());
+        let mut chain = sync.into_chain_sync(importer, validator_set(&validator_keys()));
+        assert_eq!(chain.sync(&[first, second]).await.unwrap(), 20);
+        assert!(check_integrity(store, PruningMode::Archive)
+            .unwrap()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_sync_falls_back_to_other_manifests() {
+        let source = serving_chain(10);
+        let blocks = Arc::new(BlockStore::new(source.clone()));
+        let genesis = blocks.canonical_hash(0).unwrap().unwrap();
+        let checkpoint = blocks.canonical_hash(10).unwrap().unwrap();
+        let (manifest, chunks) = chunked_snapshot(&source, 10, 4);
+
+        // Most peers serve a manifest missing the last chunk, which rebuilds
+        // a different state
+        let (honest, truncating) = (peer(), [peer(), peer()]);
+        let mut transport = LocalTransport::default();
+        let provider = Snapshotting {
+            blocks: blocks.clone(),
+            manifest: manifest.clone(),
+            chunks: chunks.clone(),
+        };
+        transport.providers.insert(honest, Arc::new(provider));
+        for peer in truncating {
+            let provider = Snapshotting {
+                blocks: blocks.clone(),
+                manifest: SnapshotManifest {
+                    chunk_hashes: manifest.chunk_hashes[..3].to_vec(),
+                    ..manifest.clone()
+                },
+                chunks: chunks[..3].to_vec(),
+            };
+            transport.providers.insert(peer, Arc::new(provider));
+        }
+
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut sync = SnapshotSync::new(transport, store.clone(), genesis);
+        let peers = [truncating[0], honest, truncating[1]];
+        assert_eq!(sync.sync(10, checkpoint, &peers).await.unwrap(), 10);
+        assert_eq!(*sync.banned(), HashSet::from(truncating));
+        assert_eq!(
+            BlockStore::new(store).head_hash().unwrap(),
+            Some(checkpoint)
+        );
+    }
+}
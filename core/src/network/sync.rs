@@ -1,3 +1,1008 @@
 //! State synchronization
+//!
+//! Header-first block sync over a request/response protocol. A syncing node
+//! asks several peers for consecutive header ranges at once, checks that the
+//! ranges link onto its head and onto each other, and checks the finality
+//! proofs served with them against the validator set. Only headers up to a
+//! proven block are accepted. Bodies for those are then fetched in parallel
+//! batches, matched against each header's transactions root and fed to the
+//! [`BlockImporter`], which re-executes them and stores the proof. Peers that
+//! serve invalid data are disconnected; peers that merely fail are skipped and
+//! the request is retried on the next one.
+//!
+//! The same protocol serves state snapshot manifests and chunks for
+//! [`SnapshotSync`](super::state_sync::SnapshotSync).
 
-// TODO: Implement fast sync, warp sync, light client sync
+use super::handshake::Capability;
+use super::{NetworkHandle, NetworkResult, PeerId};
+use crate::consensus::finality::FinalityProof;
+use crate::consensus::Validator;
+use crate::crypto::merkle::transactions_root;
+use crate::storage::{
+    BlockImporter, BlockStore, ImportError, SnapshotManifest, SnapshotStore, StorageError,
+    StorageResult,
+};
+use crate::types::*;
+use async_trait::async_trait;
+use libp2p::futures::future::join_all;
+use libp2p::futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::{request_response, StreamProtocol};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use thiserror::Error;
+
+/// Sync protocol name
+pub const SYNC_PROTOCOL: &str = "/quantumchain/sync/1";
+
+/// Largest accepted sync message
+pub const MAX_SYNC_MESSAGE: u32 = 16 * 1024 * 1024;
+
+/// Most headers served per request
+pub const MAX_HEADERS_PER_REQUEST: u32 = 512;
+
+/// Most bodies served per request
+pub const MAX_BODIES_PER_REQUEST: usize = 128;
+
+/// Sync requests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncRequest {
+    /// Canonical headers in ascending order
+    Headers {
+        /// First block number
+        start: BlockNumber,
+        /// Most headers wanted
+        max: u32,
+    },
+    /// Transactions of the given blocks, in order
+    Bodies(Vec<BlockHash>),
+    /// Manifest of the snapshot taken at a block, or of the latest one
+    SnapshotManifest(Option<BlockNumber>),
+    /// One chunk of the snapshot taken at a block
+    SnapshotChunk {
+        /// Snapshot block number
+        number: BlockNumber,
+        /// Chunk index
+        index: u32,
+    },
+}
+
+/// Sync responses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncResponse {
+    /// Consecutive headers, optionally with a proof that one of them is final
+    Headers {
+        /// Headers from the requested start, possibly fewer than asked for
+        headers: Vec<BlockHeader>,
+        /// Finality proof for one of the headers
+        finality: Option<FinalityProof>,
+    },
+    /// Block bodies for a prefix of the requested hashes
+    Bodies(Vec<Vec<Transaction>>),
+    /// Requested manifest, if such a snapshot is served
+    SnapshotManifest(Option<SnapshotManifest>),
+    /// Requested chunk, if served
+    SnapshotChunk(Option<Vec<u8>>),
+}
+
+/// Answers sync requests from local storage
+pub trait SyncProvider: Send + Sync {
+    /// Response to a request from a peer
+    fn respond(&self, request: &SyncRequest) -> SyncResponse;
+
+    /// Services announced in the handshake
+    fn capabilities(&self) -> Vec<Capability> {
+        vec![Capability::Blocks]
+    }
+
+    /// Best and finalized block numbers announced in the handshake, if the
+    /// provider tracks a chain
+    fn chain_head(&self) -> Option<(BlockNumber, BlockNumber)> {
+        None
+    }
+}
+
+/// Serves canonical headers with the highest stored finality proof among
+/// them, and bodies, but no snapshots
+impl SyncProvider for BlockStore {
+    fn respond(&self, request: &SyncRequest) -> SyncResponse {
+        match request {
+            SyncRequest::Headers { start, max } => {
+                let mut headers = vec![];
+                for number in (*start..).take((*max).min(MAX_HEADERS_PER_REQUEST) as usize) {
+                    match self.block_by_number(number) {
+                        Ok(Some(block)) => headers.push(block.header),
+                        Ok(None) => break,
+                        Err(e) => {
+                            tracing::warn!("Failed to serve header {}: {}", number, e);
+                            break;
+                        }
+                    }
+                }
+                let finality = headers.iter().rev().find_map(|header| {
+                    match self.finality_proof(header.number) {
+                        Ok(proof) => proof.filter(|proof| proof.block_hash == header.hash()),
+                        Err(e) => {
+                            tracing::warn!("Failed to serve finality of {}: {}", header.number, e);
+                            None
+                        }
+                    }
+                });
+                SyncResponse::Headers { headers, finality }
+            }
+            SyncRequest::Bodies(hashes) => {
+                let mut bodies = vec![];
+                for hash in hashes.iter().take(MAX_BODIES_PER_REQUEST) {
+                    match self.block(hash) {
+                        Ok(Some(block)) => bodies.push(block.transactions),
+                        Ok(None) => break,
+                        Err(e) => {
+                            tracing::warn!("Failed to serve block {}: {}", hash, e);
+                            break;
+                        }
+                    }
+                }
+                SyncResponse::Bodies(bodies)
+            }
+            SyncRequest::SnapshotManifest(_) => SyncResponse::SnapshotManifest(None),
+            SyncRequest::SnapshotChunk { .. } => SyncResponse::SnapshotChunk(None),
+        }
+    }
+
+    fn chain_head(&self) -> Option<(BlockNumber, BlockNumber)> {
+        let read = || -> StorageResult<_> {
+            let Some(head) = self.head()? else {
+                return Ok(None);
+            };
+            Ok(Some((head.number, self.finalized()?.unwrap_or(0))))
+        };
+        read().unwrap_or_else(|e| {
+            tracing::warn!("Failed to read the chain head: {}", e);
+            None
+        })
+    }
+}
+
+/// Serves blocks from a block store and snapshots from a snapshot directory
+pub struct SyncServer {
+    blocks: BlockStore,
+    snapshots: SnapshotStore,
+}
+
+impl SyncServer {
+    /// Serve `blocks` and the complete snapshots in `snapshots`
+    pub fn new(blocks: BlockStore, snapshots: SnapshotStore) -> Self {
+        Self { blocks, snapshots }
+    }
+
+    fn manifest(&self, number: Option<BlockNumber>) -> StorageResult<Option<SnapshotManifest>> {
+        let available = self.snapshots.snapshots()?;
+        match number.or_else(|| available.last().copied()) {
+            Some(number) if available.contains(&number) => {
+                Ok(Some(self.snapshots.snapshot(number).manifest()?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn chunk(&self, number: BlockNumber, index: usize) -> StorageResult<Option<Vec<u8>>> {
+        match self.manifest(Some(number))? {
+            Some(manifest) if index < manifest.chunk_hashes.len() => {
+                Ok(Some(self.snapshots.snapshot(number).chunk(index)?))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl SyncProvider for SyncServer {
+    fn respond(&self, request: &SyncRequest) -> SyncResponse {
+        match request {
+            SyncRequest::SnapshotManifest(number) => {
+                SyncResponse::SnapshotManifest(self.manifest(*number).unwrap_or_else(|e| {
+                    tracing::warn!("Failed to serve snapshot manifest: {}", e);
+                    None
+                }))
+            }
+            SyncRequest::SnapshotChunk { number, index } => SyncResponse::SnapshotChunk(
+                self.chunk(*number, *index as usize).unwrap_or_else(|e| {
+                    tracing::warn!("Failed to serve snapshot chunk {}/{}: {}", number, index, e);
+                    None
+                }),
+            ),
+            request => self.blocks.respond(request),
+        }
+    }
+
+    fn chain_head(&self) -> Option<(BlockNumber, BlockNumber)> {
+        self.blocks.chain_head()
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        vec![Capability::Blocks, Capability::Snapshots]
+    }
+}
+
+/// Length-prefixed bincode encoding of sync messages
+#[derive(Debug, Clone, Default)]
+pub struct SyncCodec;
+
+pub(super) async fn read_message<T, M>(io: &mut T) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let mut prefix = [0u8; 4];
+    io.read_exact(&mut prefix).await?;
+    let length = u32::from_be_bytes(prefix);
+    if length > MAX_SYNC_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "sync message too large",
+        ));
+    }
+    let mut message = vec![0u8; length as usize];
+    io.read_exact(&mut message).await?;
+    bincode::deserialize(&message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub(super) async fn write_message<T, M>(io: &mut T, message: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let message = bincode::serialize(message).unwrap();
+    let length = u32::try_from(message.len())
+        .ok()
+        .filter(|length| *length <= MAX_SYNC_MESSAGE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sync message too large"))?;
+    io.write_all(&length.to_be_bytes()).await?;
+    io.write_all(&message).await?;
+    io.close().await
+}
+
+#[async_trait]
+impl request_response::Codec for SyncCodec {
+    type Protocol = StreamProtocol;
+    type Request = SyncRequest;
+    type Response = SyncResponse;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<SyncRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<SyncResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: SyncRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: SyncResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &response).await
+    }
+}
+
+/// Request/response behaviour for the sync protocol
+pub(crate) fn behaviour() -> request_response::Behaviour<SyncCodec> {
+    request_response::Behaviour::new(
+        [(
+            StreamProtocol::new(SYNC_PROTOCOL),
+            request_response::ProtocolSupport::Full,
+        )],
+        request_response::Config::default(),
+    )
+}
+
+/// Sends sync requests to peers
+#[async_trait]
+pub trait SyncTransport: Send + Sync {
+    /// Send a request and wait for the response
+    async fn request(&self, peer: PeerId, request: SyncRequest) -> NetworkResult<SyncResponse>;
+
+    /// Drop a peer that served invalid data
+    fn penalize(&self, peer: PeerId);
+}
+
+#[async_trait]
+impl SyncTransport for NetworkHandle {
+    async fn request(&self, peer: PeerId, request: SyncRequest) -> NetworkResult<SyncResponse> {
+        NetworkHandle::request(self, peer, request).await
+    }
+
+    fn penalize(&self, peer: PeerId) {
+        let _ = self.disconnect(peer);
+    }
+}
+
+/// Sync errors
+#[derive(Error, Debug)]
+pub enum SyncError {
+    #[error("Local chain has no head block")]
+    NoHead,
+
+    #[error("No usable peers left")]
+    NoPeers,
+
+    #[error("No peer served the body of block {0}")]
+    BodiesUnavailable(BlockNumber),
+
+    #[error("Local chain is not empty")]
+    ChainNotEmpty,
+
+    #[error("No peer served checkpoint block {0}")]
+    CheckpointUnavailable(BlockNumber),
+
+    #[error("No peer serves a snapshot of block {0}")]
+    NoSnapshot(BlockNumber),
+
+    #[error("No peer served snapshot chunk {0}")]
+    ChunkUnavailable(usize),
+
+    #[error("Import failed: {0}")]
+    Import(#[from] ImportError),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+}
+
+/// Result type for sync operations
+pub type SyncResult<T> = Result<T, SyncError>;
+
+/// Sync tuning
+#[derive(Debug, Clone)]
+pub struct SyncConfig {
+    /// Headers requested from each peer per round
+    pub header_batch: u32,
+    /// Bodies requested per request
+    pub body_batch: usize,
+    /// Peers asked for the same body batch before giving up
+    pub max_attempts: usize,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            header_batch: 192,
+            body_batch: 64,
+            max_attempts: 3,
+        }
+    }
+}
+
+/// Header-first block sync
+pub struct ChainSync<T: SyncTransport> {
+    transport: T,
+    importer: BlockImporter,
+    validators: Vec<Validator>,
+    config: SyncConfig,
+    banned: HashSet<PeerId>,
+    rounds: usize,
+}
+
+impl<T: SyncTransport> ChainSync<T> {
+    /// Sync into `importer` over `transport`, importing only headers covered
+    /// by a finality proof from `validators`
+    pub fn new(transport: T, importer: BlockImporter, validators: Vec<Validator>) -> Self {
+        Self {
+            transport,
+            importer,
+            validators,
+            config: SyncConfig::default(),
+            banned: HashSet::new(),
+            rounds: 0,
+        }
+    }
+
+    /// Override the default batch sizes and retries
+    pub fn with_config(mut self, config: SyncConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Importer the synced blocks are written to
+    pub fn importer(&self) -> &BlockImporter {
+        &self.importer
+    }
+
+    /// Peers dropped for serving invalid data
+    pub fn banned(&self) -> &HashSet<PeerId> {
+        &self.banned
+    }
+
+    /// Sync from `peers` until none of them has anything new
+    ///
+    /// Returns the local head number afterwards.
+    pub async fn sync(&mut self, peers: &[PeerId]) -> SyncResult<BlockNumber> {
+        let mut failures = 0;
+        loop {
+            let head = self.importer.blocks().head()?.ok_or(SyncError::NoHead)?;
+            let peers: Vec<_> = peers
+                .iter()
+                .filter(|peer| !self.banned.contains(peer))
+                .copied()
+                .collect();
+            if peers.is_empty() {
+                return Err(SyncError::NoPeers);
+            }
+
+            let round = self.download_headers(&head, &peers).await;
+            if round.ranges.is_empty() {
+                // Retry with the ranges rotated across peers before giving up
+                if round.failed && failures + 1 < self.config.max_attempts {
+                    failures += 1;
+                    continue;
+                }
+                return Ok(head.number);
+            }
+            failures = 0;
+            let headers = round
+                .ranges
+                .iter()
+                .flat_map(|(_, headers)| headers.iter().cloned())
+                .collect();
+            let mut blocks = self.download_bodies(headers, &peers).await?.into_iter();
+            let mut imported = None;
+            'ranges: for (peer, headers) in round.ranges {
+                for block in blocks.by_ref().take(headers.len()) {
+                    match self.importer.import_block(&block) {
+                        Ok(_) => {
+                            let first = imported.map_or(block.header.number, |(first, _)| first);
+                            imported = Some((first, block.header.number));
+                        }
+                        // Local failures say nothing about the peer
+                        Err(
+                            e @ (ImportError::Storage(_)
+                            | ImportError::State(_)
+                            | ImportError::MissingState(_)),
+                        ) => return Err(e.into()),
+                        Err(e) => {
+                            // Later ranges build on this one, so refetch them next round
+                            self.ban(peer, &format!("block {}: {}", block.header.number, e));
+                            break 'ranges;
+                        }
+                    }
+                }
+            }
+            if let Some((first, last)) = imported {
+                tracing::info!("Synced blocks {}-{}", first, last);
+                // Keep the proof to serve it on
+                if let Some(proof) = round.finality.filter(|proof| proof.number <= last) {
+                    self.importer.finalize(&proof)?;
+                }
+            }
+        }
+    }
+
+    /// Fetch one header range per peer in parallel and keep the linked prefix
+    /// up to the highest proven header
+    async fn download_headers(&mut self, head: &BlockHeader, peers: &[PeerId]) -> HeaderRound {
+        let batch = self.config.header_batch.clamp(1, MAX_HEADERS_PER_REQUEST);
+        let offset = self.rounds % peers.len();
+        self.rounds += 1;
+        let order = peers.iter().cycle().skip(offset).take(peers.len());
+        let transport = &self.transport;
+        let requests = order.enumerate().map(|(index, peer)| {
+            let start = head.number + 1 + index as u64 * batch as u64;
+            let request = SyncRequest::Headers { start, max: batch };
+            async move { (*peer, transport.request(*peer, request).await) }
+        });
+        let responses = join_all(requests).await;
+
+        let mut accepted: Vec<(PeerId, Vec<BlockHeader>)> = vec![];
+        let mut parent = head.clone();
+        let mut proven: Option<FinalityProof> = None;
+        let mut failed = false;
+        for (peer, response) in responses {
+            let (headers, finality) = match response {
+                Ok(SyncResponse::Headers { headers, finality }) => (headers, finality),
+                Ok(_) => {
+                    self.ban(peer, "wrong response type");
+                    failed = true;
+                    break;
+                }
+                Err(e) => {
+                    tracing::debug!("Header request to {} failed: {}", peer, e);
+                    failed = true;
+                    break;
+                }
+            };
+            if let Err(reason) = check_header_chain(&parent, &headers, batch) {
+                self.ban(peer, reason);
+                failed = true;
+                break;
+            }
+            if let Some(proof) = finality {
+                match self.check_finality(&headers, &proof) {
+                    Ok(()) => proven = Some(proof),
+                    Err(reason) => {
+                        self.ban(peer, reason);
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+            let complete = headers.len() == batch as usize;
+            if let Some(last) = headers.last() {
+                parent = last.clone();
+                accepted.push((peer, headers));
+            }
+            if !complete {
+                break;
+            }
+        }
+
+        // Unproven headers wait for a later proof
+        let finalized = proven.as_ref().map(|proof| proof.number);
+        for (_, headers) in &mut accepted {
+            headers.retain(|header| finalized.is_some_and(|number| header.number <= number));
+        }
+        accepted.retain(|(_, headers)| !headers.is_empty());
+        HeaderRound {
+            ranges: accepted,
+            finality: proven,
+            failed,
+        }
+    }
+
+    fn check_finality(
+        &self,
+        headers: &[BlockHeader],
+        proof: &FinalityProof,
+    ) -> Result<(), &'static str> {
+        let proven = headers
+            .iter()
+            .find(|header| header.number == proof.number)
+            .ok_or("finality proof for a header outside the range")?;
+        if proven.hash() != proof.block_hash {
+            return Err("finality proof for a different block");
+        }
+        proof
+            .verify(&self.validators)
+            .map_err(|_| "invalid finality proof")
+    }
+
+    /// Fetch bodies for `headers`, rotating failed batches to other peers
+    async fn download_bodies(
+        &mut self,
+        headers: Vec<BlockHeader>,
+        peers: &[PeerId],
+    ) -> SyncResult<Vec<Block>> {
+        let mut pending: VecDeque<BodyBatch> = headers
+            .chunks(self.config.body_batch.clamp(1, MAX_BODIES_PER_REQUEST))
+            .map(|chunk| BodyBatch {
+                headers: chunk.to_vec(),
+                tried: vec![],
+            })
+            .collect();
+        let mut bodies: HashMap<BlockHash, Vec<Transaction>> = HashMap::new();
+
+        while !pending.is_empty() {
+            let peers: Vec<_> = peers
+                .iter()
+                .filter(|peer| !self.banned.contains(peer))
+                .copied()
+                .collect();
+            if peers.is_empty() {
+                return Err(SyncError::NoPeers);
+            }
+
+            let wave = schedule(&mut pending, &peers, |batch| &batch.tried);
+            let transport = &self.transport;
+            let requests = wave.iter().map(|(peer, batch)| {
+                let hashes = batch.headers.iter().map(|h| h.hash()).collect();
+                transport.request(*peer, SyncRequest::Bodies(hashes))
+            });
+            let responses = join_all(requests).await;
+
+            for ((peer, mut batch), response) in wave.into_iter().zip(responses) {
+                let served = match response {
+                    Ok(SyncResponse::Bodies(served)) if served.len() <= batch.headers.len() => {
+                        served
+                    }
+                    Ok(_) => {
+                        self.ban(peer, "invalid bodies response");
+                        vec![]
+                    }
+                    Err(e) => {
+                        tracing::debug!("Body request to {} failed: {}", peer, e);
+                        vec![]
+                    }
+                };
+                let mut received = 0;
+                for (header, transactions) in batch.headers.iter().zip(served) {
+                    if transactions_root(&transactions) != header.transactions_root {
+                        self.ban(peer, "body does not match header");
+                        break;
+                    }
+                    bodies.insert(header.hash(), transactions);
+                    received += 1;
+                }
+
+                if received == batch.headers.len() {
+                    continue;
+                }
+                batch.headers.drain(..received);
+                if received == 0 {
+                    batch.tried.push(peer);
+                    if batch.tried.len() >= self.config.max_attempts {
+                        return Err(SyncError::BodiesUnavailable(batch.headers[0].number));
+                    }
+                } else {
+                    batch.tried.clear();
+                }
+                pending.push_back(batch);
+            }
+        }
+
+        Ok(headers
+            .into_iter()
+            .map(|header| {
+                let transactions = bodies.remove(&header.hash()).unwrap();
+                Block {
+                    header,
+                    transactions,
+                }
+            })
+            .collect())
+    }
+
+    fn ban(&mut self, peer: PeerId, reason: &str) {
+        tracing::warn!("Dropping sync peer {}: {}", peer, reason);
+        self.banned.insert(peer);
+        self.transport.penalize(peer);
+    }
+}
+
+/// Assign pending requests to distinct peers, leaving the rest in `pending`
+///
+/// A request only goes back to a peer it failed on once every other peer has
+/// failed it too.
+pub(super) fn schedule<R>(
+    pending: &mut VecDeque<R>,
+    peers: &[PeerId],
+    tried: impl Fn(&R) -> &[PeerId],
+) -> Vec<(PeerId, R)> {
+    let mut wave: Vec<(PeerId, R)> = vec![];
+    let mut waiting = VecDeque::new();
+    for request in pending.drain(..) {
+        let mut candidates: Vec<_> = peers
+            .iter()
+            .filter(|peer| !tried(&request).contains(peer))
+            .collect();
+        if candidates.is_empty() {
+            candidates = peers.iter().collect();
+        }
+        let peer = candidates
+            .into_iter()
+            .find(|peer| !wave.iter().any(|(busy, _)| busy == *peer));
+        match peer {
+            Some(peer) => wave.push((*peer, request)),
+            None => waiting.push_back(request),
+        }
+    }
+    *pending = waiting;
+    wave
+}
+
+/// Headers fetched in one round of requests
+struct HeaderRound {
+    /// Linked header ranges and the peer that served each
+    ranges: Vec<(PeerId, Vec<BlockHeader>)>,
+    /// Proof for the highest accepted header
+    finality: Option<FinalityProof>,
+    /// Whether a request failed before the end of the chain
+    failed: bool,
+}
+
+/// Headers whose bodies are still missing
+struct BodyBatch {
+    headers: Vec<BlockHeader>,
+    /// Peers that failed to serve any of them
+    tried: Vec<PeerId>,
+}
+
+/// Check that `headers` are consecutive, link onto `parent` and fit the batch
+fn check_header_chain(
+    parent: &BlockHeader,
+    headers: &[BlockHeader],
+    batch: u32,
+) -> Result<(), &'static str> {
+    if headers.len() > batch as usize {
+        return Err("too many headers");
+    }
+    let mut parent_hash = parent.hash();
+    let mut number = parent.number;
+    for header in headers {
+        if header.number != number + 1 {
+            return Err("non-consecutive header numbers");
+        }
+        if header.parent_hash != parent_hash {
+            return Err("header does not link to its parent");
+        }
+        parent_hash = header.hash();
+        number = header.number;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConsensusConfig, GenesisValidator};
+    use crate::consensus::{ConsensusEngine, HybridConsensus};
+    use crate::storage::{KeyValueStore, MemoryStore, StateManager};
+    use crate::test_utils::{
+        empty_block, finalize_head, peer, validator_keys, validator_set, LocalTransport,
+    };
+    use crate::vm::executor::TransactionExecutor;
+    use ed25519_dalek::SigningKey;
+    use std::sync::Arc;
+
+    /// Serves bodies that do not match their headers
+    struct Tampering(BlockStore);
+
+    impl SyncProvider for Tampering {
+        fn respond(&self, request: &SyncRequest) -> SyncResponse {
+            match self.0.respond(request) {
+                SyncResponse::Bodies(mut bodies) => {
+                    for body in &mut bodies {
+                        body.push(dummy_tx());
+                    }
+                    SyncResponse::Bodies(bodies)
+                }
+                response => response,
+            }
+        }
+    }
+
+    /// Attaches a finality proof for block `number` when it is in range
+    struct Finalizing {
+        blocks: BlockStore,
+        keys: Vec<SigningKey>,
+        number: BlockNumber,
+    }
+
+    impl SyncProvider for Finalizing {
+        fn respond(&self, request: &SyncRequest) -> SyncResponse {
+            match self.blocks.respond(request) {
+                SyncResponse::Headers { headers, .. } => {
+                    let finality = headers.iter().find(|h| h.number == self.number).map(|h| {
+                        let mut proof = FinalityProof::new(h.number, h.hash());
+                        for key in &self.keys {
+                            proof.sign(key);
+                        }
+                        proof
+                    });
+                    SyncResponse::Headers { headers, finality }
+                }
+                response => response,
+            }
+        }
+    }
+
+    fn dummy_tx() -> Transaction {
+        Transaction {
+            from: Address([1; 20]),
+            to: None,
+            value: Balance::ZERO,
+            data: vec![],
+            gas_limit: 21_000,
+            kind: TxKind::Legacy {
+                gas_price: Balance::ZERO,
+            },
+            nonce: 0,
+            paymaster: None,
+            authorization: Authorization::Contract { data: vec![] },
+        }
+    }
+
+    fn genesis_store() -> Arc<dyn KeyValueStore> {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut state = StateManager::new(store.clone());
+        let account = Account {
+            balance: Balance::from(1_000u64),
+            ..Default::default()
+        };
+        state.set_account(Address([1; 20]), account);
+        let root = state.commit(0).unwrap();
+        let importer = BlockImporter::new(store.clone(), TransactionExecutor::new());
+        importer.import_block(&empty_block(None, root)).unwrap();
+        store
+    }
+
+    /// Store holding a chain of `length` empty blocks after genesis
+    fn serving_store(length: u64) -> BlockStore {
+        let store = genesis_store();
+        let importer = BlockImporter::new(store.clone(), TransactionExecutor::new());
+        for _ in 0..length {
+            let head = importer.blocks().head().unwrap().unwrap();
+            importer
+                .import_block(&empty_block(Some(&head), head.state_root))
+                .unwrap();
+            finalize_head(importer.blocks());
+        }
+        BlockStore::new(store)
+    }
+
+    /// Store whose blocks after `valid` carry a wrong state root
+    fn invalid_store(valid: u64, length: u64) -> BlockStore {
+        let blocks = serving_store(valid);
+        for _ in valid..length {
+            let head = blocks.head().unwrap().unwrap();
+            let block = empty_block(Some(&head), BlockHash([0xee; 32]));
+            let hash = blocks.insert_block(&block, &[]).unwrap();
+            blocks.set_head(hash).unwrap();
+            finalize_head(&blocks);
+        }
+        blocks
+    }
+
+    fn small_batches() -> SyncConfig {
+        SyncConfig {
+            header_batch: 8,
+            body_batch: 4,
+            max_attempts: 3,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_rotates_away_from_bad_peers() {
+        let (good, bad, silent) = (peer(), peer(), peer());
+        let mut transport = LocalTransport::default();
+        transport
+            .providers
+            .insert(good, Arc::new(serving_store(30)));
+        transport
+            .providers
+            .insert(bad, Arc::new(Tampering(serving_store(30))));
+
+        let importer = BlockImporter::new(genesis_store(), TransactionExecutor::new());
+        let mut sync = ChainSync::new(transport, importer, validator_set(&validator_keys()))
+            .with_config(small_batches());
+        assert_eq!(sync.sync(&[good, bad, silent]).await.unwrap(), 30);
+        assert_eq!(sync.banned().iter().collect::<Vec<_>>(), vec![&bad]);
+        assert_eq!(*sync.transport.penalized.lock().unwrap(), vec![bad]);
+
+        // Nothing new to fetch: the head stays put
+        assert_eq!(sync.sync(&[good]).await.unwrap(), 30);
+
+        // The proofs are kept and served on
+        let blocks = sync.importer().blocks();
+        assert_eq!(blocks.finalized().unwrap(), Some(30));
+        assert!(matches!(
+            blocks.respond(&SyncRequest::Headers { start: 25, max: 8 }),
+            SyncResponse::Headers { finality: Some(proof), .. } if proof.number == 30
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sync_bans_peers_serving_invalid_blocks() {
+        let (bad, good) = (peer(), peer());
+        let mut transport = LocalTransport::default();
+        transport
+            .providers
+            .insert(bad, Arc::new(invalid_store(4, 30)));
+        let importer = BlockImporter::new(genesis_store(), TransactionExecutor::new());
+        let mut sync = ChainSync::new(transport, importer, validator_set(&validator_keys()))
+            .with_config(small_batches());
+
+        // The valid prefix is kept and the peer dropped at the first bad block
+        assert!(matches!(sync.sync(&[bad]).await, Err(SyncError::NoPeers)));
+        assert_eq!(sync.banned().iter().collect::<Vec<_>>(), vec![&bad]);
+        assert_eq!(sync.importer().blocks().head().unwrap().unwrap().number, 4);
+
+        sync.transport
+            .providers
+            .insert(good, Arc::new(serving_store(30)));
+        assert_eq!(sync.sync(&[bad, good]).await.unwrap(), 30);
+    }
+
+    #[tokio::test]
+    async fn test_sync_requires_finality() {
+        let keys = validator_keys();
+
+        // Proofs signed by too little stake are rejected
+        let (forger, honest) = (peer(), peer());
+        let mut transport = LocalTransport::default();
+        let forged = Finalizing {
+            blocks: serving_store(20),
+            keys: keys[..1].to_vec(),
+            number: 20,
+        };
+        transport.providers.insert(forger, Arc::new(forged));
+        let importer = BlockImporter::new(genesis_store(), TransactionExecutor::new());
+        let mut sync =
+            ChainSync::new(transport, importer, validator_set(&keys)).with_config(SyncConfig {
+                header_batch: 32,
+                ..small_batches()
+            });
+        assert!(matches!(
+            sync.sync(&[forger]).await,
+            Err(SyncError::NoPeers)
+        ));
+        assert!(sync.banned().contains(&forger));
+        assert_eq!(sync.importer().blocks().head().unwrap().unwrap().number, 0);
+
+        // Only headers up to the proven block are imported
+        let finalizing = Finalizing {
+            blocks: serving_store(20),
+            keys,
+            number: 12,
+        };
+        sync.transport
+            .providers
+            .insert(honest, Arc::new(finalizing));
+        assert_eq!(sync.sync(&[forger, honest]).await.unwrap(), 12);
+    }
+
+    #[tokio::test]
+    async fn test_sync_with_consensus_finality() {
+        let keys = validator_keys();
+        let store = genesis_store();
+        let config = ConsensusConfig {
+            min_validator_stake: Balance::from(100u64),
+            validators: keys
+                .iter()
+                .map(|key| GenesisValidator {
+                    public_key: PublicKey(key.verifying_key().to_bytes()),
+                    stake: Balance::from(100u64),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let consensus = HybridConsensus::new(config).with_store(store.clone());
+
+        // Blocks are proposed, imported and finalized once all validators voted
+        let importer = BlockImporter::new(store.clone(), TransactionExecutor::new());
+        for _ in 0..10 {
+            let head = importer.blocks().head().unwrap().unwrap();
+            let block = consensus.propose_block(&head, vec![]).await.unwrap();
+            importer.import_block(&block).unwrap();
+            if block.header.number > 7 {
+                continue;
+            }
+            let mut votes = FinalityProof::new(block.header.number, block.hash());
+            for key in &keys {
+                votes.sign(key);
+            }
+            consensus.add_votes(&votes).await.unwrap();
+            consensus.finalize_block(&block).await.unwrap();
+        }
+        assert_eq!(importer.blocks().finalized().unwrap(), Some(7));
+
+        let server = peer();
+        let mut transport = LocalTransport::default();
+        transport
+            .providers
+            .insert(server, Arc::new(BlockStore::new(store)));
+        let validators = consensus.get_validators().await.unwrap();
+        let importer = BlockImporter::new(genesis_store(), TransactionExecutor::new());
+        let mut sync = ChainSync::new(transport, importer, validators).with_config(small_batches());
+        assert_eq!(sync.sync(&[server]).await.unwrap(), 7);
+    }
+}
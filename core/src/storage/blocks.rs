@@ -0,0 +1,550 @@
+//! Block store
+//!
+//! Headers, bodies and receipts are stored by block hash for every block,
+//! canonical or not. The canonical chain is an index from block number to
+//! hash, and transactions are indexed by hash only while their block is
+//! canonical. The archive index maps canonical block numbers to state roots
+//! for historical state queries. Moving the head rewrites all three indexes
+//! in one batch.
+//!
+//! A chain restored from a state snapshot starts at a base block instead of
+//! genesis: nothing below the base is stored, and the base block has no
+//! receipts. The genesis hash is recorded separately, so that it is known
+//! either way.
+//!
+//! Finality proofs are kept by block number for the canonical blocks they
+//! finalize, so that syncing peers can check served headers against them.
+//! Finalized blocks are never retracted: a head that does not descend from
+//! the finalized block is refused.
+
+use super::{Column, KeyValueStore, StorageError, StorageResult, WriteBatch};
+use crate::consensus::finality::FinalityProof;
+use crate::types::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Metadata key holding the canonical head hash
+pub const CANONICAL_HEAD_KEY: &[u8] = b"canonical_head";
+
+/// Metadata key holding the number of the first stored canonical block
+pub const CHAIN_BASE_KEY: &[u8] = b"chain_base";
+
+/// Metadata key holding the highest finalized block number
+pub const FINALIZED_KEY: &[u8] = b"finalized";
+
+/// Metadata key holding the genesis block hash
+pub const GENESIS_KEY: &[u8] = b"genesis";
+
+/// Position of a transaction in the canonical chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxLocation {
+    /// Containing block
+    pub block_hash: BlockHash,
+    /// Number of the containing block
+    pub block_number: BlockNumber,
+    /// Index within the block
+    pub index: u32,
+}
+
+/// Blocks that left and joined the canonical chain when the head moved
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CanonicalUpdate {
+    /// Blocks no longer canonical, highest first
+    pub retracted: Vec<BlockHash>,
+    /// Blocks newly canonical, lowest first
+    pub enacted: Vec<BlockHash>,
+}
+
+/// Persistent block storage
+pub struct BlockStore {
+    store: Arc<dyn KeyValueStore>,
+}
+
+impl BlockStore {
+    /// Create a block store on top of a store
+    pub fn new(store: Arc<dyn KeyValueStore>) -> Self {
+        Self { store }
+    }
+
+    fn load<T: DeserializeOwned>(&self, column: Column, key: &[u8]) -> StorageResult<Option<T>> {
+        self.store
+            .get(column, key)?
+            .map(|bytes| {
+                bincode::deserialize(&bytes)
+                    .map_err(|e| StorageError::Corrupted(format!("{}: {}", column.name(), e)))
+            })
+            .transpose()
+    }
+
+    /// Store a block and its receipts (does not make it canonical)
+    pub fn insert_block(&self, block: &Block, receipts: &[Receipt]) -> StorageResult<BlockHash> {
+        let hash = block.hash();
+        let mut batch = WriteBatch::new();
+        batch.put(
+            Column::Headers,
+            hash.0,
+            bincode::serialize(&block.header).unwrap(),
+        );
+        batch.put(
+            Column::Bodies,
+            hash.0,
+            bincode::serialize(&block.transactions).unwrap(),
+        );
+        batch.put(
+            Column::Receipts,
+            hash.0,
+            bincode::serialize(receipts).unwrap(),
+        );
+        self.store.write(batch)?;
+        Ok(hash)
+    }
+
+    /// Check whether a block is stored
+    pub fn contains(&self, hash: &BlockHash) -> StorageResult<bool> {
+        self.store.contains(Column::Headers, &hash.0)
+    }
+
+    /// Get a header by block hash
+    pub fn header(&self, hash: &BlockHash) -> StorageResult<Option<BlockHeader>> {
+        self.load(Column::Headers, &hash.0)
+    }
+
+    /// Get a block by hash
+    pub fn block(&self, hash: &BlockHash) -> StorageResult<Option<Block>> {
+        let Some(header) = self.header(hash)? else {
+            return Ok(None);
+        };
+        let transactions = self
+            .load(Column::Bodies, &hash.0)?
+            .ok_or_else(|| StorageError::Corrupted(format!("Missing body of block {}", hash)))?;
+        Ok(Some(Block {
+            header,
+            transactions,
+        }))
+    }
+
+    /// Get the receipts of a block
+    pub fn receipts(&self, hash: &BlockHash) -> StorageResult<Option<Vec<Receipt>>> {
+        self.load(Column::Receipts, &hash.0)
+    }
+
+    /// Hash of the canonical block at `number`
+    pub fn canonical_hash(&self, number: BlockNumber) -> StorageResult<Option<BlockHash>> {
+        Ok(self
+            .store
+            .get(Column::Canonical, &number.to_be_bytes())?
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(BlockHash))
+    }
+
+    /// State root of the canonical block at `number` (archive index)
+    pub fn state_root(&self, number: BlockNumber) -> StorageResult<Option<BlockHash>> {
+        Ok(self
+            .store
+            .get(Column::StateRoots, &number.to_be_bytes())?
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(BlockHash))
+    }
+
+    /// Store a proof that the canonical block it names is final
+    pub fn insert_finality_proof(&self, proof: &FinalityProof) -> StorageResult<()> {
+        if self.canonical_hash(proof.number)? != Some(proof.block_hash) {
+            return Err(StorageError::UnknownBlock(proof.block_hash));
+        }
+        let mut batch = WriteBatch::new();
+        batch.put(
+            Column::Finality,
+            proof.number.to_be_bytes(),
+            bincode::serialize(proof).unwrap(),
+        );
+        if self.finalized()?.is_none_or(|number| number < proof.number) {
+            batch.put(Column::Metadata, FINALIZED_KEY, proof.number.to_be_bytes());
+        }
+        self.store.write(batch)
+    }
+
+    /// Finality proof of the canonical block at `number`
+    pub fn finality_proof(&self, number: BlockNumber) -> StorageResult<Option<FinalityProof>> {
+        self.load(Column::Finality, &number.to_be_bytes())
+    }
+
+    /// Highest block with a stored finality proof
+    pub fn finalized(&self) -> StorageResult<Option<BlockNumber>> {
+        Ok(self
+            .store
+            .get(Column::Metadata, FINALIZED_KEY)?
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(BlockNumber::from_be_bytes))
+    }
+
+    /// Hash of the chain's genesis block, even if it is not stored
+    pub fn genesis_hash(&self) -> StorageResult<Option<BlockHash>> {
+        Ok(self
+            .store
+            .get(Column::Metadata, GENESIS_KEY)?
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(BlockHash))
+    }
+
+    /// Canonical block at `number`
+    pub fn block_by_number(&self, number: BlockNumber) -> StorageResult<Option<Block>> {
+        match self.canonical_hash(number)? {
+            Some(hash) => self.block(&hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Hash of the canonical head
+    pub fn head_hash(&self) -> StorageResult<Option<BlockHash>> {
+        Ok(self
+            .store
+            .get(Column::Metadata, CANONICAL_HEAD_KEY)?
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(BlockHash))
+    }
+
+    /// Header of the canonical head
+    pub fn head(&self) -> StorageResult<Option<BlockHeader>> {
+        match self.head_hash()? {
+            Some(hash) => self.header(&hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Number of the first stored canonical block (0 unless the chain
+    /// started from a snapshot)
+    pub fn base(&self) -> StorageResult<BlockNumber> {
+        Ok(self
+            .store
+            .get(Column::Metadata, CHAIN_BASE_KEY)?
+            .and_then(|bytes| bytes.try_into().ok())
+            .map_or(0, BlockNumber::from_be_bytes))
+    }
+
+    /// Location of a transaction in the canonical chain
+    pub fn transaction_location(&self, hash: &TxHash) -> StorageResult<Option<TxLocation>> {
+        self.load(Column::TxIndex, &hash.0)
+    }
+
+    /// Canonical transaction and its location
+    pub fn transaction(&self, hash: &TxHash) -> StorageResult<Option<(Transaction, TxLocation)>> {
+        let Some(location) = self.transaction_location(hash)? else {
+            return Ok(None);
+        };
+        let transactions: Vec<Transaction> = self
+            .load(Column::Bodies, &location.block_hash.0)?
+            .unwrap_or_default();
+        Ok(transactions
+            .into_iter()
+            .nth(location.index as usize)
+            .map(|tx| (tx, location)))
+    }
+
+    /// Receipt of a canonical transaction
+    pub fn receipt(&self, hash: &TxHash) -> StorageResult<Option<Receipt>> {
+        let Some(location) = self.transaction_location(hash)? else {
+            return Ok(None);
+        };
+        Ok(self
+            .receipts(&location.block_hash)?
+            .and_then(|receipts| receipts.into_iter().nth(location.index as usize)))
+    }
+
+    /// Make `hash` the canonical head, rewriting the canonical and
+    /// transaction indexes back to the common ancestor
+    ///
+    /// Fails if that would retract the finalized block.
+    pub fn set_head(&self, hash: BlockHash) -> StorageResult<CanonicalUpdate> {
+        let mut update = CanonicalUpdate::default();
+        let mut enacted = Vec::new();
+
+        // Walk the new chain back to a canonical block
+        let head = self
+            .header(&hash)?
+            .ok_or(StorageError::UnknownBlock(hash))?;
+        let (mut current, mut header) = (hash, head.clone());
+        let ancestor = loop {
+            if self.canonical_hash(header.number)? == Some(current) {
+                break Some(header.number);
+            }
+            enacted.push((header.number, current, header.state_root));
+            if header.number == 0 {
+                break None;
+            }
+            current = header.parent_hash;
+            header = self
+                .header(&current)?
+                .ok_or(StorageError::UnknownBlock(current))?;
+        };
+        if let Some(finalized) = self.finalized()? {
+            if ancestor.is_none_or(|number| number < finalized) {
+                return Err(StorageError::FinalityConflict(hash));
+            }
+        }
+
+        let mut batch = WriteBatch::new();
+        let first_replaced = ancestor.map_or(0, |number| number + 1);
+        let old_head = self.head()?.map(|header| header.number);
+
+        if let Some(old_head) = old_head {
+            for number in (first_replaced..=old_head).rev() {
+                let Some(old) = self.canonical_hash(number)? else {
+                    continue;
+                };
+                for tx in self
+                    .block(&old)?
+                    .map(|b| b.transactions)
+                    .unwrap_or_default()
+                {
+                    batch.delete(Column::TxIndex, tx.hash().0);
+                }
+                if number > head.number {
+                    batch.delete(Column::Canonical, number.to_be_bytes());
+                    batch.delete(Column::StateRoots, number.to_be_bytes());
+                }
+                update.retracted.push(old);
+            }
+        }
+
+        for (number, block_hash, state_root) in enacted.into_iter().rev() {
+            if number == 0 {
+                batch.put(Column::Metadata, GENESIS_KEY, block_hash.0);
+            }
+            batch.put(Column::Canonical, number.to_be_bytes(), block_hash.0);
+            batch.put(Column::StateRoots, number.to_be_bytes(), state_root.0);
+            let transactions = self
+                .block(&block_hash)?
+                .map(|b| b.transactions)
+                .unwrap_or_default();
+            for (index, tx) in transactions.iter().enumerate() {
+                let location = TxLocation {
+                    block_hash,
+                    block_number: number,
+                    index: index as u32,
+                };
+                batch.put(
+                    Column::TxIndex,
+                    tx.hash().0,
+                    bincode::serialize(&location).unwrap(),
+                );
+            }
+            update.enacted.push(block_hash);
+        }
+
+        batch.put(Column::Metadata, CANONICAL_HEAD_KEY, hash.0);
+        self.store.write(batch)?;
+        Ok(update)
+    }
+
+    /// Start an empty chain at `block`, whose state was restored separately
+    ///
+    /// The block becomes the canonical head without its ancestors, and later
+    /// blocks are imported on top of it as on top of genesis. `genesis_hash`
+    /// names the chain it belongs to.
+    pub fn set_base(&self, block: &Block, genesis_hash: BlockHash) -> StorageResult<BlockHash> {
+        if let Some(head) = self.head_hash()? {
+            return Err(StorageError::Corrupted(format!(
+                "Cannot set a chain base below existing head {}",
+                head
+            )));
+        }
+        let hash = block.hash();
+        let number = block.header.number;
+        let mut batch = WriteBatch::new();
+        batch.put(
+            Column::Headers,
+            hash.0,
+            bincode::serialize(&block.header).unwrap(),
+        );
+        batch.put(
+            Column::Bodies,
+            hash.0,
+            bincode::serialize(&block.transactions).unwrap(),
+        );
+        batch.put(Column::Canonical, number.to_be_bytes(), hash.0);
+        batch.put(
+            Column::StateRoots,
+            number.to_be_bytes(),
+            block.header.state_root.0,
+        );
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let location = TxLocation {
+                block_hash: hash,
+                block_number: number,
+                index: index as u32,
+            };
+            batch.put(
+                Column::TxIndex,
+                tx.hash().0,
+                bincode::serialize(&location).unwrap(),
+            );
+        }
+        batch.put(Column::Metadata, CHAIN_BASE_KEY, number.to_be_bytes());
+        batch.put(Column::Metadata, CANONICAL_HEAD_KEY, hash.0);
+        batch.put(Column::Metadata, GENESIS_KEY, genesis_hash.0);
+        self.store.write(batch)?;
+        Ok(hash)
+    }
+}
+
+/// Schema 1 to 2: build the archive index from the canonical chain
+pub(crate) fn index_state_roots(
+    store: &dyn KeyValueStore,
+    batch: &mut WriteBatch,
+) -> StorageResult<()> {
+    for entry in store.iter_prefix(Column::Canonical, &[]) {
+        let (number, hash) = entry?;
+        let header = store
+            .get(Column::Headers, &hash)?
+            .ok_or_else(|| StorageError::Corrupted("Missing canonical header".to_string()))?;
+        let header: BlockHeader = bincode::deserialize(&header)
+            .map_err(|e| StorageError::Corrupted(format!("headers: {}", e)))?;
+        batch.put(Column::StateRoots, number, header.state_root.0);
+    }
+    Ok(())
+}
+
+/// Schema 2 to 3: record the genesis hash from the canonical chain
+pub(crate) fn record_genesis_hash(
+    store: &dyn KeyValueStore,
+    batch: &mut WriteBatch,
+) -> StorageResult<()> {
+    if let Some(hash) = store.get(Column::Canonical, &0u64.to_be_bytes())? {
+        batch.put(Column::Metadata, GENESIS_KEY, hash);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{migration, MemoryStore};
+
+    fn block(parent: &Block, fork: u8) -> Block {
+        let number = parent.header.number + 1;
+        let mut child = parent.clone();
+        child.header.number = number;
+        child.header.parent_hash = parent.hash();
+        child.header.extra_data = vec![fork];
+        child.transactions[0].nonce = number;
+        child.transactions[0].data = vec![fork];
+        child
+    }
+
+    fn genesis() -> Block {
+        let tx = Transaction {
+            from: Address([1; 20]),
+            to: Some(Address([2; 20])),
+            value: Balance::ZERO,
+            data: vec![],
+            gas_limit: 21_000,
+            kind: TxKind::Legacy {
+                gas_price: Balance::ZERO,
+            },
+            nonce: 0,
+            paymaster: None,
+            authorization: Authorization::Contract { data: vec![] },
+        };
+        Block {
+            header: BlockHeader {
+                number: 0,
+                parent_hash: BlockHash([0; 32]),
+                timestamp: 0,
+                state_root: BlockHash([0; 32]),
+                transactions_root: BlockHash([0; 32]),
+                receipts_root: BlockHash([0; 32]),
+                proposer: Address([0; 20]),
+                logs_bloom: Bloom::default(),
+                gas_limit: 0,
+                gas_used: 0,
+                base_fee_per_gas: Balance::ZERO,
+                extra_data: vec![],
+            },
+            transactions: vec![tx],
+        }
+    }
+
+    #[test]
+    fn test_lookups_and_reorg() {
+        let store = BlockStore::new(Arc::new(MemoryStore::new()));
+        let mut chain_a = vec![genesis()];
+        for _ in 0..3 {
+            chain_a.push(block(chain_a.last().unwrap(), 0));
+        }
+        let mut chain_b = vec![chain_a[1].clone()];
+        for _ in 0..3 {
+            chain_b.push(block(chain_b.last().unwrap(), 1));
+        }
+        for block in chain_a.iter().chain(&chain_b[1..]) {
+            store.insert_block(block, &[]).unwrap();
+        }
+
+        let update = store.set_head(chain_a[3].hash()).unwrap();
+        assert_eq!(update.enacted.len(), 4);
+        assert_eq!(store.genesis_hash().unwrap(), Some(chain_a[0].hash()));
+
+        // Databases from before the genesis key get it by migration
+        store.store.delete(Column::Metadata, GENESIS_KEY).unwrap();
+        assert_eq!(store.genesis_hash().unwrap(), None);
+        migration::migrate(&*store.store, migration::MIGRATIONS, 2, 3).unwrap();
+        assert_eq!(store.genesis_hash().unwrap(), Some(chain_a[0].hash()));
+        assert_eq!(
+            store.block_by_number(2).unwrap().unwrap().hash(),
+            chain_a[2].hash()
+        );
+
+        let tx = &chain_a[2].transactions[0];
+        let (found, location) = store.transaction(&tx.hash()).unwrap().unwrap();
+        assert_eq!(found.hash(), tx.hash());
+        assert_eq!((location.block_number, location.index), (2, 0));
+
+        // Longer fork from block 1 replaces blocks 2 and 3
+        let update = store.set_head(chain_b[3].hash()).unwrap();
+        assert_eq!(update.retracted, vec![chain_a[3].hash(), chain_a[2].hash()]);
+        assert_eq!(
+            update.enacted,
+            chain_b[1..].iter().map(Block::hash).collect::<Vec<_>>()
+        );
+        assert_eq!(store.canonical_hash(4).unwrap(), Some(chain_b[3].hash()));
+        assert!(store.transaction(&tx.hash()).unwrap().is_none());
+        assert!(store.block(&chain_a[2].hash()).unwrap().is_some());
+
+        // Back to the shorter chain drops the canonical entry at 4
+        store.set_head(chain_a[3].hash()).unwrap();
+        assert_eq!(store.canonical_hash(4).unwrap(), None);
+        assert_eq!(store.head().unwrap().unwrap().number, 3);
+        assert!(store.transaction(&tx.hash()).unwrap().is_some());
+        let fork_tx = chain_b[3].transactions[0].hash();
+        assert!(store.transaction(&fork_tx).unwrap().is_none());
+
+        // Only canonical blocks are finalized, and the highest one is tracked
+        let fork_proof = FinalityProof::new(2, chain_b[1].hash());
+        assert!(store.insert_finality_proof(&fork_proof).is_err());
+        assert_eq!(store.finalized().unwrap(), None);
+        for number in [2, 1] {
+            let proof = FinalityProof::new(number, chain_a[number as usize].hash());
+            store.insert_finality_proof(&proof).unwrap();
+        }
+        assert_eq!(store.finalized().unwrap(), Some(2));
+        let proof = store.finality_proof(1).unwrap().unwrap();
+        assert_eq!(proof.block_hash, chain_a[1].hash());
+        assert!(store.finality_proof(3).unwrap().is_none());
+
+        // The longer fork from block 1 would retract finalized block 2
+        assert!(matches!(
+            store.set_head(chain_b[3].hash()),
+            Err(StorageError::FinalityConflict(_))
+        ));
+        assert!(matches!(
+            store.set_head(chain_a[1].hash()),
+            Err(StorageError::FinalityConflict(_))
+        ));
+        assert_eq!(store.head_hash().unwrap(), Some(chain_a[3].hash()));
+        assert_eq!(store.canonical_hash(2).unwrap(), Some(chain_a[2].hash()));
+
+        // Extending the finalized chain is still allowed
+        let next = block(&chain_a[3], 0);
+        store.insert_block(&next, &[]).unwrap();
+        store.set_head(next.hash()).unwrap();
+    }
+}
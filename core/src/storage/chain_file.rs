@@ -0,0 +1,137 @@
+//! Portable chain export files
+//!
+//! A chain file starts with a magic tag and a format version, followed by a
+//! gzip stream of blocks. Each block is bincode-encoded and prefixed with
+//! its length as a big-endian u32. The file ends at a record boundary; a
+//! partial record means the file was truncated.
+
+use super::{StorageError, StorageResult};
+use crate::types::Block;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{ErrorKind, Read, Write};
+
+/// Tag at the start of every chain file
+pub const CHAIN_FILE_MAGIC: &[u8; 4] = b"QCHN";
+
+/// Chain file format version
+pub const CHAIN_FILE_VERSION: u32 = 1;
+
+/// Largest accepted encoded block
+pub const MAX_BLOCK_RECORD: u32 = 64 * 1024 * 1024;
+
+/// Writes blocks to a chain file
+pub struct ChainFileWriter<W: Write> {
+    encoder: GzEncoder<W>,
+    blocks: u64,
+}
+
+impl<W: Write> ChainFileWriter<W> {
+    /// Write the file header and start the block stream
+    pub fn new(mut writer: W) -> StorageResult<Self> {
+        writer.write_all(CHAIN_FILE_MAGIC)?;
+        writer.write_all(&CHAIN_FILE_VERSION.to_be_bytes())?;
+        Ok(Self {
+            encoder: GzEncoder::new(writer, Compression::default()),
+            blocks: 0,
+        })
+    }
+
+    /// Append a block
+    pub fn write_block(&mut self, block: &Block) -> StorageResult<()> {
+        let record = bincode::serialize(block).unwrap();
+        let length = u32::try_from(record.len())
+            .ok()
+            .filter(|length| *length <= MAX_BLOCK_RECORD)
+            .ok_or_else(|| {
+                StorageError::InvalidChainFile(format!("Block {} is too large", block.header.number))
+            })?;
+        self.encoder.write_all(&length.to_be_bytes())?;
+        self.encoder.write_all(&record)?;
+        self.blocks += 1;
+        Ok(())
+    }
+
+    /// Number of blocks written
+    pub fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// Finish the compressed stream and return the underlying writer
+    pub fn finish(self) -> StorageResult<W> {
+        Ok(self.encoder.finish()?)
+    }
+}
+
+/// Reads blocks from a chain file
+pub struct ChainFileReader<R: Read> {
+    decoder: GzDecoder<R>,
+}
+
+impl<R: Read> ChainFileReader<R> {
+    /// Read and check the file header
+    pub fn new(mut reader: R) -> StorageResult<Self> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => StorageError::InvalidChainFile("Missing header".to_string()),
+            _ => StorageError::Io(e),
+        })?;
+        if &header[..4] != CHAIN_FILE_MAGIC {
+            return Err(StorageError::InvalidChainFile("Not a chain file".to_string()));
+        }
+        let version = u32::from_be_bytes(header[4..].try_into().unwrap());
+        if version != CHAIN_FILE_VERSION {
+            return Err(StorageError::InvalidChainFile(format!(
+                "Unsupported version {} (expected {})",
+                version, CHAIN_FILE_VERSION
+            )));
+        }
+        Ok(Self {
+            decoder: GzDecoder::new(reader),
+        })
+    }
+
+    /// Read the next block (None at the end of the file)
+    pub fn read_block(&mut self) -> StorageResult<Option<Block>> {
+        let mut prefix = [0u8; 4];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            match self.decoder.read(&mut prefix[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(truncated()),
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(StorageError::Io(e)),
+            }
+        }
+
+        let length = u32::from_be_bytes(prefix);
+        if length > MAX_BLOCK_RECORD {
+            return Err(StorageError::InvalidChainFile(format!(
+                "Record of {} bytes exceeds the limit",
+                length
+            )));
+        }
+        let mut record = vec![0u8; length as usize];
+        self.decoder.read_exact(&mut record).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => truncated(),
+            _ => StorageError::Io(e),
+        })?;
+        bincode::deserialize(&record)
+            .map(Some)
+            .map_err(|e| StorageError::InvalidChainFile(format!("Undecodable block: {}", e)))
+    }
+}
+
+impl<R: Read> Iterator for ChainFileReader<R> {
+    type Item = StorageResult<Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_block().transpose()
+    }
+}
+
+fn truncated() -> StorageError {
+    StorageError::InvalidChainFile("Truncated block record".to_string())
+}
@@ -0,0 +1,410 @@
+//! Database integrity check
+//!
+//! Walks the canonical chain from genesis (or the base block of a chain
+//! restored from a snapshot) to the head and reports what is
+//! missing or inconsistent: broken header links, absent bodies or receipts,
+//! transaction index entries that are missing or point outside the canonical
+//! chain, archive index entries that disagree with headers, state that
+//! should be retained but cannot be read, and contract code
+//! missing from the code store.
+
+use super::{
+    has_state, BlockStore, Column, KeyValueStore, PruningMode, StorageError, StorageResult, Trie,
+    TxLocation,
+};
+use crate::types::*;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Inconsistency found by [`check_integrity`]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue {
+    #[error("Canonical head is missing or not at the top of the canonical index")]
+    InvalidHead,
+
+    #[error("No canonical block at {0}")]
+    MissingCanonical(BlockNumber),
+
+    #[error("Block {number} ({hash}) has no {part}")]
+    MissingBlockData {
+        number: BlockNumber,
+        hash: BlockHash,
+        part: &'static str,
+    },
+
+    #[error("Block {0} does not extend the canonical block below it")]
+    BrokenChain(BlockNumber),
+
+    #[error("Block {0} has a receipt count different from its transaction count")]
+    ReceiptCount(BlockNumber),
+
+    #[error("Transaction {tx} of block {number} is missing from the transaction index")]
+    MissingTxIndex { tx: TxHash, number: BlockNumber },
+
+    #[error("Transaction index entry {0} points outside the canonical chain")]
+    StaleTxIndex(TxHash),
+
+    #[error("Archive index entry of block {0} is missing or wrong")]
+    StateRootIndex(BlockNumber),
+
+    #[error("State {root} of block {number} is not available")]
+    MissingState {
+        number: BlockNumber,
+        root: BlockHash,
+    },
+
+    #[error("Head state {root} is incomplete: {reason}")]
+    IncompleteState { root: BlockHash, reason: String },
+
+    #[error("Code {code_hash} of account {address} is missing")]
+    MissingCode {
+        address: Address,
+        code_hash: BlockHash,
+    },
+}
+
+/// Outcome of an integrity check
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    /// Canonical head number (None for an empty chain)
+    pub head: Option<BlockNumber>,
+    /// Canonical blocks checked
+    pub blocks: u64,
+    /// Accounts in the head state
+    pub accounts: u64,
+    /// Storage slots in the head state
+    pub storage_slots: u64,
+    /// Problems found
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    /// Check whether no problems were found
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Check the block store, indexes and retained state of `store`
+///
+/// `pruning` decides which historical states must still be readable.
+pub fn check_integrity(
+    store: Arc<dyn KeyValueStore>,
+    pruning: PruningMode,
+) -> StorageResult<IntegrityReport> {
+    let blocks = BlockStore::new(store.clone());
+    let mut report = IntegrityReport::default();
+
+    let Some(head_hash) = blocks.head_hash()? else {
+        return Ok(report);
+    };
+    let Some(head) = blocks.header(&head_hash)? else {
+        report.issues.push(IntegrityIssue::InvalidHead);
+        return Ok(report);
+    };
+    if blocks.canonical_hash(head.number)? != Some(head_hash)
+        || blocks.canonical_hash(head.number + 1)?.is_some()
+    {
+        report.issues.push(IntegrityIssue::InvalidHead);
+    }
+    report.head = Some(head.number);
+
+    let base = blocks.base()?;
+    let mut checker = BlockChecker {
+        store: &*store,
+        blocks: &blocks,
+        base,
+        retained_from: match pruning {
+            PruningMode::Archive => 0,
+            PruningMode::KeepRecent(history) => (head.number + 1).saturating_sub(history),
+        },
+        report: &mut report,
+    };
+    let mut parent = None;
+    for number in base..=head.number {
+        parent = match blocks.canonical_hash(number)? {
+            Some(hash) => checker.check(number, hash, parent)?,
+            None => {
+                checker
+                    .report
+                    .issues
+                    .push(IntegrityIssue::MissingCanonical(number));
+                None
+            }
+        };
+    }
+
+    // Every index entry must point at its canonical position
+    for entry in store.iter_prefix(Column::TxIndex, &[]) {
+        let (key, value) = entry?;
+        let tx =
+            TxHash(key.as_slice().try_into().map_err(|_| {
+                StorageError::Corrupted("Invalid transaction index key".to_string())
+            })?);
+        let canonical = match bincode::deserialize::<TxLocation>(&value) {
+            Ok(location) => {
+                blocks.canonical_hash(location.block_number)? == Some(location.block_hash)
+            }
+            Err(_) => false,
+        };
+        if !canonical {
+            report.issues.push(IntegrityIssue::StaleTxIndex(tx));
+        }
+    }
+
+    check_head_state(&*store, head.state_root, &mut report)?;
+    Ok(report)
+}
+
+/// Checks canonical blocks one at a time
+struct BlockChecker<'a> {
+    store: &'a dyn KeyValueStore,
+    blocks: &'a BlockStore,
+    /// First stored canonical block
+    base: BlockNumber,
+    /// First block whose state must be readable
+    retained_from: BlockNumber,
+    report: &'a mut IntegrityReport,
+}
+
+impl BlockChecker<'_> {
+    /// Check the canonical block at `number`, returning its hash if it can
+    /// serve as the parent link of the next one
+    fn check(
+        &mut self,
+        number: BlockNumber,
+        hash: BlockHash,
+        parent: Option<BlockHash>,
+    ) -> StorageResult<Option<BlockHash>> {
+        let missing = |part| IntegrityIssue::MissingBlockData { number, hash, part };
+        self.report.blocks += 1;
+
+        let Some(header) = self.blocks.header(&hash)? else {
+            self.report.issues.push(missing("header"));
+            return Ok(None);
+        };
+        let links = header.number == number
+            && match parent {
+                Some(parent) => header.parent_hash == parent,
+                None => number == 0,
+            };
+        if !links && (number == 0 || parent.is_some()) {
+            self.report.issues.push(IntegrityIssue::BrokenChain(number));
+        }
+
+        let root = header.state_root;
+        if self.blocks.state_root(number)? != Some(root) {
+            self.report
+                .issues
+                .push(IntegrityIssue::StateRootIndex(number));
+        }
+        if number >= self.retained_from && !has_state(self.store, &root)? {
+            self.report
+                .issues
+                .push(IntegrityIssue::MissingState { number, root });
+        }
+
+        let Some(block) = self.blocks.block(&hash).or_else(|e| match e {
+            StorageError::Corrupted(_) => Ok(None),
+            e => Err(e),
+        })?
+        else {
+            self.report.issues.push(missing("body"));
+            return Ok(Some(hash));
+        };
+        match self.blocks.receipts(&hash)? {
+            // The base of a snapshot-restored chain is stored without receipts
+            None if number == self.base && number > 0 => {}
+            None => self.report.issues.push(missing("receipts")),
+            Some(receipts) if receipts.len() != block.transactions.len() => {
+                self.report
+                    .issues
+                    .push(IntegrityIssue::ReceiptCount(number));
+            }
+            Some(_) => {}
+        }
+
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let expected = TxLocation {
+                block_hash: hash,
+                block_number: number,
+                index: index as u32,
+            };
+            let tx = tx.hash();
+            if self.blocks.transaction_location(&tx).ok().flatten() != Some(expected) {
+                self.report
+                    .issues
+                    .push(IntegrityIssue::MissingTxIndex { tx, number });
+            }
+        }
+        Ok(Some(hash))
+    }
+}
+
+/// Read every account, storage slot and code of the head state
+fn check_head_state(
+    store: &dyn KeyValueStore,
+    root: BlockHash,
+    report: &mut IntegrityReport,
+) -> StorageResult<()> {
+    let incomplete = |reason: String| IntegrityIssue::IncompleteState { root, reason };
+
+    for entry in Trie::new(store, root).iter() {
+        let decoded = entry.and_then(|(key, value)| {
+            let address = key
+                .try_into()
+                .map(Address)
+                .map_err(|_| StorageError::Corrupted("Invalid account key".to_string()))?;
+            let account: Account = bincode::deserialize(&value)
+                .map_err(|e| StorageError::Corrupted(format!("Account {}: {}", address, e)))?;
+            Ok((address, account))
+        });
+        let (address, account) = match decoded {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                report.issues.push(incomplete(e.to_string()));
+                return Ok(());
+            }
+        };
+        report.accounts += 1;
+
+        if let Some(code_hash) = account.code_hash {
+            if !store.contains(Column::Code, &code_hash.0)? {
+                report
+                    .issues
+                    .push(IntegrityIssue::MissingCode { address, code_hash });
+            }
+        }
+        for slot in Trie::new(store, account.storage_root).iter() {
+            if let Err(e) = slot {
+                report.issues.push(incomplete(e.to_string()));
+                return Ok(());
+            }
+            report.storage_slots += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStore, StateManager, WriteBatch};
+
+    fn chain(store: &Arc<dyn KeyValueStore>, state_root: BlockHash) -> Vec<Block> {
+        let blocks = BlockStore::new(store.clone());
+        let mut chain: Vec<Block> = Vec::new();
+        for number in 0..3u64 {
+            let tx = Transaction {
+                from: Address([1; 20]),
+                to: Some(Address([2; 20])),
+                value: Balance::ZERO,
+                data: vec![],
+                gas_limit: 21_000,
+                kind: TxKind::Legacy {
+                    gas_price: Balance::ZERO,
+                },
+                nonce: number,
+                paymaster: None,
+                authorization: Authorization::Contract { data: vec![] },
+            };
+            let receipt = Receipt {
+                tx_hash: tx.hash(),
+                block_number: number,
+                gas_used: 21_000,
+                success: true,
+                logs: vec![],
+                logs_bloom: Bloom::default(),
+                contract_address: None,
+            };
+            let block = Block {
+                header: BlockHeader {
+                    number,
+                    parent_hash: chain.last().map_or(BlockHash([0; 32]), Block::hash),
+                    timestamp: number,
+                    state_root,
+                    transactions_root: BlockHash([0; 32]),
+                    receipts_root: BlockHash([0; 32]),
+                    proposer: Address([0; 20]),
+                    logs_bloom: Bloom::default(),
+                    gas_limit: 0,
+                    gas_used: 0,
+                    base_fee_per_gas: Balance::ZERO,
+                    extra_data: vec![],
+                },
+                transactions: vec![tx],
+            };
+            blocks.insert_block(&block, &[receipt]).unwrap();
+            chain.push(block);
+        }
+        blocks.set_head(chain[2].hash()).unwrap();
+        chain
+    }
+
+    #[test]
+    fn test_detects_inconsistencies() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut state = StateManager::new(store.clone());
+        let code_hash = state.insert_code(b"code".to_vec());
+        for i in 0..10u8 {
+            let account = Account {
+                code_hash: (i == 0).then_some(code_hash),
+                ..Default::default()
+            };
+            state.set_account(Address([i; 20]), account);
+            state.set_storage(Address([i; 20]), vec![i], vec![i]);
+        }
+        let root = state.commit(0).unwrap();
+        let chain = chain(&store, root);
+
+        let report = check_integrity(store.clone(), PruningMode::Archive).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!((report.head, report.blocks), (Some(2), 3));
+        assert_eq!((report.accounts, report.storage_slots), (10, 10));
+
+        store.delete(Column::Code, &code_hash.0).unwrap();
+        let report = check_integrity(store.clone(), PruningMode::Archive).unwrap();
+        let missing_code = IntegrityIssue::MissingCode {
+            address: Address([0; 20]),
+            code_hash,
+        };
+        assert_eq!(report.issues, vec![missing_code]);
+
+        // Drop an index entry, leave a stale one and lose a state node
+        let tx = chain[1].transactions[0].hash();
+        let stale = TxHash([7; 32]);
+        let location = TxLocation {
+            block_hash: BlockHash([7; 32]),
+            block_number: 1,
+            index: 0,
+        };
+        let node = store
+            .iter_prefix(Column::StateNodes, &[])
+            .map(Result::unwrap)
+            .find(|(key, _)| key.as_slice() != root.0)
+            .unwrap()
+            .0;
+        let mut batch = WriteBatch::new();
+        batch.delete(Column::TxIndex, tx.0);
+        batch.put(
+            Column::TxIndex,
+            stale.0,
+            bincode::serialize(&location).unwrap(),
+        );
+        batch.delete(Column::StateNodes, node);
+        batch.delete(Column::Receipts, chain[2].hash().0);
+        store.write(batch).unwrap();
+
+        let issues = check_integrity(store, PruningMode::Archive).unwrap().issues;
+        assert!(issues.contains(&IntegrityIssue::MissingTxIndex { tx, number: 1 }));
+        assert!(issues.contains(&IntegrityIssue::StaleTxIndex(stale)));
+        assert!(issues.contains(&IntegrityIssue::MissingBlockData {
+            number: 2,
+            hash: chain[2].hash(),
+            part: "receipts",
+        }));
+        assert!(issues
+            .iter()
+            .any(|issue| matches!(issue, IntegrityIssue::IncompleteState { .. })));
+    }
+}
@@ -1,12 +1,351 @@
 //! Database layer (RocksDB)
 
+use super::{migration, StorageError, StorageResult};
+use crate::config::StorageConfig;
+use rocksdb::{
+    BlockBasedOptions, Cache, ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode,
+    Options, Snapshot, DB,
+};
+
+/// On-disk layout version written by this build
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Metadata key holding the schema version (big-endian u32)
+pub const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+const MIB: usize = 1024 * 1024;
+
+/// Column families
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Column {
+    /// Block headers by block hash
+    Headers,
+    /// Block bodies (transactions) by block hash
+    Bodies,
+    /// Receipts by block hash
+    Receipts,
+    /// State trie nodes by node hash
+    StateNodes,
+    /// Transaction location by transaction hash
+    TxIndex,
+    /// Chain metadata (schema version, heads, ...)
+    Metadata,
+    /// Log index entries by address/topic and section
+    LogIndex,
+    /// State trie node reference counts by node hash
+    StateRefs,
+    /// State trie nodes dropped by each block, by block number and hash
+    StateJournal,
+    /// Canonical block hash by block number (big-endian)
+    Canonical,
+    /// Contract code by code hash
+    Code,
+    /// State root of each canonical block by block number (big-endian)
+    StateRoots,
+    /// Finality proofs of canonical blocks by block number (big-endian)
+    Finality,
+}
+
+impl Column {
+    /// All column families, in creation order
+    pub const ALL: [Column; 13] = [
+        Column::Headers,
+        Column::Bodies,
+        Column::Receipts,
+        Column::StateNodes,
+        Column::TxIndex,
+        Column::Metadata,
+        Column::LogIndex,
+        Column::StateRefs,
+        Column::StateJournal,
+        Column::Canonical,
+        Column::Code,
+        Column::StateRoots,
+        Column::Finality,
+    ];
+
+    /// Column family name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Headers => "headers",
+            Column::Bodies => "bodies",
+            Column::Receipts => "receipts",
+            Column::StateNodes => "state_nodes",
+            Column::TxIndex => "tx_index",
+            Column::Metadata => "metadata",
+            Column::LogIndex => "log_index",
+            Column::StateRefs => "state_refs",
+            Column::StateJournal => "state_journal",
+            Column::Canonical => "canonical",
+            Column::Code => "code",
+            Column::StateRoots => "state_roots",
+            Column::Finality => "finality",
+        }
+    }
+}
+
+/// Single write in a batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp {
+    /// Insert or overwrite a key
+    Put {
+        /// Target column
+        column: Column,
+        /// Key
+        key: Vec<u8>,
+        /// Value
+        value: Vec<u8>,
+    },
+    /// Remove a key
+    Delete {
+        /// Target column
+        column: Column,
+        /// Key
+        key: Vec<u8>,
+    },
+}
+
+/// Set of writes applied atomically
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    /// Create empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue an insert
+    pub fn put(&mut self, column: Column, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) {
+        self.ops.push(BatchOp::Put {
+            column,
+            key: key.into(),
+            value: value.into(),
+        });
+    }
+
+    /// Queue a removal
+    pub fn delete(&mut self, column: Column, key: impl Into<Vec<u8>>) {
+        self.ops.push(BatchOp::Delete {
+            column,
+            key: key.into(),
+        });
+    }
+
+    /// Queued writes, in order
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    /// Number of queued writes
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Check whether the batch is empty
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
 /// Database manager
 pub struct Database {
-    // TODO: Implement RocksDB wrapper
+    db: DB,
 }
 
 impl Database {
-    pub fn new(_path: &str) -> Self {
-        Self {}
+    /// Open (or create) the database at `config.db_path`
+    ///
+    /// Databases written with an older schema version are migrated in place.
+    /// Fails if the directory was written by a newer version.
+    pub fn open(config: &StorageConfig) -> StorageResult<Self> {
+        let database = Self::open_unchecked(config)?;
+        database.upgrade_schema()?;
+        Ok(database)
+    }
+
+    /// Open without checking or upgrading the schema version (for inspection)
+    pub fn open_unchecked(config: &StorageConfig) -> StorageResult<Self> {
+        let cache = Cache::new_lru_cache(config.block_cache_size_mb * MIB);
+        let mut table_options = BlockBasedOptions::default();
+        table_options.set_block_cache(&cache);
+
+        let mut column_options = Options::default();
+        column_options.set_write_buffer_size(config.write_buffer_size_mb * MIB);
+        column_options.set_block_based_table_factory(&table_options);
+
+        let mut db_options = Options::default();
+        db_options.create_if_missing(true);
+        db_options.create_missing_column_families(true);
+
+        let descriptors = Column::ALL
+            .iter()
+            .map(|column| ColumnFamilyDescriptor::new(column.name(), column_options.clone()));
+        let db = DB::open_cf_descriptors(&db_options, &config.db_path, descriptors)?;
+        Ok(Self { db })
+    }
+
+    fn cf(&self, column: Column) -> &ColumnFamily {
+        self.db
+            .cf_handle(column.name())
+            .expect("all column families are created at open")
+    }
+
+    /// Get a value
+    pub fn get(&self, column: Column, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        Ok(self.db.get_cf(self.cf(column), key)?)
+    }
+
+    /// Insert or overwrite a single value
+    pub fn put(&self, column: Column, key: &[u8], value: &[u8]) -> StorageResult<()> {
+        Ok(self.db.put_cf(self.cf(column), key, value)?)
+    }
+
+    /// Remove a single value
+    pub fn delete(&self, column: Column, key: &[u8]) -> StorageResult<()> {
+        Ok(self.db.delete_cf(self.cf(column), key)?)
+    }
+
+    /// Apply a batch atomically
+    pub fn write(&self, batch: WriteBatch) -> StorageResult<()> {
+        let mut rocks_batch = rocksdb::WriteBatch::default();
+        for op in batch.ops {
+            match op {
+                BatchOp::Put { column, key, value } => rocks_batch.put_cf(self.cf(column), key, value),
+                BatchOp::Delete { column, key } => rocks_batch.delete_cf(self.cf(column), key),
+            }
+        }
+        Ok(self.db.write(rocks_batch)?)
+    }
+
+    /// Iterate over all entries whose key starts with `prefix`, in key order
+    pub fn iter_prefix<'a>(
+        &'a self,
+        column: Column,
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = StorageResult<(Vec<u8>, Vec<u8>)>> + 'a {
+        self.db
+            .iterator_cf(self.cf(column), IteratorMode::From(prefix, Direction::Forward))
+            .map(|item| {
+                item.map(|(key, value)| (key.into_vec(), value.into_vec()))
+                    .map_err(StorageError::from)
+            })
+            .take_while(move |item| match item {
+                Ok((key, _)) => key.starts_with(prefix),
+                Err(_) => true,
+            })
+    }
+
+    /// Consistent read-only view unaffected by later writes
+    pub fn snapshot(&self) -> DatabaseSnapshot<'_> {
+        DatabaseSnapshot {
+            database: self,
+            snapshot: self.db.snapshot(),
+        }
+    }
+
+    /// Schema version stored in the database (None for a fresh database)
+    pub fn schema_version(&self) -> StorageResult<Option<u32>> {
+        match self.get(Column::Metadata, SCHEMA_VERSION_KEY)? {
+            None => Ok(None),
+            Some(bytes) => {
+                let bytes: [u8; 4] = bytes
+                    .try_into()
+                    .map_err(|_| StorageError::Corrupted("Invalid schema version".to_string()))?;
+                Ok(Some(u32::from_be_bytes(bytes)))
+            }
+        }
+    }
+
+    fn upgrade_schema(&self) -> StorageResult<()> {
+        match self.schema_version()? {
+            None => self.put(Column::Metadata, SCHEMA_VERSION_KEY, &SCHEMA_VERSION.to_be_bytes()),
+            Some(found) => {
+                migration::migrate(self, migration::MIGRATIONS, found, SCHEMA_VERSION)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Point-in-time view of a [`Database`]
+pub struct DatabaseSnapshot<'a> {
+    database: &'a Database,
+    snapshot: Snapshot<'a>,
+}
+
+impl DatabaseSnapshot<'_> {
+    /// Get a value as of the snapshot
+    pub fn get(&self, column: Column, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        Ok(self.snapshot.get_cf(self.database.cf(column), key)?)
+    }
+
+    /// Iterate over entries whose key starts with `prefix`, as of the snapshot
+    pub fn iter_prefix<'b>(
+        &'b self,
+        column: Column,
+        prefix: &'b [u8],
+    ) -> impl Iterator<Item = StorageResult<(Vec<u8>, Vec<u8>)>> + 'b {
+        self.snapshot
+            .iterator_cf(self.database.cf(column), IteratorMode::From(prefix, Direction::Forward))
+            .map(|item| {
+                item.map(|(key, value)| (key.into_vec(), value.into_vec()))
+                    .map_err(StorageError::from)
+            })
+            .take_while(move |item| match item {
+                Ok((key, _)) => key.starts_with(prefix),
+                Err(_) => true,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_config(name: &str) -> StorageConfig {
+        let path = std::env::temp_dir().join(format!("quantumchain-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        StorageConfig {
+            db_path: path.to_string_lossy().into_owned(),
+            block_cache_size_mb: 8,
+            write_buffer_size_mb: 4,
+            ..StorageConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_batch_and_prefix_iteration() {
+        let db = Database::open(&temp_config("db-batch")).unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put(Column::Headers, b"a1".to_vec(), b"x".to_vec());
+        batch.put(Column::Headers, b"a2".to_vec(), b"y".to_vec());
+        batch.put(Column::Headers, b"b1".to_vec(), b"z".to_vec());
+        batch.put(Column::Bodies, b"a3".to_vec(), b"w".to_vec());
+        batch.delete(Column::Headers, b"a2".to_vec());
+        db.write(batch).unwrap();
+
+        let entries: Vec<_> = db.iter_prefix(Column::Headers, b"a").map(Result::unwrap).collect();
+        assert_eq!(entries, vec![(b"a1".to_vec(), b"x".to_vec())]);
+        assert_eq!(db.get(Column::Bodies, b"a3").unwrap(), Some(b"w".to_vec()));
+    }
+
+    #[test]
+    fn test_schema_version_checked_at_open() {
+        let config = temp_config("db-schema");
+        {
+            let db = Database::open(&config).unwrap();
+            assert_eq!(db.schema_version().unwrap(), Some(SCHEMA_VERSION));
+            db.put(Column::Metadata, SCHEMA_VERSION_KEY, &(SCHEMA_VERSION + 1).to_be_bytes())
+                .unwrap();
+        }
+
+        assert!(matches!(
+            Database::open(&config),
+            Err(StorageError::SchemaVersion { .. })
+        ));
     }
 }
@@ -0,0 +1,150 @@
+//! Historical state queries
+//!
+//! The archive index ([`Column::StateRoots`](super::Column::StateRoots))
+//! maps each canonical block number to its state root, so a query at a past
+//! height reads the retained trie directly instead of replaying the chain.
+//! Heights older than the pruning window fail with
+//! [`StorageError::MissingState`]; archive nodes can answer for any height.
+
+use super::{BlockStore, KeyValueStore, StateView, StorageError, StorageResult};
+use crate::types::*;
+use std::sync::Arc;
+
+/// State queries at canonical block heights
+pub struct StateHistory {
+    store: Arc<dyn KeyValueStore>,
+    blocks: BlockStore,
+}
+
+impl StateHistory {
+    /// Create a query interface on top of a store
+    pub fn new(store: Arc<dyn KeyValueStore>) -> Self {
+        Self {
+            blocks: BlockStore::new(store.clone()),
+            store,
+        }
+    }
+
+    /// State after canonical block `number`
+    pub fn at_block(&self, number: BlockNumber) -> StorageResult<StateView> {
+        let root = self
+            .blocks
+            .state_root(number)?
+            .ok_or(StorageError::UnknownBlockNumber(number))?;
+        StateView::new(self.store.clone(), root)
+    }
+
+    /// Account after canonical block `number`
+    pub fn account_at(
+        &self,
+        address: &Address,
+        number: BlockNumber,
+    ) -> StorageResult<Option<Account>> {
+        self.at_block(number)?.get_account(address)
+    }
+
+    /// Balance after canonical block `number`
+    pub fn balance_at(&self, address: &Address, number: BlockNumber) -> StorageResult<Balance> {
+        self.at_block(number)?.balance(address)
+    }
+
+    /// Contract storage value after canonical block `number`
+    pub fn storage_at(
+        &self,
+        address: &Address,
+        key: &[u8],
+        number: BlockNumber,
+    ) -> StorageResult<Option<Vec<u8>>> {
+        self.at_block(number)?.get_storage(address, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{migration, Column, MemoryStore, PruningMode, StateManager, WriteBatch};
+
+    fn build_chain(store: Arc<dyn KeyValueStore>, pruning: PruningMode, length: u64) {
+        let blocks = BlockStore::new(store.clone());
+        let mut state = StateManager::new(store).with_pruning(pruning);
+        let mut parent_hash = BlockHash([0; 32]);
+
+        for number in 0..length {
+            let account = Account {
+                balance: Balance::from(100 * number),
+                ..Default::default()
+            };
+            state.set_account(Address([1; 20]), account);
+            state.set_storage(Address([1; 20]), b"height".to_vec(), number.to_be_bytes().to_vec());
+            let (root, changes) = state.prepare_commit().unwrap();
+            let block = Block {
+                header: BlockHeader {
+                    number,
+                    parent_hash,
+                    timestamp: number,
+                    state_root: root,
+                    transactions_root: BlockHash([0; 32]),
+                    receipts_root: BlockHash([0; 32]),
+                    proposer: Address([0; 20]),
+                    logs_bloom: Bloom::default(),
+                    gas_limit: 0,
+                    gas_used: 0,
+                    base_fee_per_gas: Balance::ZERO,
+                    extra_data: vec![],
+                },
+                transactions: vec![],
+            };
+            state.write_commit((number, block.hash()), root, changes).unwrap();
+            parent_hash = blocks.insert_block(&block, &[]).unwrap();
+            blocks.set_head(parent_hash).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_queries_at_height() {
+        let alice = Address([1; 20]);
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        build_chain(store.clone(), PruningMode::Archive, 6);
+        let history = StateHistory::new(store.clone());
+
+        for number in 0..6u64 {
+            assert_eq!(
+                history.balance_at(&alice, number).unwrap(),
+                Balance::from(100 * number)
+            );
+            assert_eq!(
+                history.storage_at(&alice, b"height", number).unwrap(),
+                Some(number.to_be_bytes().to_vec())
+            );
+        }
+        assert!(history.account_at(&Address([2; 20]), 3).unwrap().is_none());
+        assert!(matches!(
+            history.at_block(6),
+            Err(StorageError::UnknownBlockNumber(6))
+        ));
+
+        // Databases from before the archive index are backfilled by migration
+        let mut batch = WriteBatch::new();
+        for number in 0..6u64 {
+            batch.delete(Column::StateRoots, number.to_be_bytes());
+        }
+        store.write(batch).unwrap();
+        assert!(history.at_block(2).is_err());
+        migration::migrate(&*store, migration::MIGRATIONS, 1, 2).unwrap();
+        assert_eq!(history.balance_at(&alice, 2).unwrap(), Balance::from(200u64));
+    }
+
+    #[test]
+    fn test_pruned_heights_unavailable() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        build_chain(store.clone(), PruningMode::KeepRecent(3), 8);
+        let history = StateHistory::new(store);
+
+        assert!(matches!(
+            history.at_block(2),
+            Err(StorageError::MissingState(_))
+        ));
+        let view = history.at_block(5).unwrap();
+        assert_eq!(view.balance(&Address([1; 20])).unwrap(), Balance::from(500u64));
+    }
+}
@@ -0,0 +1,502 @@
+//! Validated block import
+//!
+//! A block is accepted only if it extends a stored parent, passes the
+//! consensus header checks, its header commitments match its contents, and
+//! re-executing its transactions on the parent state reproduces its gas
+//! usage, receipts and state root. Accepted
+//! blocks are stored with their receipts and become the canonical head when
+//! they are higher than the current one; a higher block that would retract a
+//! finalized block is rejected. A new head at a snapshot interval
+//! is snapshotted if snapshots are enabled.
+
+use super::{
+    has_state, BlockStore, KeyValueStore, PruningMode, SnapshotStore, StateManager, StorageError,
+};
+use crate::consensus::finality::FinalityProof;
+use crate::consensus::{self, ConsensusError, Validator};
+use crate::crypto::merkle::{receipts_root, transactions_root};
+use crate::types::*;
+use crate::vm::executor::{BlockContext, ExecutionError, TransactionExecutor};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Reasons a block is rejected
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("Unknown parent block {0}")]
+    UnknownParent(BlockHash),
+
+    #[error("State {0} is not available")]
+    MissingState(BlockHash),
+
+    #[error("Consensus check failed: {0}")]
+    Consensus(#[from] ConsensusError),
+
+    #[error("Transactions root mismatch")]
+    TransactionsRoot,
+
+    #[error("Transaction {index} rejected: {error}")]
+    Transaction { index: usize, error: ExecutionError },
+
+    #[error("Block gas limit exceeded")]
+    GasLimitExceeded,
+
+    #[error("Gas used {actual} does not match header {expected}")]
+    GasUsed { expected: Gas, actual: Gas },
+
+    #[error("Receipts root mismatch")]
+    ReceiptsRoot,
+
+    #[error("Logs bloom mismatch")]
+    LogsBloom,
+
+    #[error("State root {actual} does not match header {expected}")]
+    StateRoot { expected: BlockHash, actual: BlockHash },
+
+    #[error("Block {0} does not descend from the finalized block")]
+    FinalityConflict(BlockHash),
+
+    #[error("State unavailable: {0}")]
+    State(String),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+}
+
+/// Result type for block import
+pub type ImportResult<T> = Result<T, ImportError>;
+
+/// Validates blocks by re-execution and stores them
+pub struct BlockImporter {
+    store: Arc<dyn KeyValueStore>,
+    blocks: BlockStore,
+    executor: TransactionExecutor,
+    pruning: PruningMode,
+    snapshots: Option<SnapshotStore>,
+    validators: Option<Vec<Validator>>,
+}
+
+impl BlockImporter {
+    /// Create an importer on top of a store
+    pub fn new(store: Arc<dyn KeyValueStore>, executor: TransactionExecutor) -> Self {
+        Self {
+            blocks: BlockStore::new(store.clone()),
+            store,
+            executor,
+            pruning: PruningMode::Archive,
+            snapshots: None,
+            validators: None,
+        }
+    }
+
+    /// Set how long replaced state is kept (archive by default)
+    pub fn with_pruning(mut self, mode: PruningMode) -> Self {
+        self.pruning = mode;
+        self
+    }
+
+    /// Take periodic snapshots of the canonical state (none by default)
+    pub fn with_snapshots(mut self, snapshots: SnapshotStore) -> Self {
+        self.snapshots = Some(snapshots);
+        self
+    }
+
+    /// Only accept blocks proposed by active members of `validators`
+    pub fn with_validators(mut self, validators: Vec<Validator>) -> Self {
+        self.validators = Some(validators);
+        self
+    }
+
+    /// Block store written by this importer
+    pub fn blocks(&self) -> &BlockStore {
+        &self.blocks
+    }
+
+    /// Validate and store a block, returning its hash
+    ///
+    /// Importing a stored block is a no-op. The genesis block is not executed;
+    /// its state must already be present.
+    pub fn import_block(&self, block: &Block) -> ImportResult<BlockHash> {
+        let hash = block.hash();
+        if self.blocks.contains(&hash)? {
+            return Ok(hash);
+        }
+
+        let header = &block.header;
+        if transactions_root(&block.transactions) != header.transactions_root {
+            return Err(ImportError::TransactionsRoot);
+        }
+
+        if header.number == 0 {
+            if !has_state(&*self.store, &header.state_root)? {
+                return Err(ImportError::MissingState(header.state_root));
+            }
+            self.blocks.insert_block(block, &[])?;
+            return self.update_head(hash, header);
+        }
+
+        let parent = self
+            .blocks
+            .header(&header.parent_hash)?
+            .ok_or(ImportError::UnknownParent(header.parent_hash))?;
+        consensus::validate_header(&parent, header)?;
+        if let Some(validators) = &self.validators {
+            consensus::validate_proposer(validators, &header.proposer)?;
+        }
+        consensus::validate_signatures(block)?;
+        if !has_state(&*self.store, &parent.state_root)? {
+            return Err(ImportError::MissingState(parent.state_root));
+        }
+
+        let mut state =
+            StateManager::at_root(self.store.clone(), parent.state_root).with_pruning(self.pruning);
+        let context = BlockContext {
+            number: header.number,
+            base_fee: header.base_fee_per_gas,
+            proposer: header.proposer,
+        };
+        let mut receipts = Vec::with_capacity(block.transactions.len());
+        let mut gas_used: Gas = 0;
+        let mut logs_bloom = Bloom::default();
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let receipt = self
+                .executor
+                .execute(&mut state, tx, &context)
+                .map_err(|error| match error {
+                    // A local read failure says nothing about the block
+                    ExecutionError::State(message) => ImportError::State(message),
+                    error => ImportError::Transaction { index, error },
+                })?;
+            gas_used = gas_used
+                .checked_add(receipt.gas_used)
+                .filter(|gas| *gas <= header.gas_limit)
+                .ok_or(ImportError::GasLimitExceeded)?;
+            logs_bloom.accrue_bloom(&receipt.logs_bloom);
+            receipts.push(receipt);
+        }
+
+        if gas_used != header.gas_used {
+            return Err(ImportError::GasUsed {
+                expected: header.gas_used,
+                actual: gas_used,
+            });
+        }
+        if receipts_root(&receipts) != header.receipts_root {
+            return Err(ImportError::ReceiptsRoot);
+        }
+        if logs_bloom != header.logs_bloom {
+            return Err(ImportError::LogsBloom);
+        }
+
+        // Only persist the state once the root is known to match
+        let (root, changes) = state.prepare_commit()?;
+        if root != header.state_root {
+            return Err(ImportError::StateRoot {
+                expected: header.state_root,
+                actual: root,
+            });
+        }
+        state.write_commit((header.number, hash), root, changes)?;
+        self.blocks.insert_block(block, &receipts)?;
+        self.update_head(hash, header)
+    }
+
+    /// Record a proof that a canonical block is final
+    ///
+    /// The proof is checked against the validator set when one is configured.
+    pub fn finalize(&self, proof: &FinalityProof) -> ImportResult<()> {
+        if let Some(validators) = &self.validators {
+            proof.verify(validators)?;
+        }
+        self.blocks.insert_finality_proof(proof)?;
+        Ok(())
+    }
+
+    fn update_head(&self, hash: BlockHash, header: &BlockHeader) -> ImportResult<BlockHash> {
+        if self.blocks.head()?.is_none_or(|head| header.number > head.number) {
+            self.blocks.set_head(hash).map_err(|error| match error {
+                StorageError::FinalityConflict(hash) => ImportError::FinalityConflict(hash),
+                error => ImportError::Storage(error),
+            })?;
+            if let Some(snapshots) = &self.snapshots {
+                // The block is already stored; a failed snapshot is retried at
+                // the next interval
+                if let Err(e) = snapshots.on_block(&*self.store, header.state_root, header.number) {
+                    tracing::warn!("Snapshot at block {} failed: {}", header.number, e);
+                }
+            }
+        }
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{ChainFileReader, ChainFileWriter, MemoryStore};
+    use ed25519_dalek::SigningKey;
+
+    /// Produces valid blocks by executing on its own store
+    struct Producer {
+        state: StateManager,
+        chain: Vec<Block>,
+    }
+
+    fn genesis_state(store: Arc<dyn KeyValueStore>, senders: &[Address]) -> StateManager {
+        let mut state = StateManager::new(store);
+        for sender in senders {
+            let account = Account {
+                balance: Balance::from(1_000_000_000u64),
+                ..Default::default()
+            };
+            state.set_account(*sender, account);
+        }
+        state.commit(0).unwrap();
+        state
+    }
+
+    impl Producer {
+        fn new(senders: &[Address]) -> Self {
+            let state = genesis_state(Arc::new(MemoryStore::new()), senders);
+            let genesis = Block {
+                header: BlockHeader {
+                    number: 0,
+                    parent_hash: BlockHash([0; 32]),
+                    timestamp: 0,
+                    state_root: state.state_root(),
+                    transactions_root: transactions_root(&[]),
+                    receipts_root: receipts_root(&[]),
+                    proposer: Address([0xff; 20]),
+                    logs_bloom: Bloom::default(),
+                    gas_limit: 1_000_000,
+                    gas_used: 0,
+                    base_fee_per_gas: Balance::from(10u64),
+                    extra_data: vec![],
+                },
+                transactions: vec![],
+            };
+            Self {
+                state,
+                chain: vec![genesis],
+            }
+        }
+
+        fn produce(&mut self, transactions: Vec<Transaction>) {
+            let parent = &self.chain.last().unwrap().header;
+            let mut header = BlockHeader {
+                number: parent.number + 1,
+                parent_hash: self.chain.last().unwrap().hash(),
+                timestamp: parent.timestamp + 1,
+                base_fee_per_gas: parent.next_base_fee(),
+                transactions_root: transactions_root(&transactions),
+                ..parent.clone()
+            };
+            let context = BlockContext {
+                number: header.number,
+                base_fee: header.base_fee_per_gas,
+                proposer: header.proposer,
+            };
+            let executor = TransactionExecutor::new();
+            let receipts: Vec<_> = transactions
+                .iter()
+                .map(|tx| executor.execute(&mut self.state, tx, &context).unwrap())
+                .collect();
+            header.gas_used = receipts.iter().map(|r| r.gas_used).sum();
+            header.receipts_root = receipts_root(&receipts);
+            header.logs_bloom = Bloom::from_logs(receipts.iter().flat_map(|r| &r.logs));
+            header.state_root = self.state.commit(header.number).unwrap();
+            self.chain.push(Block {
+                header,
+                transactions,
+            });
+        }
+    }
+
+    fn transfer(key: &SigningKey, nonce: Nonce) -> Transaction {
+        let mut tx = Transaction {
+            from: PublicKey(key.verifying_key().to_bytes()).to_address(),
+            to: Some(Address([2; 20])),
+            value: Balance::from(100u64),
+            data: vec![],
+            gas_limit: 21_000,
+            kind: TxKind::Legacy {
+                gas_price: Balance::from(20u64),
+            },
+            nonce,
+            paymaster: None,
+            authorization: Authorization::Contract { data: vec![] },
+        };
+        tx.sign(key);
+        tx
+    }
+
+    #[test]
+    fn test_export_and_replay() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let sender = PublicKey(key.verifying_key().to_bytes()).to_address();
+        let mut producer = Producer::new(&[sender]);
+        for number in 0..4u64 {
+            producer.produce(vec![transfer(&key, 2 * number), transfer(&key, 2 * number + 1)]);
+        }
+
+        let mut writer = ChainFileWriter::new(Vec::new()).unwrap();
+        for block in &producer.chain {
+            writer.write_block(block).unwrap();
+        }
+        let file = writer.finish().unwrap();
+
+        // A fresh node with the same genesis state replays the file
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        genesis_state(store.clone(), &[sender]);
+        let importer = BlockImporter::new(store.clone(), TransactionExecutor::new());
+        for block in ChainFileReader::new(file.as_slice()).unwrap() {
+            importer.import_block(&block.unwrap()).unwrap();
+        }
+        let head = importer.blocks().head().unwrap().unwrap();
+        assert_eq!(head.number, 4);
+        assert_eq!(head.state_root, producer.state.state_root());
+        let receipt = importer
+            .blocks()
+            .receipt(&producer.chain[3].transactions[1].hash())
+            .unwrap()
+            .unwrap();
+        assert!(receipt.success);
+
+        // A tampered block is rejected and the head stays put
+        let mut forged = producer.chain[4].clone();
+        forged.header.state_root = BlockHash([9; 32]);
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        genesis_state(store.clone(), &[sender]);
+        let importer = BlockImporter::new(store, TransactionExecutor::new());
+        for block in &producer.chain[..4] {
+            importer.import_block(block).unwrap();
+        }
+        assert!(matches!(
+            importer.import_block(&forged),
+            Err(ImportError::StateRoot { .. })
+        ));
+        assert_eq!(importer.blocks().head().unwrap().unwrap().number, 3);
+
+        // Truncated files fail instead of ending early
+        let mut reader = ChainFileReader::new(&file[..file.len() - 20]).unwrap();
+        assert!(reader.any(|block| block.is_err()));
+    }
+
+    #[test]
+    fn test_consensus_checks() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let sender = PublicKey(key.verifying_key().to_bytes()).to_address();
+        let mut producer = Producer::new(&[sender]);
+        producer.produce(vec![transfer(&key, 0)]);
+        let importer = |validators: Vec<Validator>| {
+            let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+            genesis_state(store.clone(), &[sender]);
+            let importer =
+                BlockImporter::new(store, TransactionExecutor::new()).with_validators(validators);
+            importer.import_block(&producer.chain[0]).unwrap();
+            importer
+        };
+        let validator = |address, is_active| Validator {
+            address,
+            stake: Balance::ZERO,
+            public_key: PublicKey([0; 32]),
+            reputation: 0,
+            is_active,
+        };
+
+        let proposer = producer.chain[1].header.proposer;
+        assert!(matches!(
+            importer(vec![validator(Address([3; 20]), true)]).import_block(&producer.chain[1]),
+            Err(ImportError::Consensus(ConsensusError::ValidatorNotFound))
+        ));
+        assert!(importer(vec![validator(proposer, false)])
+            .import_block(&producer.chain[1])
+            .is_err());
+
+        let importer = importer(vec![validator(proposer, true)]);
+        let mut stale = producer.chain[1].clone();
+        stale.header.timestamp = producer.chain[0].header.timestamp;
+        assert!(matches!(
+            importer.import_block(&stale),
+            Err(ImportError::Consensus(ConsensusError::InvalidBlock(_)))
+        ));
+        let mut forged = producer.chain[1].clone();
+        forged.transactions[0].value = Balance::from(1u64);
+        forged.header.transactions_root = transactions_root(&forged.transactions);
+        assert!(matches!(
+            importer.import_block(&forged),
+            Err(ImportError::Consensus(ConsensusError::InvalidSignature))
+        ));
+        importer.import_block(&producer.chain[1]).unwrap();
+    }
+
+    #[test]
+    fn test_pruning_keeps_canonical_state_across_forks() {
+        let keys = [
+            SigningKey::from_bytes(&[1; 32]),
+            SigningKey::from_bytes(&[2; 32]),
+        ];
+        let senders = keys
+            .each_ref()
+            .map(|key| PublicKey(key.verifying_key().to_bytes()).to_address());
+        let mut canonical = Producer::new(&senders);
+        for nonce in 0..6 {
+            canonical.produce(vec![transfer(&keys[0], nonce)]);
+        }
+        // The fork touches an account the canonical chain leaves alone
+        let mut fork = Producer::new(&senders);
+        fork.produce(vec![transfer(&keys[1], 0)]);
+
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        genesis_state(store.clone(), &senders);
+        let importer = BlockImporter::new(store.clone(), TransactionExecutor::new())
+            .with_pruning(PruningMode::KeepRecent(3));
+        importer.import_block(&canonical.chain[0]).unwrap();
+        importer.import_block(&canonical.chain[1]).unwrap();
+        importer.import_block(&fork.chain[1]).unwrap();
+        for block in &canonical.chain[2..] {
+            importer.import_block(block).unwrap();
+        }
+
+        let head = importer.blocks().head().unwrap().unwrap();
+        assert_eq!(head.number, 6);
+        let state = StateManager::at_root(store, head.state_root);
+        let recipients = [Address([2; 20]), Address([0xff; 20])];
+        for address in senders.iter().chain(&recipients) {
+            let expected = canonical.state.get_account(address).unwrap().unwrap();
+            let account = state.get_account(address).unwrap().unwrap();
+            assert_eq!(
+                (account.nonce, account.balance),
+                (expected.nonce, expected.balance)
+            );
+        }
+    }
+
+    #[test]
+    fn test_snapshots_at_interval() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let sender = PublicKey(key.verifying_key().to_bytes()).to_address();
+        let mut producer = Producer::new(&[sender]);
+        for nonce in 0..5 {
+            producer.produce(vec![transfer(&key, nonce)]);
+        }
+
+        let dir =
+            std::env::temp_dir().join(format!("quantumchain-import-snapshots-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        genesis_state(store.clone(), &[sender]);
+        let importer = BlockImporter::new(store, TransactionExecutor::new())
+            .with_snapshots(SnapshotStore::new(&dir, 2));
+        for block in &producer.chain {
+            importer.import_block(block).unwrap();
+        }
+
+        let snapshots = SnapshotStore::new(&dir, 2);
+        assert_eq!(snapshots.snapshots().unwrap(), vec![2, 4]);
+        let manifest = snapshots.snapshot(4).manifest().unwrap();
+        assert_eq!(manifest.state_root, producer.chain[4].header.state_root);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+//! Journaled state overlay
+//!
+//! Writes go to an in-memory overlay and every write records what it
+//! replaced, so the overlay can be rolled back to any open checkpoint.
+//! Checkpoints nest: committing an inner checkpoint keeps its changes
+//! revertible by the outer one. Nothing reaches the underlying state until
+//! [`JournaledState::flush`].
+
+use super::{code_hash, AccountState, StorageResult};
+use crate::types::*;
+use std::collections::HashMap;
+
+/// Overlay value replaced by a write (None if the key was not in the overlay)
+enum JournalEntry {
+    Account {
+        address: Address,
+        previous: Option<Account>,
+    },
+    Storage {
+        address: Address,
+        key: Vec<u8>,
+        previous: Option<Option<Vec<u8>>>,
+    },
+    Code {
+        hash: BlockHash,
+    },
+}
+
+/// State overlay with checkpoint, commit and revert
+pub struct JournaledState<'a, S: AccountState + ?Sized> {
+    base: &'a mut S,
+    accounts: HashMap<Address, Account>,
+    storage: HashMap<(Address, Vec<u8>), Option<Vec<u8>>>,
+    code: HashMap<BlockHash, Vec<u8>>,
+    journal: Vec<JournalEntry>,
+    checkpoints: Vec<usize>,
+}
+
+impl<'a, S: AccountState + ?Sized> JournaledState<'a, S> {
+    /// Create an empty overlay on top of `base`
+    pub fn new(base: &'a mut S) -> Self {
+        Self {
+            base,
+            accounts: HashMap::new(),
+            storage: HashMap::new(),
+            code: HashMap::new(),
+            journal: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Open a checkpoint
+    pub fn checkpoint(&mut self) {
+        self.checkpoints.push(self.journal.len());
+    }
+
+    /// Close the innermost checkpoint, keeping its changes
+    pub fn commit_checkpoint(&mut self) {
+        self.checkpoints.pop().expect("no open checkpoint");
+    }
+
+    /// Close the innermost checkpoint, undoing its changes
+    pub fn revert_checkpoint(&mut self) {
+        let length = self.checkpoints.pop().expect("no open checkpoint");
+        for entry in self.journal.drain(length..).rev() {
+            match entry {
+                JournalEntry::Account { address, previous } => match previous {
+                    Some(account) => {
+                        self.accounts.insert(address, account);
+                    }
+                    None => {
+                        self.accounts.remove(&address);
+                    }
+                },
+                JournalEntry::Storage {
+                    address,
+                    key,
+                    previous,
+                } => match previous {
+                    Some(value) => {
+                        self.storage.insert((address, key), value);
+                    }
+                    None => {
+                        self.storage.remove(&(address, key));
+                    }
+                },
+                JournalEntry::Code { hash } => {
+                    self.code.remove(&hash);
+                }
+            }
+        }
+    }
+
+    /// Number of open checkpoints
+    pub fn depth(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Accounts written through the overlay
+    pub fn dirty_accounts(&self) -> impl Iterator<Item = &Address> {
+        self.accounts.keys()
+    }
+
+    /// Storage slots written through the overlay
+    pub fn dirty_storage(&self) -> impl Iterator<Item = (&Address, &[u8])> {
+        self.storage.keys().map(|(address, key)| (address, key.as_slice()))
+    }
+
+    /// Write the overlay to the underlying state (open checkpoints are committed)
+    pub fn flush(self) {
+        for (address, account) in self.accounts {
+            self.base.set_account(address, account);
+        }
+        for ((address, key), value) in self.storage {
+            self.base.set_storage(address, key, value);
+        }
+        for code in self.code.into_values() {
+            self.base.insert_code(code);
+        }
+    }
+}
+
+impl<S: AccountState + ?Sized> AccountState for JournaledState<'_, S> {
+    fn get_account(&self, address: &Address) -> StorageResult<Option<Account>> {
+        match self.accounts.get(address) {
+            Some(account) => Ok(Some(account.clone())),
+            None => self.base.get_account(address),
+        }
+    }
+
+    fn set_account(&mut self, address: Address, account: Account) {
+        let previous = self.accounts.insert(address, account);
+        self.journal.push(JournalEntry::Account { address, previous });
+    }
+
+    fn get_storage(&self, address: &Address, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        match self.storage.get(&(*address, key.to_vec())) {
+            Some(value) => Ok(value.clone()),
+            None => self.base.get_storage(address, key),
+        }
+    }
+
+    fn set_storage(&mut self, address: Address, key: Vec<u8>, value: Option<Vec<u8>>) {
+        let previous = self.storage.insert((address, key.clone()), value);
+        self.journal.push(JournalEntry::Storage {
+            address,
+            key,
+            previous,
+        });
+    }
+
+    fn get_code(&self, hash: &BlockHash) -> StorageResult<Option<Vec<u8>>> {
+        match self.code.get(hash) {
+            Some(code) => Ok(Some(code.clone())),
+            None => self.base.get_code(hash),
+        }
+    }
+
+    fn insert_code(&mut self, code: Vec<u8>) -> BlockHash {
+        let hash = code_hash(&code);
+        if self.code.insert(hash, code).is_none() {
+            self.journal.push(JournalEntry::Code { hash });
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{KeyValueStore, MemoryStore, StateManager};
+    use std::sync::Arc;
+
+    fn account(nonce: Nonce) -> Account {
+        Account {
+            nonce,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_nested_checkpoints() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut base = StateManager::new(store);
+        let (alice, bob) = (Address([1; 20]), Address([2; 20]));
+        base.set_account(alice, account(1));
+
+        let mut state = JournaledState::new(&mut base);
+        state.set_account(alice, account(2));
+
+        state.checkpoint();
+        state.set_account(bob, account(7));
+        state.set_storage(bob, b"k".to_vec(), Some(b"v".to_vec()));
+
+        state.checkpoint();
+        state.set_account(alice, account(3));
+        state.commit_checkpoint();
+        assert_eq!(state.get_account(&alice).unwrap().unwrap().nonce, 3);
+
+        // Reverting the outer checkpoint also undoes the committed inner one
+        state.revert_checkpoint();
+        assert_eq!(state.get_account(&alice).unwrap().unwrap().nonce, 2);
+        assert!(state.get_account(&bob).unwrap().is_none());
+        assert_eq!(state.get_storage(&bob, b"k").unwrap(), None);
+        assert_eq!(state.dirty_accounts().collect::<Vec<_>>(), vec![&alice]);
+        assert_eq!(state.dirty_storage().count(), 0);
+
+        state.flush();
+        assert_eq!(base.get_account(&alice).unwrap().unwrap().nonce, 2);
+        assert!(base.get_account(&bob).unwrap().is_none());
+    }
+}
@@ -0,0 +1,212 @@
+//! Pluggable key-value backends
+//!
+//! Everything above the database layer (state, block store, indexes) talks
+//! to a [`KeyValueStore`], so it runs unchanged against RocksDB on disk or
+//! against [`MemoryStore`] in tests and simulations.
+
+use super::{BatchOp, Column, Database, DatabaseSnapshot, StorageResult, WriteBatch};
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, HashMap};
+
+/// Iterator over `(key, value)` entries in key order
+pub type KvIter<'a> = Box<dyn Iterator<Item = StorageResult<(Vec<u8>, Vec<u8>)>> + 'a>;
+
+/// Read access, shared by stores and their snapshots
+pub trait KeyValueRead {
+    /// Get a value
+    fn get(&self, column: Column, key: &[u8]) -> StorageResult<Option<Vec<u8>>>;
+
+    /// Iterate over entries whose key starts with `prefix`, in key order
+    fn iter_prefix<'a>(&'a self, column: Column, prefix: &'a [u8]) -> KvIter<'a>;
+
+    /// Check whether a key exists
+    fn contains(&self, column: Column, key: &[u8]) -> StorageResult<bool> {
+        Ok(self.get(column, key)?.is_some())
+    }
+}
+
+/// Key-value store backend
+pub trait KeyValueStore: KeyValueRead + Send + Sync {
+    /// Apply a batch atomically
+    fn write(&self, batch: WriteBatch) -> StorageResult<()>;
+
+    /// Consistent read-only view unaffected by later writes
+    fn snapshot(&self) -> Box<dyn KeyValueRead + '_>;
+
+    /// Insert or overwrite a single value
+    fn put(&self, column: Column, key: &[u8], value: &[u8]) -> StorageResult<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(column, key, value);
+        self.write(batch)
+    }
+
+    /// Remove a single value
+    fn delete(&self, column: Column, key: &[u8]) -> StorageResult<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(column, key);
+        self.write(batch)
+    }
+}
+
+impl KeyValueRead for Database {
+    fn get(&self, column: Column, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        Database::get(self, column, key)
+    }
+
+    fn iter_prefix<'a>(&'a self, column: Column, prefix: &'a [u8]) -> KvIter<'a> {
+        Box::new(Database::iter_prefix(self, column, prefix))
+    }
+}
+
+impl KeyValueStore for Database {
+    fn write(&self, batch: WriteBatch) -> StorageResult<()> {
+        Database::write(self, batch)
+    }
+
+    fn snapshot(&self) -> Box<dyn KeyValueRead + '_> {
+        Box::new(Database::snapshot(self))
+    }
+}
+
+impl KeyValueRead for DatabaseSnapshot<'_> {
+    fn get(&self, column: Column, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        DatabaseSnapshot::get(self, column, key)
+    }
+
+    fn iter_prefix<'a>(&'a self, column: Column, prefix: &'a [u8]) -> KvIter<'a> {
+        Box::new(DatabaseSnapshot::iter_prefix(self, column, prefix))
+    }
+}
+
+type Columns = HashMap<Column, BTreeMap<Vec<u8>, Vec<u8>>>;
+
+fn read_prefix<'a>(columns: &Columns, column: Column, prefix: &'a [u8]) -> KvIter<'a> {
+    let entries: Vec<_> = columns
+        .get(&column)
+        .map(|entries| {
+            entries
+                .range(prefix.to_vec()..)
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .map(|(key, value)| Ok((key.clone(), value.clone())))
+                .collect()
+        })
+        .unwrap_or_default();
+    Box::new(entries.into_iter())
+}
+
+/// In-memory store backed by sorted maps
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    columns: RwLock<Columns>,
+}
+
+impl MemoryStore {
+    /// Create empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of entries in a column
+    pub fn len(&self, column: Column) -> usize {
+        self.columns.read().get(&column).map_or(0, BTreeMap::len)
+    }
+}
+
+impl KeyValueRead for MemoryStore {
+    fn get(&self, column: Column, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        Ok(self
+            .columns
+            .read()
+            .get(&column)
+            .and_then(|entries| entries.get(key).cloned()))
+    }
+
+    fn iter_prefix<'a>(&'a self, column: Column, prefix: &'a [u8]) -> KvIter<'a> {
+        read_prefix(&self.columns.read(), column, prefix)
+    }
+}
+
+impl KeyValueStore for MemoryStore {
+    fn write(&self, batch: WriteBatch) -> StorageResult<()> {
+        let mut columns = self.columns.write();
+        for op in batch.ops() {
+            match op {
+                BatchOp::Put { column, key, value } => {
+                    columns.entry(*column).or_default().insert(key.clone(), value.clone());
+                }
+                BatchOp::Delete { column, key } => {
+                    if let Some(entries) = columns.get_mut(column) {
+                        entries.remove(key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Box<dyn KeyValueRead + '_> {
+        Box::new(MemorySnapshot {
+            columns: self.columns.read().clone(),
+        })
+    }
+}
+
+/// Point-in-time copy of a [`MemoryStore`]
+struct MemorySnapshot {
+    columns: Columns,
+}
+
+impl KeyValueRead for MemorySnapshot {
+    fn get(&self, column: Column, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        Ok(self
+            .columns
+            .get(&column)
+            .and_then(|entries| entries.get(key).cloned()))
+    }
+
+    fn iter_prefix<'a>(&'a self, column: Column, prefix: &'a [u8]) -> KvIter<'a> {
+        read_prefix(&self.columns, column, prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StorageConfig;
+
+    fn exercise(store: &dyn KeyValueStore) {
+        let mut batch = WriteBatch::new();
+        batch.put(Column::Headers, b"a1".to_vec(), b"x".to_vec());
+        batch.put(Column::Headers, b"a2".to_vec(), b"y".to_vec());
+        batch.put(Column::Headers, b"b1".to_vec(), b"z".to_vec());
+        store.write(batch).unwrap();
+
+        let snapshot = store.snapshot();
+        store.delete(Column::Headers, b"a1").unwrap();
+        store.put(Column::Headers, b"a3", b"w").unwrap();
+
+        let keys = |iter: KvIter| iter.map(|entry| entry.unwrap().0).collect::<Vec<_>>();
+        assert_eq!(keys(store.iter_prefix(Column::Headers, b"a")), vec![b"a2".to_vec(), b"a3".to_vec()]);
+        assert_eq!(keys(snapshot.iter_prefix(Column::Headers, b"a")), vec![b"a1".to_vec(), b"a2".to_vec()]);
+
+        assert_eq!(snapshot.get(Column::Headers, b"a1").unwrap(), Some(b"x".to_vec()));
+        assert!(!store.contains(Column::Headers, b"a1").unwrap());
+        assert!(!store.contains(Column::Bodies, b"a2").unwrap());
+    }
+
+    #[test]
+    fn test_memory_store() {
+        exercise(&MemoryStore::new());
+    }
+
+    #[test]
+    fn test_rocksdb_store() {
+        let path = std::env::temp_dir().join(format!("quantumchain-kv-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let config = StorageConfig {
+            db_path: path.to_string_lossy().into_owned(),
+            ..StorageConfig::default()
+        };
+        exercise(&Database::open(&config).unwrap());
+    }
+}
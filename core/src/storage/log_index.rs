@@ -0,0 +1,358 @@
+//! Log index for `getLogs`-style filtering
+//!
+//! Maps each emitting address and each (position, topic) pair to the blocks
+//! that contain a matching log, grouped into fixed-size sections so a query
+//! over a block range touches one entry per section instead of every block.
+
+use super::{Column, KeyValueStore, StorageError, StorageResult, WriteBatch};
+use crate::types::*;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// Number of blocks covered by one index entry
+pub const LOG_INDEX_SECTION_SIZE: BlockNumber = 4096;
+
+const ADDRESS_PREFIX: u8 = b'a';
+const TOPIC_PREFIX: u8 = b't';
+const HEAD_KEY: &[u8] = b"head";
+
+/// Log filter
+///
+/// A log matches if it was emitted by one of `addresses` (any address if
+/// empty) and, for each position in `topics`, its topic at that position is
+/// one of the listed values (`None` matches anything).
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    /// First block to search (inclusive)
+    pub from_block: BlockNumber,
+    /// Last block to search (inclusive)
+    pub to_block: BlockNumber,
+    /// Emitting addresses
+    pub addresses: Vec<Address>,
+    /// Positional topic alternatives
+    pub topics: Vec<Option<Vec<[u8; 32]>>>,
+}
+
+impl LogFilter {
+    /// Check a single log against the filter
+    pub fn matches(&self, log: &Log) -> bool {
+        if !self.addresses.is_empty() && !self.addresses.contains(&log.address) {
+            return false;
+        }
+
+        self.topics.iter().enumerate().all(|(position, alternatives)| {
+            match alternatives {
+                None => true,
+                Some(alternatives) => log
+                    .topics
+                    .get(position)
+                    .is_some_and(|topic| alternatives.contains(topic)),
+            }
+        })
+    }
+
+    /// Check whether a bloom may contain matching logs
+    pub fn matches_bloom(&self, bloom: &Bloom) -> bool {
+        let address_ok = self.addresses.is_empty()
+            || self.addresses.iter().any(|a| bloom.contains_input(&a.0));
+
+        address_ok
+            && self.topics.iter().flatten().all(|alternatives| {
+                alternatives.iter().any(|topic| bloom.contains_input(topic))
+            })
+    }
+}
+
+/// Index from addresses and topics to block numbers
+///
+/// Entries live in [`Column::LogIndex`] keyed by
+/// `prefix || address-or-topic || section` with the section in big-endian, so
+/// all sections for one key are adjacent. Values are the sorted block numbers.
+/// The highest indexed block is kept under a separate key so queries never
+/// scan past it.
+pub struct LogIndex {
+    store: Arc<dyn KeyValueStore>,
+}
+
+impl LogIndex {
+    /// Create an index on top of a store
+    pub fn new(store: Arc<dyn KeyValueStore>) -> Self {
+        Self { store }
+    }
+
+    fn address_key(address: &Address, section: u64) -> Vec<u8> {
+        let mut key = Vec::with_capacity(1 + 20 + 8);
+        key.push(ADDRESS_PREFIX);
+        key.extend_from_slice(&address.0);
+        key.extend_from_slice(&section.to_be_bytes());
+        key
+    }
+
+    fn topic_key(position: usize, topic: &[u8; 32], section: u64) -> Vec<u8> {
+        let mut key = Vec::with_capacity(2 + 32 + 8);
+        key.push(TOPIC_PREFIX);
+        key.push(position as u8);
+        key.extend_from_slice(topic);
+        key.extend_from_slice(&section.to_be_bytes());
+        key
+    }
+
+    /// Keys touched by the logs of a block
+    fn block_keys(number: BlockNumber, receipts: &[Receipt]) -> BTreeSet<Vec<u8>> {
+        let section = number / LOG_INDEX_SECTION_SIZE;
+
+        receipts
+            .iter()
+            .flat_map(|r| &r.logs)
+            .flat_map(|log| {
+                std::iter::once(Self::address_key(&log.address, section)).chain(
+                    log.topics
+                        .iter()
+                        .enumerate()
+                        .map(move |(position, topic)| Self::topic_key(position, topic, section)),
+                )
+            })
+            .collect()
+    }
+
+    fn load(&self, key: &[u8]) -> StorageResult<BTreeSet<BlockNumber>> {
+        match self.store.get(Column::LogIndex, key)? {
+            None => Ok(BTreeSet::new()),
+            Some(bytes) => bincode::deserialize(&bytes)
+                .map_err(|e| StorageError::Corrupted(format!("Log index entry: {}", e))),
+        }
+    }
+
+    /// Highest block indexed so far
+    pub fn head(&self) -> StorageResult<Option<BlockNumber>> {
+        match self.store.get(Column::LogIndex, HEAD_KEY)? {
+            None => Ok(None),
+            Some(bytes) => bytes
+                .try_into()
+                .map(|bytes| Some(BlockNumber::from_be_bytes(bytes)))
+                .map_err(|_| StorageError::Corrupted("Log index head".to_string())),
+        }
+    }
+
+    /// Apply `update` to the entries of every key touched by a block
+    fn update_block(
+        &self,
+        number: BlockNumber,
+        receipts: &[Receipt],
+        update: impl Fn(&mut BTreeSet<BlockNumber>),
+        mut batch: WriteBatch,
+    ) -> StorageResult<()> {
+        for key in Self::block_keys(number, receipts) {
+            let mut blocks = self.load(&key)?;
+            update(&mut blocks);
+            if blocks.is_empty() {
+                batch.delete(Column::LogIndex, key);
+            } else {
+                batch.put(Column::LogIndex, key, bincode::serialize(&blocks).unwrap());
+            }
+        }
+        self.store.write(batch)
+    }
+
+    /// Index the logs of all receipts in a block
+    pub fn index_block(&self, number: BlockNumber, receipts: &[Receipt]) -> StorageResult<()> {
+        let mut batch = WriteBatch::new();
+        if self.head()?.is_none_or(|head| number > head) {
+            batch.put(Column::LogIndex, HEAD_KEY.to_vec(), number.to_be_bytes().to_vec());
+        }
+        self.update_block(
+            number,
+            receipts,
+            |blocks| {
+                blocks.insert(number);
+            },
+            batch,
+        )
+    }
+
+    /// Remove a block from the index (e.g. when it leaves the canonical chain)
+    ///
+    /// Blocks are removed from the top, so the block below becomes the
+    /// highest indexed one.
+    pub fn remove_block(&self, number: BlockNumber, receipts: &[Receipt]) -> StorageResult<()> {
+        let mut batch = WriteBatch::new();
+        match number.checked_sub(1) {
+            Some(below) => {
+                batch.put(Column::LogIndex, HEAD_KEY.to_vec(), below.to_be_bytes().to_vec())
+            }
+            None => batch.delete(Column::LogIndex, HEAD_KEY.to_vec()),
+        }
+        self.update_block(
+            number,
+            receipts,
+            |blocks| {
+                blocks.remove(&number);
+            },
+            batch,
+        )
+    }
+
+    /// Blocks in range whose entries match any of `keys`
+    fn blocks_for(
+        &self,
+        from_block: BlockNumber,
+        to_block: BlockNumber,
+        keys: impl Fn(u64) -> Vec<Vec<u8>>,
+    ) -> StorageResult<BTreeSet<BlockNumber>> {
+        let first = from_block / LOG_INDEX_SECTION_SIZE;
+        let last = to_block / LOG_INDEX_SECTION_SIZE;
+
+        let mut result = BTreeSet::new();
+        for key in (first..=last).flat_map(&keys) {
+            let blocks = self.load(&key)?;
+            result.extend(blocks.range(from_block..=to_block));
+        }
+        Ok(result)
+    }
+
+    /// Blocks that may contain logs matching the filter
+    ///
+    /// Each criterion narrows the candidates; a filter with no criteria
+    /// returns every block in the range. The range is clamped to the highest
+    /// indexed block. Callers still check individual logs with
+    /// [`LogFilter::matches`].
+    pub fn candidate_blocks(&self, filter: &LogFilter) -> StorageResult<Vec<BlockNumber>> {
+        let Some(head) = self.head()? else {
+            return Ok(Vec::new());
+        };
+        let (from_block, to_block) = (filter.from_block, filter.to_block.min(head));
+        if from_block > to_block {
+            return Ok(Vec::new());
+        }
+
+        let mut candidates: Option<BTreeSet<BlockNumber>> = None;
+        let mut narrow = |blocks: BTreeSet<BlockNumber>| {
+            candidates = Some(match candidates.take() {
+                None => blocks,
+                Some(current) => current.intersection(&blocks).copied().collect(),
+            });
+        };
+
+        if !filter.addresses.is_empty() {
+            narrow(self.blocks_for(from_block, to_block, |section| {
+                filter
+                    .addresses
+                    .iter()
+                    .map(|address| Self::address_key(address, section))
+                    .collect()
+            })?);
+        }
+
+        for (position, alternatives) in filter.topics.iter().enumerate() {
+            if let Some(alternatives) = alternatives {
+                narrow(self.blocks_for(from_block, to_block, |section| {
+                    alternatives
+                        .iter()
+                        .map(|topic| Self::topic_key(position, topic, section))
+                        .collect()
+                })?);
+            }
+        }
+
+        Ok(match candidates {
+            Some(blocks) => blocks.into_iter().collect(),
+            None => (from_block..=to_block).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+
+    fn receipt(address: Address, topics: Vec<[u8; 32]>) -> Receipt {
+        let logs = vec![Log {
+            address,
+            topics,
+            data: vec![],
+        }];
+        Receipt {
+            tx_hash: TxHash([0; 32]),
+            block_number: 0,
+            gas_used: 0,
+            success: true,
+            logs_bloom: Bloom::from_logs(&logs),
+            logs,
+            contract_address: None,
+        }
+    }
+
+    #[test]
+    fn test_candidate_blocks() {
+        let token = Address([1; 20]);
+        let transfer = [2; 32];
+        let approval = [3; 32];
+
+        let index = LogIndex::new(Arc::new(MemoryStore::new()));
+        index.index_block(10, &[receipt(token, vec![transfer])]).unwrap();
+        index.index_block(5000, &[receipt(token, vec![approval])]).unwrap();
+        index.index_block(6000, &[receipt(Address([9; 20]), vec![transfer])]).unwrap();
+
+        let mut filter = LogFilter {
+            from_block: 0,
+            to_block: 10_000,
+            addresses: vec![token],
+            topics: vec![],
+        };
+        assert_eq!(index.candidate_blocks(&filter).unwrap(), vec![10, 5000]);
+
+        filter.topics = vec![Some(vec![transfer])];
+        assert_eq!(index.candidate_blocks(&filter).unwrap(), vec![10]);
+
+        filter.addresses.clear();
+        assert_eq!(index.candidate_blocks(&filter).unwrap(), vec![10, 6000]);
+
+        filter.from_block = 100;
+        assert_eq!(index.candidate_blocks(&filter).unwrap(), vec![6000]);
+
+        // Open-ended ranges stop at the highest indexed block
+        let everything = LogFilter {
+            from_block: 5990,
+            to_block: u64::MAX,
+            ..Default::default()
+        };
+        assert_eq!(index.head().unwrap(), Some(6000));
+        assert_eq!(
+            index.candidate_blocks(&everything).unwrap(),
+            (5990..=6000).collect::<Vec<_>>()
+        );
+
+        index.remove_block(6000, &[receipt(Address([9; 20]), vec![transfer])]).unwrap();
+        assert!(index.candidate_blocks(&filter).unwrap().is_empty());
+        assert_eq!(index.head().unwrap(), Some(5999));
+        assert_eq!(
+            index.candidate_blocks(&everything).unwrap(),
+            (5990..=5999).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let log = Log {
+            address: Address([1; 20]),
+            topics: vec![[2; 32], [3; 32]],
+            data: vec![],
+        };
+        let bloom = Bloom::from_logs([&log]);
+
+        let filter = LogFilter {
+            topics: vec![None, Some(vec![[4; 32], [3; 32]])],
+            ..Default::default()
+        };
+        assert!(filter.matches(&log));
+        assert!(filter.matches_bloom(&bloom));
+
+        let filter = LogFilter {
+            addresses: vec![Address([2; 20])],
+            ..Default::default()
+        };
+        assert!(!filter.matches(&log));
+        assert!(!filter.matches_bloom(&bloom));
+    }
+}
@@ -1,7 +1,72 @@
 //! Storage and state management
 
+use thiserror::Error;
+
 pub mod state;
+pub mod journal;
 pub mod db;
+pub mod migration;
+pub mod kv;
+pub mod trie;
+pub mod proof;
+pub mod pruning;
+pub mod snapshot;
+pub mod blocks;
+pub mod log_index;
+pub mod chain_file;
+pub mod import;
+pub mod check;
+pub mod history;
 
 pub use state::*;
+pub use journal::*;
 pub use db::*;
+pub use migration::{Migration, MIGRATIONS};
+pub use kv::*;
+pub use trie::*;
+pub use proof::*;
+pub use pruning::{PruningMode, PruningStats};
+pub use snapshot::*;
+pub use blocks::*;
+pub use log_index::*;
+pub use chain_file::*;
+pub use import::*;
+pub use check::*;
+pub use history::*;
+
+/// Storage errors
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Database error: {0}")]
+    Database(#[from] rocksdb::Error),
+    
+    #[error("Incompatible schema version: found {found}, expected {expected}")]
+    SchemaVersion { found: u32, expected: u32 },
+    
+    #[error("Corrupted data: {0}")]
+    Corrupted(String),
+    
+    #[error("Unknown block: {0}")]
+    UnknownBlock(crate::types::BlockHash),
+    
+    #[error("No canonical block at height {0}")]
+    UnknownBlockNumber(crate::types::BlockNumber),
+    
+    #[error("Block {0} does not descend from the finalized block")]
+    FinalityConflict(crate::types::BlockHash),
+    
+    #[error("State {0} is not available (pruned or never stored)")]
+    MissingState(crate::types::BlockHash),
+    
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+    
+    #[error("Invalid chain file: {0}")]
+    InvalidChainFile(String),
+    
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Result type for storage operations
+pub type StorageResult<T> = Result<T, StorageError>;
@@ -0,0 +1,190 @@
+//! State inclusion and exclusion proofs
+//!
+//! A proof is the list of sibling hashes along a key's path, plus the leaf
+//! found at the end of it. The leaf proves inclusion when it holds the key,
+//! and exclusion when it holds a different key (or when the path ends in an
+//! empty subtree). Verification only needs the root, so light clients can
+//! check proofs without access to the database.
+
+use super::trie::{hash_leaf_digest, key_path, path_bit, EMPTY_TRIE_ROOT};
+use crate::crypto::merkle::hash_node;
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+
+/// Leaf at the end of a proof path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofLeaf {
+    /// Path of the leaf's key
+    pub path: [u8; 32],
+    /// Hash of the leaf's value
+    pub value_hash: [u8; 32],
+}
+
+/// Proof that a key has a given value, or is absent, in a trie
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrieProof {
+    /// Sibling hashes from the root downwards
+    pub siblings: Vec<[u8; 32]>,
+    /// Leaf where the path ends (None for an empty subtree)
+    pub leaf: Option<ProofLeaf>,
+}
+
+impl TrieProof {
+    /// Check that `key` maps to `value` (or is absent if `None`) under `root`
+    pub fn verify(&self, root: &BlockHash, key: &[u8], value: Option<&[u8]>) -> bool {
+        let path = key_path(key);
+        let depth = self.siblings.len();
+        if depth > 256 {
+            return false;
+        }
+
+        let mut hash = match (&self.leaf, value) {
+            (Some(leaf), Some(value)) => {
+                if leaf.path != path || leaf.value_hash != *blake3::hash(value).as_bytes() {
+                    return false;
+                }
+                hash_leaf_digest(&leaf.path, &leaf.value_hash)
+            }
+            (Some(leaf), None) => {
+                // A different key whose path shares the prefix walked so far
+                let shares_prefix =
+                    (0..depth).all(|i| path_bit(&leaf.path, i) == path_bit(&path, i));
+                if leaf.path == path || !shares_prefix {
+                    return false;
+                }
+                hash_leaf_digest(&leaf.path, &leaf.value_hash)
+            }
+            (None, None) => EMPTY_TRIE_ROOT,
+            (None, Some(_)) => return false,
+        };
+
+        for (i, sibling) in self.siblings.iter().enumerate().rev() {
+            hash = if path_bit(&path, i) {
+                hash_node(sibling, &hash)
+            } else {
+                hash_node(&hash, sibling)
+            };
+        }
+
+        hash == root.0
+    }
+}
+
+/// Proof of an account against a state root
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountProof {
+    /// Proven address
+    pub address: Address,
+    /// Account state (None if the account does not exist)
+    pub account: Option<Account>,
+    /// Path through the account trie
+    pub proof: TrieProof,
+}
+
+impl AccountProof {
+    /// Check the proof against a state root
+    pub fn verify(&self, state_root: &BlockHash) -> bool {
+        let encoded = self
+            .account
+            .as_ref()
+            .map(|a| bincode::serialize(a).unwrap());
+        self.proof
+            .verify(state_root, &self.address.0, encoded.as_deref())
+    }
+}
+
+/// Proof of a contract storage slot against a state root
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageProof {
+    /// Proof of the owning account (which commits to its storage root)
+    pub account: AccountProof,
+    /// Storage key
+    pub key: Vec<u8>,
+    /// Stored value (None if the slot is empty)
+    pub value: Option<Vec<u8>>,
+    /// Path through the account's storage trie
+    pub proof: TrieProof,
+}
+
+impl StorageProof {
+    /// Check the proof against a state root
+    pub fn verify(&self, state_root: &BlockHash) -> bool {
+        let storage_root = self
+            .account
+            .account
+            .as_ref()
+            .map_or(BlockHash(EMPTY_TRIE_ROOT), |a| a.storage_root);
+
+        self.account.verify(state_root)
+            && self
+                .proof
+                .verify(&storage_root, &self.key, self.value.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{KeyValueStore, MemoryStore, StateManager};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_account_proofs() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut state = StateManager::new(store);
+        for i in 0..20u8 {
+            let account = Account {
+                nonce: i as u64,
+                ..Default::default()
+            };
+            state.set_account(Address([i; 20]), account);
+        }
+        let root = state.commit(0).unwrap();
+
+        let present = state.prove_account(&Address([7; 20])).unwrap();
+        assert_eq!(present.account.as_ref().unwrap().nonce, 7);
+        assert!(present.verify(&root));
+
+        let absent = state.prove_account(&Address([99; 20])).unwrap();
+        assert!(absent.account.is_none());
+        assert!(absent.verify(&root));
+
+        // Claiming a different account, or absence of a present one, fails
+        let mut forged = present.clone();
+        forged.account.as_mut().unwrap().nonce = 8;
+        assert!(!forged.verify(&root));
+        forged.account = None;
+        assert!(!forged.verify(&root));
+
+        let mut moved = absent;
+        moved.address = Address([7; 20]);
+        assert!(!moved.verify(&root));
+    }
+
+    #[test]
+    fn test_storage_proofs() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut state = StateManager::new(store);
+        let contract = Address([1; 20]);
+        state.set_storage(contract, b"a".to_vec(), b"1".to_vec());
+        state.set_storage(contract, b"b".to_vec(), b"2".to_vec());
+        let root = state.commit(0).unwrap();
+
+        let proof = state.prove_storage(&contract, b"b").unwrap();
+        assert_eq!(proof.value, Some(b"2".to_vec()));
+        assert!(proof.verify(&root));
+
+        let proof = state.prove_storage(&contract, b"c").unwrap();
+        assert_eq!(proof.value, None);
+        assert!(proof.verify(&root));
+
+        let mut forged = proof;
+        forged.value = Some(b"3".to_vec());
+        assert!(!forged.verify(&root));
+
+        // Slots of a missing account are provably empty
+        let proof = state.prove_storage(&Address([2; 20]), b"a").unwrap();
+        assert!(proof.account.account.is_none());
+        assert!(proof.verify(&root));
+    }
+}
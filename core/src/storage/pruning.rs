@@ -0,0 +1,267 @@
+//! State trie pruning
+//!
+//! Every persisted trie node has a reference count in [`Column::StateRefs`]:
+//! the number of positions across all current tries that hold it. Nodes
+//! dropped by a block are not released at once but journaled under the block
+//! number and hash in [`Column::StateJournal`]. Once a height falls out of the
+//! retained history the journal of its canonical block is replayed,
+//! decrementing counts and deleting nodes that reach zero, so the roots of
+//! the last `pruning_history` blocks stay readable. Journals of other blocks
+//! at that height are discarded: their nodes may still be live on the
+//! canonical chain.
+//!
+//! In archive mode nothing is journaled and every node is kept. Nodes created
+//! only on abandoned forks are not reclaimed.
+
+use super::{Column, KeyValueStore, NodeChanges, StorageError, StorageResult, WriteBatch};
+use crate::config::StorageConfig;
+use crate::types::{BlockHash, BlockNumber};
+use std::collections::HashMap;
+use std::ops::AddAssign;
+
+/// How long replaced state is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruningMode {
+    /// Keep every historical state
+    Archive,
+    /// Keep the states of the given number of most recent blocks
+    KeepRecent(u64),
+}
+
+impl PruningMode {
+    /// Mode selected by `enable_pruning` and `pruning_history`
+    pub fn from_config(config: &StorageConfig) -> Self {
+        if config.enable_pruning {
+            PruningMode::KeepRecent(config.pruning_history.max(1))
+        } else {
+            PruningMode::Archive
+        }
+    }
+
+    /// Check whether all history is kept
+    pub fn is_archive(&self) -> bool {
+        matches!(self, PruningMode::Archive)
+    }
+}
+
+/// Space reclaimed by pruning
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruningStats {
+    /// Block journals replayed
+    pub blocks_pruned: u64,
+    /// Trie nodes deleted
+    pub nodes_removed: u64,
+    /// Bytes of deleted node keys and values
+    pub bytes_reclaimed: u64,
+}
+
+impl AddAssign for PruningStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks_pruned += other.blocks_pruned;
+        self.nodes_removed += other.nodes_removed;
+        self.bytes_reclaimed += other.bytes_reclaimed;
+    }
+}
+
+/// Reference counts read through a cache of this batch's updates
+struct RefCounts<'a> {
+    store: &'a dyn KeyValueStore,
+    counts: HashMap<[u8; 32], u32>,
+}
+
+impl<'a> RefCounts<'a> {
+    fn new(store: &'a dyn KeyValueStore) -> Self {
+        Self {
+            store,
+            counts: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, hash: &[u8; 32]) -> StorageResult<u32> {
+        if let Some(count) = self.counts.get(hash) {
+            return Ok(*count);
+        }
+        let count = match self.store.get(Column::StateRefs, hash)? {
+            None => 0,
+            Some(bytes) => u32::from_be_bytes(bytes.try_into().map_err(|_| {
+                StorageError::Corrupted("Invalid trie node reference count".to_string())
+            })?),
+        };
+        self.counts.insert(*hash, count);
+        Ok(count)
+    }
+
+    fn flush(self, batch: &mut WriteBatch) {
+        for (hash, count) in self.counts {
+            if count == 0 {
+                batch.delete(Column::StateRefs, hash.to_vec());
+            } else {
+                batch.put(
+                    Column::StateRefs,
+                    hash.to_vec(),
+                    count.to_be_bytes().to_vec(),
+                );
+            }
+        }
+    }
+}
+
+/// Dropped nodes of a block, as stored in the journal
+type Journal = Vec<([u8; 32], u32)>;
+
+/// Journal key of a block: number (big-endian) then hash
+fn journal_key(number: BlockNumber, hash: &BlockHash) -> Vec<u8> {
+    let mut key = Vec::with_capacity(8 + 32);
+    key.extend_from_slice(&number.to_be_bytes());
+    key.extend_from_slice(&hash.0);
+    key
+}
+
+/// Queue the node writes of block `number` and release expired history
+///
+/// Returns what the batch reclaims once written.
+pub fn write_changes(
+    store: &dyn KeyValueStore,
+    mode: PruningMode,
+    (number, hash): (BlockNumber, BlockHash),
+    mut changes: NodeChanges,
+    batch: &mut WriteBatch,
+) -> StorageResult<PruningStats> {
+    changes.net();
+    let mut refs = RefCounts::new(store);
+    let mut stats = PruningStats::default();
+
+    for (hash, (node, count)) in changes.inserted {
+        let current = refs.get(&hash)?;
+        if current == 0 {
+            batch.put(Column::StateNodes, hash.to_vec(), node.encode());
+        }
+        refs.counts.insert(hash, current + count);
+    }
+
+    if let PruningMode::KeepRecent(history) = mode {
+        let mut removed: Journal = changes.removed.into_iter().collect();
+        removed.sort();
+        if !removed.is_empty() {
+            batch.put(
+                Column::StateJournal,
+                journal_key(number, &hash),
+                bincode::serialize(&removed).unwrap(),
+            );
+        }
+
+        // The state before `expired` leaves the window with this block. The
+        // parent state is always kept: whether this block is canonical is not
+        // known yet, and a sibling may still be imported on top of it.
+        let expired = (number + 1).checked_sub(history.max(2));
+        let released = match expired {
+            Some(expired) => load_journal(store, expired, batch)?,
+            None => Vec::new(),
+        };
+
+        if expired.is_some() {
+            stats.blocks_pruned += 1;
+        }
+        for (hash, count) in released {
+            let remaining = refs.get(&hash)?.saturating_sub(count);
+            if remaining == 0 {
+                if let Some(node) = store.get(Column::StateNodes, &hash)? {
+                    stats.bytes_reclaimed += (hash.len() + node.len()) as u64;
+                }
+                batch.delete(Column::StateNodes, hash.to_vec());
+                stats.nodes_removed += 1;
+            }
+            refs.counts.insert(hash, remaining);
+        }
+    }
+
+    refs.flush(batch);
+    Ok(stats)
+}
+
+/// Read the journal of the canonical block at `number` and queue deletion of
+/// all journals at that height
+///
+/// State committed without a stored block has no canonical entry; all its
+/// journals at the height are replayed.
+fn load_journal(
+    store: &dyn KeyValueStore,
+    number: BlockNumber,
+    batch: &mut WriteBatch,
+) -> StorageResult<Journal> {
+    let prefix = number.to_be_bytes();
+    let canonical = store.get(Column::Canonical, &prefix)?;
+
+    let mut released = Journal::new();
+    for entry in store.iter_prefix(Column::StateJournal, &prefix) {
+        let (key, bytes) = entry?;
+        let replay = canonical
+            .as_ref()
+            .is_none_or(|hash| key[prefix.len()..] == hash[..]);
+        batch.delete(Column::StateJournal, key);
+        if !replay {
+            continue;
+        }
+        let journal: Journal = bincode::deserialize(&bytes)
+            .map_err(|e| StorageError::Corrupted(format!("State journal {}: {}", number, e)))?;
+        released.extend(journal);
+    }
+    Ok(released)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStore, StateManager};
+    use crate::types::*;
+    use std::sync::Arc;
+
+    fn run_blocks(mode: PruningMode) -> (Arc<MemoryStore>, StateManager, Vec<BlockHash>) {
+        let store = Arc::new(MemoryStore::new());
+        let mut state = StateManager::new(store.clone()).with_pruning(mode);
+        let mut roots = Vec::new();
+
+        for number in 0..10u64 {
+            for i in 0..8u8 {
+                let account = Account {
+                    nonce: number,
+                    ..Default::default()
+                };
+                state.set_account(Address([i; 20]), account);
+            }
+            roots.push(state.commit(number).unwrap());
+        }
+        (store, state, roots)
+    }
+
+    #[test]
+    fn test_keep_recent() {
+        let (store, state, roots) = run_blocks(PruningMode::KeepRecent(3));
+        let stats = state.pruning_stats();
+        assert_eq!(stats.blocks_pruned, 8);
+        assert!(stats.nodes_removed > 0 && stats.bytes_reclaimed > 0);
+
+        for (number, root) in roots.iter().enumerate() {
+            let view = StateManager::at_root(store.clone(), *root);
+            let readable = view.get_account(&Address([0; 20])).is_ok();
+            assert_eq!(readable, number >= 7, "block {number}");
+        }
+
+        // Only the retained states' nodes remain
+        let (archive, _, _) = run_blocks(PruningMode::Archive);
+        assert!(store.len(Column::StateNodes) < archive.len(Column::StateNodes));
+        assert_eq!(store.len(Column::StateJournal), 2);
+    }
+
+    #[test]
+    fn test_archive_keeps_everything() {
+        let (store, state, roots) = run_blocks(PruningMode::Archive);
+        assert_eq!(state.pruning_stats(), PruningStats::default());
+        assert_eq!(store.len(Column::StateJournal), 0);
+
+        for root in roots {
+            let view = StateManager::at_root(store.clone(), root);
+            assert!(view.get_account(&Address([0; 20])).unwrap().is_some());
+        }
+    }
+}
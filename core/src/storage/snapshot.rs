@@ -0,0 +1,614 @@
+//! Flat state snapshots
+//!
+//! A snapshot lists every account, storage slot and distinct contract code
+//! of one state root, split into chunks of roughly [`SNAPSHOT_CHUNK_SIZE`] bytes. The manifest
+//! records the block number, the state root and the hash of each chunk, so
+//! chunks can be fetched from untrusted sources and checked one by one, and
+//! the restored state is checked against the root at the end.
+//!
+//! On disk a snapshot is a directory holding `manifest.bin` and
+//! `chunk-NNNNN.bin` files. An exported snapshot also holds the block it was
+//! taken at and the genesis hash of its chain in `block.bin`, so that a node
+//! can start its chain there.
+
+use super::{
+    BlockStore, Column, KeyValueRead, KeyValueStore, StateManager, StorageError, StorageResult,
+    Trie, EMPTY_TRIE_ROOT,
+};
+use crate::config::StorageConfig;
+use crate::crypto::merkle::transactions_root;
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Snapshot format version
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// Target size of an encoded chunk
+pub const SNAPSHOT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Periodic snapshots kept on disk
+pub const SNAPSHOTS_KEPT: usize = 2;
+
+const MANIFEST_FILE: &str = "manifest.bin";
+
+const BLOCK_FILE: &str = "block.bin";
+
+/// Single snapshot item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SnapshotEntry {
+    /// Account, followed by its storage entries
+    Account {
+        /// Address
+        address: Address,
+        /// Account state
+        account: Account,
+    },
+    /// Contract storage slot
+    Storage {
+        /// Owning contract
+        address: Address,
+        /// Storage key
+        key: Vec<u8>,
+        /// Stored value
+        value: Vec<u8>,
+    },
+    /// Contract code, after the first account using it
+    Code {
+        /// Code bytes
+        code: Vec<u8>,
+    },
+}
+
+/// Snapshot description
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// Format version
+    pub version: u32,
+    /// Block whose post-state was captured
+    pub block_number: BlockNumber,
+    /// State root the chunks rebuild
+    pub state_root: BlockHash,
+    /// blake3 hash of each encoded chunk, in order
+    pub chunk_hashes: Vec<[u8; 32]>,
+}
+
+impl SnapshotManifest {
+    /// Check a chunk against its hash
+    pub fn verify_chunk(&self, index: usize, chunk: &[u8]) -> bool {
+        self.chunk_hashes
+            .get(index)
+            .is_some_and(|hash| hash == blake3::hash(chunk).as_bytes())
+    }
+}
+
+fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8], what: &str) -> StorageResult<T> {
+    bincode::deserialize(bytes)
+        .map_err(|e| StorageError::InvalidSnapshot(format!("{}: {}", what, e)))
+}
+
+/// Accumulates entries and emits a chunk whenever the size target is reached
+struct ChunkWriter<F> {
+    sink: F,
+    entries: Vec<SnapshotEntry>,
+    size: usize,
+    hashes: Vec<[u8; 32]>,
+}
+
+impl<F: FnMut(usize, &[u8]) -> StorageResult<()>> ChunkWriter<F> {
+    fn push(&mut self, entry: SnapshotEntry) -> StorageResult<()> {
+        self.size += bincode::serialized_size(&entry).unwrap() as usize;
+        self.entries.push(entry);
+        if self.size >= SNAPSHOT_CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StorageResult<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let bytes = bincode::serialize(&self.entries).unwrap();
+        (self.sink)(self.hashes.len(), &bytes)?;
+        self.hashes.push(blake3::hash(&bytes).into());
+        self.entries.clear();
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Encode the state at `root` into chunks, passing each to `sink` in order
+pub fn create_chunks<S: KeyValueRead + ?Sized>(
+    store: &S,
+    root: BlockHash,
+    number: BlockNumber,
+    sink: impl FnMut(usize, &[u8]) -> StorageResult<()>,
+) -> StorageResult<SnapshotManifest> {
+    let mut writer = ChunkWriter {
+        sink,
+        entries: Vec::new(),
+        size: 0,
+        hashes: Vec::new(),
+    };
+
+    let mut code_written = HashSet::new();
+    for entry in Trie::new(store, root).iter() {
+        let (key, value) = entry?;
+        let address = Address(key.try_into().map_err(|_| {
+            StorageError::Corrupted("Invalid account key in state trie".to_string())
+        })?);
+        let account: Account = bincode::deserialize(&value)
+            .map_err(|e| StorageError::Corrupted(format!("Account {}: {}", address, e)))?;
+        let storage_root = account.storage_root;
+        let code_hash = account.code_hash;
+
+        writer.push(SnapshotEntry::Account { address, account })?;
+        if let Some(hash) = code_hash.filter(|hash| code_written.insert(*hash)) {
+            let code = store
+                .get(Column::Code, &hash.0)?
+                .ok_or_else(|| StorageError::Corrupted(format!("Missing code {}", hash)))?;
+            writer.push(SnapshotEntry::Code { code })?;
+        }
+        for slot in Trie::new(store, storage_root).iter() {
+            let (key, value) = slot?;
+            writer.push(SnapshotEntry::Storage {
+                address,
+                key,
+                value,
+            })?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(SnapshotManifest {
+        version: SNAPSHOT_VERSION,
+        block_number: number,
+        state_root: root,
+        chunk_hashes: writer.hashes,
+    })
+}
+
+/// Rebuilds state from the chunks of a manifest
+///
+/// Each chunk is written to the store as it is applied. The state only counts
+/// as restored once [`SnapshotRestore::finish`] has checked the root; the
+/// nodes of an abandoned restore are unreachable from any known root.
+pub struct SnapshotRestore {
+    state: StateManager,
+    manifest: SnapshotManifest,
+    next_chunk: usize,
+    code_hashes: HashSet<BlockHash>,
+}
+
+impl SnapshotRestore {
+    /// Start restoring into `store`
+    pub fn new(store: Arc<dyn KeyValueStore>, manifest: SnapshotManifest) -> StorageResult<Self> {
+        if manifest.version != SNAPSHOT_VERSION {
+            return Err(StorageError::InvalidSnapshot(format!(
+                "Unsupported version {}",
+                manifest.version
+            )));
+        }
+        Ok(Self {
+            state: StateManager::new(store),
+            manifest,
+            next_chunk: 0,
+            code_hashes: HashSet::new(),
+        })
+    }
+
+    /// Manifest being restored
+    pub fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+
+    /// Index of the next chunk expected
+    pub fn next_chunk(&self) -> usize {
+        self.next_chunk
+    }
+
+    /// Check whether all chunks have been applied
+    pub fn is_complete(&self) -> bool {
+        self.next_chunk == self.manifest.chunk_hashes.len()
+    }
+
+    /// Verify and apply the next chunk
+    pub fn apply_chunk(&mut self, chunk: &[u8]) -> StorageResult<()> {
+        if !self.manifest.verify_chunk(self.next_chunk, chunk) {
+            return Err(StorageError::InvalidSnapshot(format!(
+                "Chunk {} does not match manifest",
+                self.next_chunk
+            )));
+        }
+
+        let entries: Vec<SnapshotEntry> = decode(chunk, "Chunk")?;
+        for entry in entries {
+            match entry {
+                SnapshotEntry::Account {
+                    address,
+                    mut account,
+                } => {
+                    // Rebuilt from the storage entries that follow
+                    account.storage_root = BlockHash(EMPTY_TRIE_ROOT);
+                    self.code_hashes.extend(account.code_hash);
+                    self.state.set_account(address, account);
+                }
+                SnapshotEntry::Storage {
+                    address,
+                    key,
+                    value,
+                } => self.state.set_storage(address, key, value),
+                SnapshotEntry::Code { code } => {
+                    self.state.insert_code(code);
+                }
+            }
+        }
+
+        // A restored state replaces nothing, so no journal is written
+        let (root, changes) = self.state.prepare_commit()?;
+        self.state.write_commit(
+            (self.manifest.block_number, BlockHash([0; 32])),
+            root,
+            changes,
+        )?;
+        self.next_chunk += 1;
+        Ok(())
+    }
+
+    /// Check the restored state against the manifest
+    pub fn finish(self) -> StorageResult<SnapshotManifest> {
+        if !self.is_complete() {
+            return Err(StorageError::InvalidSnapshot(format!(
+                "Missing chunks from {}",
+                self.next_chunk
+            )));
+        }
+
+        for hash in &self.code_hashes {
+            if self.state.get_code(hash)?.is_none() {
+                return Err(StorageError::InvalidSnapshot(format!("Missing code {}", hash)));
+            }
+        }
+
+        let root = self.state.state_root();
+        if root != self.manifest.state_root {
+            return Err(StorageError::InvalidSnapshot(format!(
+                "State root mismatch: expected {}, got {}",
+                self.manifest.state_root, root
+            )));
+        }
+        Ok(self.manifest)
+    }
+}
+
+/// Snapshot directory
+pub struct SnapshotDir {
+    path: PathBuf,
+}
+
+impl SnapshotDir {
+    /// Open an existing or new directory
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Directory path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn chunk_path(&self, index: usize) -> PathBuf {
+        self.path.join(format!("chunk-{:05}.bin", index))
+    }
+
+    /// Write the state at `root` (the directory must not exist yet)
+    pub fn write<S: KeyValueRead + ?Sized>(
+        &self,
+        store: &S,
+        root: BlockHash,
+        number: BlockNumber,
+    ) -> StorageResult<SnapshotManifest> {
+        fs::create_dir_all(&self.path)?;
+        let manifest = create_chunks(store, root, number, |index, chunk| {
+            Ok(fs::write(self.chunk_path(index), chunk)?)
+        })?;
+        // Written last, so a directory without a manifest is incomplete
+        fs::write(
+            self.path.join(MANIFEST_FILE),
+            bincode::serialize(&manifest).unwrap(),
+        )?;
+        Ok(manifest)
+    }
+
+    /// Read the manifest
+    pub fn manifest(&self) -> StorageResult<SnapshotManifest> {
+        decode(&fs::read(self.path.join(MANIFEST_FILE))?, "Manifest")
+    }
+
+    /// Read a chunk (not verified)
+    pub fn chunk(&self, index: usize) -> StorageResult<Vec<u8>> {
+        Ok(fs::read(self.chunk_path(index))?)
+    }
+
+    /// Store the block the snapshot was taken at and the genesis hash of its
+    /// chain
+    pub fn write_block(&self, block: &Block, genesis_hash: BlockHash) -> StorageResult<()> {
+        Ok(fs::write(
+            self.path.join(BLOCK_FILE),
+            bincode::serialize(&(block, genesis_hash)).unwrap(),
+        )?)
+    }
+
+    /// Read the block the snapshot was taken at and the genesis hash of its
+    /// chain (not verified)
+    pub fn block(&self) -> StorageResult<(Block, BlockHash)> {
+        decode(&fs::read(self.path.join(BLOCK_FILE))?, "Block")
+    }
+
+    /// Copy to `dest`, verifying every chunk
+    pub fn copy_to(&self, dest: &SnapshotDir) -> StorageResult<SnapshotManifest> {
+        let manifest = self.manifest()?;
+        fs::create_dir_all(&dest.path)?;
+        for index in 0..manifest.chunk_hashes.len() {
+            let chunk = self.chunk(index)?;
+            if !manifest.verify_chunk(index, &chunk) {
+                return Err(StorageError::InvalidSnapshot(format!(
+                    "Chunk {} does not match manifest",
+                    index
+                )));
+            }
+            fs::write(dest.chunk_path(index), chunk)?;
+        }
+        fs::write(
+            dest.path.join(MANIFEST_FILE),
+            bincode::serialize(&manifest).unwrap(),
+        )?;
+        Ok(manifest)
+    }
+
+    /// Restore the snapshot into `store`
+    pub fn restore(&self, store: Arc<dyn KeyValueStore>) -> StorageResult<SnapshotManifest> {
+        let mut restore = SnapshotRestore::new(store, self.manifest()?)?;
+        while !restore.is_complete() {
+            restore.apply_chunk(&self.chunk(restore.next_chunk())?)?;
+        }
+        restore.finish()
+    }
+
+    /// Restore the snapshot into `store`, which must hold no blocks, and
+    /// start its chain at the snapshot block
+    pub fn restore_chain(&self, store: Arc<dyn KeyValueStore>) -> StorageResult<BlockHash> {
+        let blocks = BlockStore::new(store.clone());
+        if let Some(head) = blocks.head_hash()? {
+            return Err(StorageError::Corrupted(format!(
+                "Cannot restore a snapshot below existing head {}",
+                head
+            )));
+        }
+
+        let (block, genesis_hash) = self.block()?;
+        let manifest = self.manifest()?;
+        if block.header.number != manifest.block_number
+            || block.header.state_root != manifest.state_root
+            || block.header.transactions_root != transactions_root(&block.transactions)
+        {
+            return Err(StorageError::InvalidSnapshot(
+                "Block does not match the snapshot".to_string(),
+            ));
+        }
+        self.restore(store)?;
+        blocks.set_base(&block, genesis_hash)
+    }
+}
+
+/// Periodic snapshots of a node, one directory per block number
+pub struct SnapshotStore {
+    dir: PathBuf,
+    interval: u64,
+}
+
+impl SnapshotStore {
+    /// Store snapshots under `dir` every `interval` blocks
+    pub fn new(dir: impl Into<PathBuf>, interval: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            interval,
+        }
+    }
+
+    /// Store in the configured node's snapshot directory
+    pub fn for_node(config: &StorageConfig) -> Self {
+        Self::new(
+            Path::new(&config.db_path).join("snapshots"),
+            config.snapshot_interval,
+        )
+    }
+
+    /// Store taking periodic snapshots (None if snapshots are disabled)
+    pub fn from_config(config: &StorageConfig) -> Option<Self> {
+        config.enable_snapshots.then(|| Self::for_node(config))
+    }
+
+    /// Check whether a snapshot is due after block `number`
+    pub fn is_due(&self, number: BlockNumber) -> bool {
+        number > 0 && number.is_multiple_of(self.interval)
+    }
+
+    /// Snapshot directory for a block
+    pub fn snapshot(&self, number: BlockNumber) -> SnapshotDir {
+        SnapshotDir::new(self.dir.join(number.to_string()))
+    }
+
+    /// Block numbers of complete snapshots, ascending
+    pub fn snapshots(&self) -> StorageResult<Vec<BlockNumber>> {
+        let mut numbers = Vec::new();
+        if !self.dir.exists() {
+            return Ok(numbers);
+        }
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let number = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse().ok());
+            if let Some(number) = number {
+                if entry.path().join(MANIFEST_FILE).exists() {
+                    numbers.push(number);
+                }
+            }
+        }
+        numbers.sort_unstable();
+        Ok(numbers)
+    }
+
+    /// Most recent complete snapshot
+    pub fn latest(&self) -> StorageResult<Option<BlockNumber>> {
+        Ok(self.snapshots()?.last().copied())
+    }
+
+    /// Write a snapshot if one is due, removing the oldest beyond [`SNAPSHOTS_KEPT`]
+    pub fn on_block<S: KeyValueRead + ?Sized>(
+        &self,
+        store: &S,
+        root: BlockHash,
+        number: BlockNumber,
+    ) -> StorageResult<Option<SnapshotManifest>> {
+        if !self.is_due(number) {
+            return Ok(None);
+        }
+
+        let snapshot = self.snapshot(number);
+        if snapshot.path().exists() {
+            fs::remove_dir_all(snapshot.path())?;
+        }
+        let manifest = snapshot.write(store, root, number)?;
+
+        let snapshots = self.snapshots()?;
+        for old in &snapshots[..snapshots.len().saturating_sub(SNAPSHOTS_KEPT)] {
+            fs::remove_dir_all(self.snapshot(*old).path())?;
+        }
+        Ok(Some(manifest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{has_state, MemoryStore};
+    use crate::test_utils::empty_block;
+
+    fn populated_state() -> (Arc<MemoryStore>, BlockHash) {
+        let store = Arc::new(MemoryStore::new());
+        let mut state = StateManager::new(store.clone());
+        for i in 0..50u8 {
+            let mut account = Account {
+                nonce: i as u64,
+                ..Default::default()
+            };
+            if i % 10 == 0 {
+                account.code_hash = Some(state.insert_code(vec![i % 20]));
+            }
+            state.set_account(Address([i; 20]), account);
+            if i % 10 == 0 {
+                for slot in 0..5u8 {
+                    state.set_storage(Address([i; 20]), vec![slot], vec![i, slot]);
+                }
+            }
+        }
+        let root = state.commit(1).unwrap();
+        (store, root)
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let (store, root) = populated_state();
+        let dir =
+            std::env::temp_dir().join(format!("quantumchain-snapshot-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let snapshots = SnapshotStore::new(&dir, 10);
+        assert!(snapshots.on_block(&*store, root, 5).unwrap().is_none());
+        for number in [10, 20, 30] {
+            snapshots.on_block(&*store, root, number).unwrap().unwrap();
+        }
+        assert_eq!(snapshots.snapshots().unwrap(), vec![20, 30]);
+
+        let target = Arc::new(MemoryStore::new());
+        let manifest = snapshots.snapshot(30).restore(target.clone()).unwrap();
+        assert_eq!(manifest.state_root, root);
+
+        let restored = StateManager::at_root(target.clone(), root);
+        assert_eq!(
+            restored
+                .get_account(&Address([7; 20]))
+                .unwrap()
+                .unwrap()
+                .nonce,
+            7
+        );
+        assert_eq!(
+            restored.get_storage(&Address([20; 20]), &[3]).unwrap(),
+            Some(vec![20, 3])
+        );
+        // Contracts sharing code restore a single copy
+        assert_eq!(target.len(Column::Code), 2);
+        let code_hash = restored.get_account(&Address([30; 20])).unwrap().unwrap().code_hash;
+        assert_eq!(restored.get_code(&code_hash.unwrap()).unwrap(), Some(vec![10]));
+
+        // An exported snapshot carries its block and restores a whole chain
+        let export = SnapshotDir::new(dir.join("export"));
+        snapshots.snapshot(30).copy_to(&export).unwrap();
+        let mut block = empty_block(None, root);
+        block.header.number = 30;
+        let genesis_hash = BlockHash([7; 32]);
+        export.write_block(&block, genesis_hash).unwrap();
+        let chain = Arc::new(MemoryStore::new());
+        assert_eq!(export.restore_chain(chain.clone()).unwrap(), block.hash());
+        let blocks = BlockStore::new(chain.clone());
+        assert_eq!(blocks.head_hash().unwrap(), Some(block.hash()));
+        assert_eq!(blocks.base().unwrap(), 30);
+        assert_eq!(blocks.genesis_hash().unwrap(), Some(genesis_hash));
+        assert!(export.restore_chain(chain).is_err());
+
+        block.header.number = 20;
+        export.write_block(&block, genesis_hash).unwrap();
+        assert!(matches!(
+            export.restore_chain(Arc::new(MemoryStore::new())),
+            Err(StorageError::InvalidSnapshot(_))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_chunks() {
+        let (store, root) = populated_state();
+        let mut chunks = Vec::new();
+        let manifest = create_chunks(&*store, root, 1, |_, chunk| {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        })
+        .unwrap();
+
+        let mut restore =
+            SnapshotRestore::new(Arc::new(MemoryStore::new()), manifest.clone()).unwrap();
+        let mut tampered = chunks[0].clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(restore.apply_chunk(&tampered).is_err());
+
+        // Consistent chunks for a different root are caught at the end
+        let mut wrong_root = manifest;
+        wrong_root.state_root = BlockHash([1; 32]);
+        let target = Arc::new(MemoryStore::new());
+        let mut restore = SnapshotRestore::new(target.clone(), wrong_root).unwrap();
+        for chunk in &chunks {
+            restore.apply_chunk(chunk).unwrap();
+        }
+        assert!(restore.finish().is_err());
+        assert!(!has_state(&*target, &BlockHash([1; 32])).unwrap());
+    }
+}
@@ -1,28 +1,458 @@
-//! State management with Verkle trees
+//! State management over authenticated tries
+//!
+//! Accounts live in a trie keyed by address whose root is the block's
+//! `state_root`. Each contract's storage is a separate trie rooted at
+//! `Account::storage_root`. Nodes of both share [`Column::StateNodes`].
+//!
+//! Contract code is stored once per distinct code in [`Column::Code`], keyed
+//! by `Account::code_hash`. Code is never pruned.
 
+use super::{
+    pruning, AccountProof, Column, KeyValueRead, KeyValueStore, NodeChanges, PruningMode,
+    PruningStats, StorageError, StorageProof, StorageResult, Trie, WriteBatch, EMPTY_TRIE_ROOT,
+};
 use crate::types::*;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// State access used during transaction execution
+pub trait AccountState {
+    /// Get an account (None if it has never been written)
+    fn get_account(&self, address: &Address) -> StorageResult<Option<Account>>;
+
+    /// Create or overwrite an account
+    fn set_account(&mut self, address: Address, account: Account);
+
+    /// Get a contract storage value
+    fn get_storage(&self, address: &Address, key: &[u8]) -> StorageResult<Option<Vec<u8>>>;
+
+    /// Set (Some) or clear (None) a contract storage value
+    fn set_storage(&mut self, address: Address, key: Vec<u8>, value: Option<Vec<u8>>);
+
+    /// Get contract code by hash
+    fn get_code(&self, hash: &BlockHash) -> StorageResult<Option<Vec<u8>>>;
+
+    /// Store contract code, returning its hash
+    fn insert_code(&mut self, code: Vec<u8>) -> BlockHash;
+}
+
+/// Hash identifying contract code (`Account::code_hash`)
+pub fn code_hash(code: &[u8]) -> BlockHash {
+    BlockHash(blake3::hash(code).into())
+}
+
+/// Check whether the state at `root` can be read (not pruned)
+///
+/// A retained trie node keeps its whole subtree retained, so checking the
+/// root node is enough.
+pub fn has_state<S: KeyValueRead + ?Sized>(store: &S, root: &BlockHash) -> StorageResult<bool> {
+    Ok(root.0 == EMPTY_TRIE_ROOT || store.contains(Column::StateNodes, &root.0)?)
+}
+
+fn load_account<S: KeyValueRead + ?Sized>(
+    store: &S,
+    root: BlockHash,
+    address: &Address,
+) -> StorageResult<Option<Account>> {
+    Trie::new(store, root)
+        .get(&address.0)?
+        .map(|bytes| {
+            bincode::deserialize(&bytes)
+                .map_err(|e| StorageError::Corrupted(format!("Account {}: {}", address, e)))
+        })
+        .transpose()
+}
+
+/// Read-only view of a committed state root
+#[derive(Clone)]
+pub struct StateView {
+    store: Arc<dyn KeyValueStore>,
+    root: BlockHash,
+}
+
+impl StateView {
+    /// View the state at `root`, failing if it has been pruned
+    pub fn new(store: Arc<dyn KeyValueStore>, root: BlockHash) -> StorageResult<Self> {
+        if !has_state(&*store, &root)? {
+            return Err(StorageError::MissingState(root));
+        }
+        Ok(Self { store, root })
+    }
+
+    /// Root of the viewed state
+    pub fn state_root(&self) -> BlockHash {
+        self.root
+    }
+
+    /// Get an account
+    pub fn get_account(&self, address: &Address) -> StorageResult<Option<Account>> {
+        load_account(&*self.store, self.root, address)
+    }
+
+    /// Balance of an account (zero if it does not exist)
+    pub fn balance(&self, address: &Address) -> StorageResult<Balance> {
+        Ok(self
+            .get_account(address)?
+            .map_or(Balance::ZERO, |account| account.balance))
+    }
+
+    /// Get a contract storage value
+    pub fn get_storage(&self, address: &Address, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        self.storage_trie(address)?.get(key)
+    }
+
+    /// Storage trie of a contract, for iterating its slots
+    pub fn storage_trie(&self, address: &Address) -> StorageResult<Trie<'_, dyn KeyValueStore>> {
+        let root = self
+            .get_account(address)?
+            .map_or(BlockHash(EMPTY_TRIE_ROOT), |account| account.storage_root);
+        Ok(Trie::new(&*self.store, root))
+    }
+
+    /// Get contract code by hash
+    pub fn get_code(&self, hash: &BlockHash) -> StorageResult<Option<Vec<u8>>> {
+        self.store.get(Column::Code, &hash.0)
+    }
+}
 
 /// State manager
+///
+/// Writes are buffered until [`StateManager::commit`], which applies them to
+/// the tries and persists the new nodes in a single batch once per block.
 pub struct StateManager {
-    // TODO: Implement Verkle trees
+    store: Arc<dyn KeyValueStore>,
+    root: BlockHash,
+    accounts: HashMap<Address, Account>,
+    storage: HashMap<Address, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    code: HashMap<BlockHash, Vec<u8>>,
+    pruning: PruningMode,
+    pruning_stats: PruningStats,
 }
 
 impl StateManager {
-    pub fn new() -> Self {
-        Self {}
+    /// Empty state on top of a store
+    pub fn new(store: Arc<dyn KeyValueStore>) -> Self {
+        Self::at_root(store, BlockHash(EMPTY_TRIE_ROOT))
     }
-    
-    pub fn get_account(&self, _address: &Address) -> Option<Account> {
-        None
+
+    /// State as of a committed root
+    pub fn at_root(store: Arc<dyn KeyValueStore>, root: BlockHash) -> Self {
+        Self {
+            store,
+            root,
+            accounts: HashMap::new(),
+            storage: HashMap::new(),
+            code: HashMap::new(),
+            pruning: PruningMode::Archive,
+            pruning_stats: PruningStats::default(),
+        }
+    }
+
+    /// Set how long replaced state is kept (archive by default)
+    pub fn with_pruning(mut self, mode: PruningMode) -> Self {
+        self.pruning = mode;
+        self
+    }
+
+    /// Space reclaimed by pruning since this manager was created
+    pub fn pruning_stats(&self) -> PruningStats {
+        self.pruning_stats
+    }
+
+    /// Root of the last committed state (pending writes not included)
+    pub fn state_root(&self) -> BlockHash {
+        self.root
+    }
+
+    /// Check whether there are uncommitted writes
+    pub fn has_pending_changes(&self) -> bool {
+        !self.accounts.is_empty() || !self.storage.is_empty() || !self.code.is_empty()
     }
-    
-    pub fn set_account(&mut self, _address: Address, _account: Account) {
-        // TODO
+
+    /// Read-only view of the last committed state
+    pub fn view(&self) -> StateView {
+        StateView {
+            store: self.store.clone(),
+            root: self.root,
+        }
+    }
+
+    /// Read-only view of an earlier state, failing if it has been pruned
+    pub fn view_at(&self, root: BlockHash) -> StorageResult<StateView> {
+        StateView::new(self.store.clone(), root)
+    }
+
+    fn committed_account(&self, address: &Address) -> StorageResult<Option<Account>> {
+        load_account(&*self.store, self.root, address)
+    }
+
+    /// Get an account, including pending writes
+    pub fn get_account(&self, address: &Address) -> StorageResult<Option<Account>> {
+        match self.accounts.get(address) {
+            Some(account) => Ok(Some(account.clone())),
+            None => self.committed_account(address),
+        }
+    }
+
+    /// Create or overwrite an account
+    pub fn set_account(&mut self, address: Address, account: Account) {
+        self.accounts.insert(address, account);
+    }
+
+    /// Get a contract storage value, including pending writes
+    pub fn get_storage(&self, address: &Address, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        if let Some(value) = self.storage.get(address).and_then(|slots| slots.get(key)) {
+            return Ok(value.clone());
+        }
+
+        // Storage roots of pending accounts are only updated at commit
+        let Some(account) = self.committed_account(address)? else {
+            return Ok(None);
+        };
+        Trie::new(&*self.store, account.storage_root).get(key)
+    }
+
+    /// Set a contract storage value
+    pub fn set_storage(&mut self, address: Address, key: Vec<u8>, value: Vec<u8>) {
+        self.storage.entry(address).or_default().insert(key, Some(value));
+    }
+
+    /// Remove a contract storage value
+    pub fn remove_storage(&mut self, address: Address, key: Vec<u8>) {
+        self.storage.entry(address).or_default().insert(key, None);
+    }
+
+    /// Committed storage trie of a contract, for iterating its slots
+    ///
+    /// Pending writes are not included.
+    pub fn storage_trie(&self, address: &Address) -> StorageResult<Trie<'_, dyn KeyValueStore>> {
+        let root = self
+            .committed_account(address)?
+            .map_or(BlockHash(EMPTY_TRIE_ROOT), |account| account.storage_root);
+        Ok(Trie::new(&*self.store, root))
+    }
+
+    /// Get contract code by hash, including pending code
+    pub fn get_code(&self, hash: &BlockHash) -> StorageResult<Option<Vec<u8>>> {
+        match self.code.get(hash) {
+            Some(code) => Ok(Some(code.clone())),
+            None => self.store.get(Column::Code, &hash.0),
+        }
+    }
+
+    /// Store contract code, returning its hash
+    pub fn insert_code(&mut self, code: Vec<u8>) -> BlockHash {
+        let hash = code_hash(&code);
+        self.code.insert(hash, code);
+        hash
+    }
+
+    /// Proof of an account against [`StateManager::state_root`]
+    ///
+    /// Pending writes are not included; commit first to prove them.
+    pub fn prove_account(&self, address: &Address) -> StorageResult<AccountProof> {
+        Ok(AccountProof {
+            address: *address,
+            account: self.committed_account(address)?,
+            proof: Trie::new(&*self.store, self.root).prove(&address.0)?,
+        })
+    }
+
+    /// Proof of a storage slot against [`StateManager::state_root`]
+    pub fn prove_storage(&self, address: &Address, key: &[u8]) -> StorageResult<StorageProof> {
+        let account = self.prove_account(address)?;
+        let storage_root = account
+            .account
+            .as_ref()
+            .map_or(BlockHash(EMPTY_TRIE_ROOT), |a| a.storage_root);
+        let trie = Trie::new(&*self.store, storage_root);
+
+        Ok(StorageProof {
+            account,
+            key: key.to_vec(),
+            value: trie.get(key)?,
+            proof: trie.prove(key)?,
+        })
+    }
+
+    /// Apply the pending writes of block `number` and persist them, returning
+    /// the new state root
+    ///
+    /// With pruning enabled, this also releases state that falls out of the
+    /// retained history. The commit is not tied to a stored block, so it must
+    /// not be used for forks; block import journals under the block hash.
+    pub fn commit(&mut self, number: BlockNumber) -> StorageResult<BlockHash> {
+        let (root, changes) = self.prepare_commit()?;
+        self.write_commit((number, BlockHash([0; 32])), root, changes)?;
+        Ok(root)
+    }
+
+    /// Apply pending writes to the tries without persisting anything
+    pub(crate) fn prepare_commit(&mut self) -> StorageResult<(BlockHash, NodeChanges)> {
+        let mut changes = NodeChanges::new();
+
+        let storage = std::mem::take(&mut self.storage);
+        for (address, slots) in storage {
+            let mut account = self.get_account(&address)?.unwrap_or_default();
+            let mut trie = Trie::new(&*self.store, account.storage_root);
+            for (key, value) in slots {
+                match value {
+                    Some(value) => trie.insert(&key, value)?,
+                    None => trie.remove(&key)?,
+                }
+            }
+            account.storage_root = trie.commit(&mut changes);
+            self.accounts.insert(address, account);
+        }
+
+        let mut trie = Trie::new(&*self.store, self.root);
+        for (address, account) in std::mem::take(&mut self.accounts) {
+            trie.insert(&address.0, bincode::serialize(&account).unwrap())?;
+        }
+        let root = trie.commit(&mut changes);
+        Ok((root, changes))
+    }
+
+    /// Persist the result of [`StateManager::prepare_commit`] for a block
+    pub(crate) fn write_commit(
+        &mut self,
+        (number, hash): (BlockNumber, BlockHash),
+        root: BlockHash,
+        changes: NodeChanges,
+    ) -> StorageResult<()> {
+        let mut batch = WriteBatch::new();
+        for (hash, code) in std::mem::take(&mut self.code) {
+            if !self.store.contains(Column::Code, &hash.0)? {
+                batch.put(Column::Code, hash.0, code);
+            }
+        }
+        let reclaimed = pruning::write_changes(
+            &*self.store,
+            self.pruning,
+            (number, hash),
+            changes,
+            &mut batch,
+        )?;
+        self.store.write(batch)?;
+
+        if reclaimed.nodes_removed > 0 {
+            tracing::debug!(
+                "Pruned {} state nodes ({} bytes) at block {}",
+                reclaimed.nodes_removed,
+                reclaimed.bytes_reclaimed,
+                number
+            );
+        }
+        self.pruning_stats += reclaimed;
+        self.root = root;
+        Ok(())
+    }
+
+    /// Drop pending writes
+    pub fn discard(&mut self) {
+        self.accounts.clear();
+        self.storage.clear();
+        self.code.clear();
     }
 }
 
-impl Default for StateManager {
-    fn default() -> Self {
-        Self::new()
+impl AccountState for StateManager {
+    fn get_account(&self, address: &Address) -> StorageResult<Option<Account>> {
+        StateManager::get_account(self, address)
+    }
+
+    fn set_account(&mut self, address: Address, account: Account) {
+        StateManager::set_account(self, address, account)
+    }
+
+    fn get_storage(&self, address: &Address, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        StateManager::get_storage(self, address, key)
+    }
+
+    fn set_storage(&mut self, address: Address, key: Vec<u8>, value: Option<Vec<u8>>) {
+        match value {
+            Some(value) => StateManager::set_storage(self, address, key, value),
+            None => StateManager::remove_storage(self, address, key),
+        }
+    }
+
+    fn get_code(&self, hash: &BlockHash) -> StorageResult<Option<Vec<u8>>> {
+        StateManager::get_code(self, hash)
+    }
+
+    fn insert_code(&mut self, code: Vec<u8>) -> BlockHash {
+        StateManager::insert_code(self, code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+
+    #[test]
+    fn test_commit_accounts_and_storage() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut state = StateManager::new(store.clone());
+        let alice = Address([1; 20]);
+        let contract = Address([2; 20]);
+
+        state.set_account(alice, Account { nonce: 3, ..Default::default() });
+        state.set_storage(contract, b"slot".to_vec(), b"value".to_vec());
+        assert_eq!(state.state_root(), BlockHash(EMPTY_TRIE_ROOT));
+        assert_eq!(state.get_storage(&contract, b"slot").unwrap(), Some(b"value".to_vec()));
+
+        let root = state.commit(0).unwrap();
+        assert_ne!(root, BlockHash(EMPTY_TRIE_ROOT));
+        assert!(!state.has_pending_changes());
+
+        let reopened = StateManager::at_root(store.clone(), root);
+        assert_eq!(reopened.get_account(&alice).unwrap().unwrap().nonce, 3);
+        assert_eq!(reopened.get_storage(&contract, b"slot").unwrap(), Some(b"value".to_vec()));
+        let storage_root = reopened.get_account(&contract).unwrap().unwrap().storage_root;
+        assert_ne!(storage_root, BlockHash(EMPTY_TRIE_ROOT));
+
+        // Clearing the only slot empties the storage trie again
+        state.remove_storage(contract, b"slot".to_vec());
+        state.commit(1).unwrap();
+        assert_eq!(state.get_storage(&contract, b"slot").unwrap(), None);
+        let account = state.get_account(&contract).unwrap().unwrap();
+        assert_eq!(account.storage_root, BlockHash(EMPTY_TRIE_ROOT));
+
+        // The earlier root is still readable
+        let old = StateManager::at_root(store, root);
+        assert_eq!(old.get_storage(&contract, b"slot").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn test_code_and_storage_iteration() {
+        let store = Arc::new(MemoryStore::new());
+        let mut state = StateManager::new(store.clone());
+
+        // Two contracts with the same code share one stored copy
+        for i in 1..=2u8 {
+            let hash = state.insert_code(b"contract code".to_vec());
+            let account = Account { code_hash: Some(hash), ..Default::default() };
+            state.set_account(Address([i; 20]), account);
+            for slot in 0..5u8 {
+                state.set_storage(Address([i; 20]), vec![slot], vec![i, slot]);
+            }
+        }
+        state.commit(0).unwrap();
+        assert_eq!(store.len(Column::Code), 1);
+
+        let account = state.get_account(&Address([2; 20])).unwrap().unwrap();
+        let code = state.get_code(&account.code_hash.unwrap()).unwrap();
+        assert_eq!(code, Some(b"contract code".to_vec()));
+
+        let mut slots: Vec<_> = state
+            .storage_trie(&Address([2; 20]))
+            .unwrap()
+            .iter()
+            .map(Result::unwrap)
+            .collect();
+        slots.sort();
+        assert_eq!(slots, (0..5u8).map(|slot| (vec![slot], vec![2, slot])).collect::<Vec<_>>());
+        assert_eq!(state.storage_trie(&Address([3; 20])).unwrap().iter().count(), 0);
     }
 }
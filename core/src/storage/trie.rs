@@ -0,0 +1,476 @@
+//! Authenticated binary trie
+//!
+//! Keys are located by the bits of `blake3(key)`. A leaf sits at the
+//! shallowest depth where its path is unique, and branches left with a single
+//! leaf collapse into it, so the root depends only on the key/value set and
+//! not on the order of updates.
+//!
+//! Nodes are content-addressed: they are stored in [`Column::StateNodes`]
+//! under their hash and never modified in place, so any committed root stays
+//! readable until its nodes are pruned.
+
+use super::{Column, KeyValueRead, ProofLeaf, StorageError, StorageResult, TrieProof};
+use crate::crypto::merkle::hash_node;
+use crate::types::BlockHash;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const LEAF_PREFIX: u8 = 0x00;
+
+/// Hash of the empty trie and of empty subtrees
+pub const EMPTY_TRIE_ROOT: [u8; 32] = [0; 32];
+
+/// Trie node as stored in the database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrieNode {
+    /// Key/value pair
+    Leaf {
+        /// Original key
+        key: Vec<u8>,
+        /// Value
+        value: Vec<u8>,
+    },
+    /// Inner node (empty children are [`EMPTY_TRIE_ROOT`])
+    Branch {
+        /// Child for bit 0
+        left: [u8; 32],
+        /// Child for bit 1
+        right: [u8; 32],
+    },
+}
+
+impl TrieNode {
+    /// Node hash
+    pub fn hash(&self) -> [u8; 32] {
+        match self {
+            TrieNode::Leaf { key, value } => hash_leaf(&key_path(key), value),
+            TrieNode::Branch { left, right } => hash_node(left, right),
+        }
+    }
+
+    /// Database encoding
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    /// Decode a stored node
+    pub fn decode(bytes: &[u8]) -> StorageResult<Self> {
+        bincode::deserialize(bytes)
+            .map_err(|e| StorageError::Corrupted(format!("Trie node: {}", e)))
+    }
+}
+
+/// Path of a key through the trie
+pub fn key_path(key: &[u8]) -> [u8; 32] {
+    blake3::hash(key).into()
+}
+
+/// Hash of a leaf
+pub fn hash_leaf(path: &[u8; 32], value: &[u8]) -> [u8; 32] {
+    hash_leaf_digest(path, blake3::hash(value).as_bytes())
+}
+
+/// Hash of a leaf given the hash of its value
+pub fn hash_leaf_digest(path: &[u8; 32], value_hash: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(path);
+    hasher.update(value_hash);
+    hasher.finalize().into()
+}
+
+/// Bit of `path` at `depth` (most significant first)
+pub fn path_bit(path: &[u8; 32], depth: usize) -> bool {
+    (path[depth / 8] >> (7 - depth % 8)) & 1 == 1
+}
+
+/// Nodes created and dropped by one or more trie commits
+///
+/// Counts are multiplicities: identical subtrees in different tries (e.g. two
+/// contracts with the same storage) share nodes, so a node can be created or
+/// dropped more than once in a single commit.
+#[derive(Debug, Default)]
+pub struct NodeChanges {
+    /// New nodes reachable from the committed roots
+    pub inserted: HashMap<[u8; 32], (TrieNode, u32)>,
+    /// Previously persisted nodes no longer reachable
+    pub removed: HashMap<[u8; 32], u32>,
+}
+
+impl NodeChanges {
+    /// Create empty change set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel out nodes that were both dropped and re-created
+    pub fn net(&mut self) {
+        for (hash, (_, inserted)) in self.inserted.iter_mut() {
+            if let Some(removed) = self.removed.get_mut(hash) {
+                let common = (*inserted).min(*removed);
+                *inserted -= common;
+                *removed -= common;
+            }
+        }
+        self.inserted.retain(|_, (_, count)| *count > 0);
+        self.removed.retain(|_, count| *count > 0);
+    }
+}
+
+/// Trie over a key-value store
+///
+/// Updates are kept in memory until [`Trie::commit`] hands the new nodes, and
+/// the persisted nodes they replace, to a [`NodeChanges`].
+pub struct Trie<'a, S: KeyValueRead + ?Sized> {
+    store: &'a S,
+    root: [u8; 32],
+    dirty: HashMap<[u8; 32], TrieNode>,
+    removed: Vec<[u8; 32]>,
+}
+
+impl<'a, S: KeyValueRead + ?Sized> Trie<'a, S> {
+    /// Open the trie with the given root
+    pub fn new(store: &'a S, root: BlockHash) -> Self {
+        Self {
+            store,
+            root: root.0,
+            dirty: HashMap::new(),
+            removed: Vec::new(),
+        }
+    }
+
+    /// Current root, including uncommitted updates
+    pub fn root(&self) -> BlockHash {
+        BlockHash(self.root)
+    }
+
+    /// Load a node by hash
+    pub fn node(&self, hash: &[u8; 32]) -> StorageResult<TrieNode> {
+        if let Some(node) = self.dirty.get(hash) {
+            return Ok(node.clone());
+        }
+        let bytes = self.store.get(Column::StateNodes, hash)?.ok_or_else(|| {
+            StorageError::Corrupted(format!("Missing trie node {}", BlockHash(*hash)))
+        })?;
+        TrieNode::decode(&bytes)
+    }
+
+    /// Record that `hash` is no longer part of the trie
+    fn replaced(&mut self, hash: [u8; 32]) {
+        // Nodes created since the last commit were never persisted
+        if !self.dirty.contains_key(&hash) {
+            self.removed.push(hash);
+        }
+    }
+
+    fn put(&mut self, node: TrieNode) -> [u8; 32] {
+        let hash = node.hash();
+        self.dirty.insert(hash, node);
+        hash
+    }
+
+    /// Get the value stored under `key`
+    pub fn get(&self, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        let path = key_path(key);
+        let mut hash = self.root;
+        let mut depth = 0;
+
+        while hash != EMPTY_TRIE_ROOT {
+            match self.node(&hash)? {
+                TrieNode::Leaf { key: leaf_key, value } => {
+                    return Ok((leaf_key == key).then_some(value));
+                }
+                TrieNode::Branch { left, right } => {
+                    hash = if path_bit(&path, depth) { right } else { left };
+                    depth += 1;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// All entries, in path order
+    pub fn iter(&self) -> impl Iterator<Item = StorageResult<(Vec<u8>, Vec<u8>)>> + '_ {
+        let mut pending = vec![self.root];
+        std::iter::from_fn(move || {
+            while let Some(hash) = pending.pop() {
+                if hash == EMPTY_TRIE_ROOT {
+                    continue;
+                }
+                match self.node(&hash) {
+                    Ok(TrieNode::Leaf { key, value }) => return Some(Ok((key, value))),
+                    Ok(TrieNode::Branch { left, right }) => pending.extend([right, left]),
+                    Err(e) => {
+                        pending.clear();
+                        return Some(Err(e));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Inclusion or exclusion proof for `key` against the current root
+    pub fn prove(&self, key: &[u8]) -> StorageResult<TrieProof> {
+        let path = key_path(key);
+        let mut hash = self.root;
+        let mut siblings = Vec::new();
+
+        while hash != EMPTY_TRIE_ROOT {
+            match self.node(&hash)? {
+                TrieNode::Leaf { key, value } => {
+                    let leaf = ProofLeaf {
+                        path: key_path(&key),
+                        value_hash: blake3::hash(&value).into(),
+                    };
+                    return Ok(TrieProof {
+                        siblings,
+                        leaf: Some(leaf),
+                    });
+                }
+                TrieNode::Branch { left, right } => {
+                    let (next, sibling) = if path_bit(&path, siblings.len()) {
+                        (right, left)
+                    } else {
+                        (left, right)
+                    };
+                    siblings.push(sibling);
+                    hash = next;
+                }
+            }
+        }
+
+        Ok(TrieProof {
+            siblings,
+            leaf: None,
+        })
+    }
+
+    /// Insert or overwrite a value
+    pub fn insert(&mut self, key: &[u8], value: Vec<u8>) -> StorageResult<()> {
+        let leaf = TrieNode::Leaf {
+            key: key.to_vec(),
+            value,
+        };
+        self.root = self.insert_at(self.root, 0, &key_path(key), leaf)?;
+        Ok(())
+    }
+
+    fn insert_at(
+        &mut self,
+        hash: [u8; 32],
+        depth: usize,
+        path: &[u8; 32],
+        leaf: TrieNode,
+    ) -> StorageResult<[u8; 32]> {
+        if hash == EMPTY_TRIE_ROOT {
+            return Ok(self.put(leaf));
+        }
+
+        match self.node(&hash)? {
+            TrieNode::Leaf { key, .. } => {
+                let TrieNode::Leaf { key: new_key, .. } = &leaf else { unreachable!() };
+                if key == *new_key {
+                    self.replaced(hash);
+                    return Ok(self.put(leaf));
+                }
+                let new_leaf = self.put(leaf);
+                Ok(self.split(depth, (hash, key_path(&key)), (new_leaf, *path)))
+            }
+            TrieNode::Branch { mut left, mut right } => {
+                if path_bit(path, depth) {
+                    right = self.insert_at(right, depth + 1, path, leaf)?;
+                } else {
+                    left = self.insert_at(left, depth + 1, path, leaf)?;
+                }
+                self.replaced(hash);
+                Ok(self.put(TrieNode::Branch { left, right }))
+            }
+        }
+    }
+
+    /// Branches separating two leaves whose paths agree above `depth`
+    fn split(&mut self, depth: usize, a: ([u8; 32], [u8; 32]), b: ([u8; 32], [u8; 32])) -> [u8; 32] {
+        let (a_bit, b_bit) = (path_bit(&a.1, depth), path_bit(&b.1, depth));
+        let (left, right) = if a_bit == b_bit {
+            let child = self.split(depth + 1, a, b);
+            if a_bit {
+                (EMPTY_TRIE_ROOT, child)
+            } else {
+                (child, EMPTY_TRIE_ROOT)
+            }
+        } else if a_bit {
+            (b.0, a.0)
+        } else {
+            (a.0, b.0)
+        };
+        self.put(TrieNode::Branch { left, right })
+    }
+
+    /// Remove a value (no-op if absent)
+    pub fn remove(&mut self, key: &[u8]) -> StorageResult<()> {
+        self.root = self.remove_at(self.root, 0, &key_path(key), key)?;
+        Ok(())
+    }
+
+    fn remove_at(
+        &mut self,
+        hash: [u8; 32],
+        depth: usize,
+        path: &[u8; 32],
+        key: &[u8],
+    ) -> StorageResult<[u8; 32]> {
+        if hash == EMPTY_TRIE_ROOT {
+            return Ok(hash);
+        }
+
+        match self.node(&hash)? {
+            TrieNode::Leaf { key: leaf_key, .. } => {
+                if leaf_key != key {
+                    return Ok(hash);
+                }
+                self.replaced(hash);
+                Ok(EMPTY_TRIE_ROOT)
+            }
+            TrieNode::Branch { mut left, mut right } => {
+                let before = (left, right);
+                if path_bit(path, depth) {
+                    right = self.remove_at(right, depth + 1, path, key)?;
+                } else {
+                    left = self.remove_at(left, depth + 1, path, key)?;
+                }
+                if (left, right) == before {
+                    return Ok(hash);
+                }
+                self.replaced(hash);
+
+                // A lone leaf moves up to where it is unique again
+                let remaining = match (left, right) {
+                    (EMPTY_TRIE_ROOT, EMPTY_TRIE_ROOT) => return Ok(EMPTY_TRIE_ROOT),
+                    (EMPTY_TRIE_ROOT, only) | (only, EMPTY_TRIE_ROOT) => Some(only),
+                    _ => None,
+                };
+                if let Some(only) = remaining {
+                    if matches!(self.node(&only)?, TrieNode::Leaf { .. }) {
+                        return Ok(only);
+                    }
+                }
+                Ok(self.put(TrieNode::Branch { left, right }))
+            }
+        }
+    }
+
+    /// Move updates since the last commit into `changes` and return the root
+    ///
+    /// Only new nodes still reachable from the root are reported.
+    pub fn commit(&mut self, changes: &mut NodeChanges) -> BlockHash {
+        let mut pending = vec![self.root];
+        while let Some(hash) = pending.pop() {
+            let Some(node) = self.dirty.remove(&hash) else { continue };
+            if let TrieNode::Branch { left, right } = &node {
+                pending.extend([*left, *right]);
+            }
+            changes.inserted.entry(hash).or_insert((node, 0)).1 += 1;
+        }
+        for hash in self.removed.drain(..) {
+            *changes.removed.entry(hash).or_default() += 1;
+        }
+        self.dirty.clear();
+        self.root()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{KeyValueStore, MemoryStore, WriteBatch};
+
+    fn key(i: u32) -> Vec<u8> {
+        i.to_be_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_root_is_order_independent() {
+        let store = MemoryStore::new();
+
+        let mut forward = Trie::new(&store, BlockHash(EMPTY_TRIE_ROOT));
+        for i in 0..64 {
+            forward.insert(&key(i), vec![i as u8]).unwrap();
+        }
+        let mut backward = Trie::new(&store, BlockHash(EMPTY_TRIE_ROOT));
+        for i in (0..64).rev() {
+            backward.insert(&key(i), vec![i as u8]).unwrap();
+        }
+        assert_eq!(forward.root(), backward.root());
+
+        // Removing keys gives the same root as never inserting them
+        let mut partial = Trie::new(&store, BlockHash(EMPTY_TRIE_ROOT));
+        for i in 0..32 {
+            partial.insert(&key(i), vec![i as u8]).unwrap();
+        }
+        for i in 32..64 {
+            forward.remove(&key(i)).unwrap();
+        }
+        assert_eq!(forward.root(), partial.root());
+
+        for i in 0..32 {
+            forward.remove(&key(i)).unwrap();
+        }
+        assert_eq!(forward.root(), BlockHash(EMPTY_TRIE_ROOT));
+    }
+
+    #[test]
+    fn test_commit_and_reopen() {
+        let store = MemoryStore::new();
+        let mut trie = Trie::new(&store, BlockHash(EMPTY_TRIE_ROOT));
+        for i in 0..16 {
+            trie.insert(&key(i), vec![0; 4]).unwrap();
+        }
+        // Overwrites leave unreachable nodes that must not be written
+        for i in 0..16 {
+            trie.insert(&key(i), vec![i as u8]).unwrap();
+        }
+
+        let mut direct = Trie::new(&store, BlockHash(EMPTY_TRIE_ROOT));
+        for i in 0..16 {
+            direct.insert(&key(i), vec![i as u8]).unwrap();
+        }
+        let mut direct_changes = NodeChanges::new();
+        direct.commit(&mut direct_changes);
+
+        let mut changes = NodeChanges::new();
+        let root = trie.commit(&mut changes);
+        assert_eq!(changes.inserted.len(), direct_changes.inserted.len());
+        assert!(changes.removed.is_empty());
+
+        let mut batch = WriteBatch::new();
+        for (hash, (node, _)) in changes.inserted {
+            batch.put(Column::StateNodes, hash.to_vec(), node.encode());
+        }
+        store.write(batch).unwrap();
+
+        let mut trie = Trie::new(&store, root);
+        assert_eq!(trie.get(&key(7)).unwrap(), Some(vec![7]));
+        assert_eq!(trie.get(&key(99)).unwrap(), None);
+
+        let mut entries: Vec<_> = trie.iter().map(Result::unwrap).collect();
+        entries.sort();
+        let expected: Vec<_> = (0..16).map(|i| (key(i), vec![i as u8])).collect();
+        assert_eq!(entries, expected);
+
+        // Updating a leaf replaces exactly the nodes on its path
+        trie.insert(&key(7), vec![70]).unwrap();
+        let mut changes = NodeChanges::new();
+        trie.commit(&mut changes);
+        assert!(!changes.removed.is_empty());
+        assert_eq!(changes.removed.len(), changes.inserted.len());
+
+        // Writing back the same value is no change at all
+        let mut trie = Trie::new(&store, root);
+        trie.insert(&key(7), vec![7]).unwrap();
+        let mut changes = NodeChanges::new();
+        assert_eq!(trie.commit(&mut changes), root);
+        changes.net();
+        assert!(changes.inserted.is_empty() && changes.removed.is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+//! Fixtures shared by unit tests
+
+use crate::consensus::finality::FinalityProof;
+use crate::consensus::Validator;
+use crate::crypto::merkle::{receipts_root, transactions_root};
+use crate::network::{
+    NetworkError, NetworkResult, PeerId, SyncProvider, SyncRequest, SyncResponse, SyncTransport,
+};
+use crate::storage::BlockStore;
+use crate::types::*;
+use async_trait::async_trait;
+use ed25519_dalek::SigningKey;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Routes sync requests straight to per-peer providers
+#[derive(Default)]
+pub(crate) struct LocalTransport {
+    pub providers: HashMap<PeerId, Arc<dyn SyncProvider>>,
+    pub penalized: Mutex<Vec<PeerId>>,
+}
+
+#[async_trait]
+impl SyncTransport for LocalTransport {
+    async fn request(&self, peer: PeerId, request: SyncRequest) -> NetworkResult<SyncResponse> {
+        let provider = self.providers.get(&peer).ok_or(NetworkError::Stopped)?;
+        Ok(provider.respond(&request))
+    }
+
+    fn penalize(&self, peer: PeerId) {
+        self.penalized.lock().unwrap().push(peer);
+    }
+}
+
+/// Random peer ID
+pub(crate) fn peer() -> PeerId {
+    libp2p::identity::Keypair::generate_ed25519()
+        .public()
+        .to_peer_id()
+}
+
+/// Block with no transactions on `parent` (a genesis block without one)
+/// that passes the consensus header checks
+pub(crate) fn empty_block(parent: Option<&BlockHeader>, state_root: BlockHash) -> Block {
+    let header = BlockHeader {
+        number: parent.map_or(0, |p| p.number + 1),
+        parent_hash: parent.map_or(BlockHash([0; 32]), |p| p.hash()),
+        timestamp: parent.map_or(0, |p| p.timestamp + 1),
+        state_root,
+        transactions_root: transactions_root(&[]),
+        receipts_root: receipts_root(&[]),
+        proposer: Address([0xff; 20]),
+        logs_bloom: Bloom::default(),
+        gas_limit: 1_000_000,
+        gas_used: 0,
+        base_fee_per_gas: parent.map_or(Balance::from(10u64), |p| p.next_base_fee()),
+        extra_data: vec![],
+    };
+    Block {
+        header,
+        transactions: vec![],
+    }
+}
+
+/// Keys of the validators in [`validator_set`]
+pub(crate) fn validator_keys() -> Vec<SigningKey> {
+    (1..=3u8)
+        .map(|i| SigningKey::from_bytes(&[i; 32]))
+        .collect()
+}
+
+/// Active validators with equal stake for `keys`
+pub(crate) fn validator_set(keys: &[SigningKey]) -> Vec<Validator> {
+    keys.iter()
+        .map(|key| {
+            let public_key = PublicKey(key.verifying_key().to_bytes());
+            Validator {
+                address: public_key.to_address(),
+                stake: Balance::from(100u64),
+                public_key,
+                reputation: 0,
+                is_active: true,
+            }
+        })
+        .collect()
+}
+
+/// Store a proof signed by all [`validator_keys`] that the head of `blocks`
+/// is final
+pub(crate) fn finalize_head(blocks: &BlockStore) {
+    let head = blocks.head().unwrap().unwrap();
+    let mut proof = FinalityProof::new(head.number, head.hash());
+    for key in &validator_keys() {
+        proof.sign(key);
+    }
+    blocks.insert_finality_proof(&proof).unwrap();
+}
@@ -1,40 +1,279 @@
 //! Core types used throughout the blockchain
 
-use serde::{Deserialize, Serialize};
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha3::{Digest, Keccak256};
 use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+mod u256;
+
+pub use u256::U256;
+
+/// Errors when parsing hex-encoded types from strings
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("Invalid length: expected {expected} hex digits, got {actual}")]
+    InvalidLength { expected: usize, actual: usize },
+
+    #[error("Invalid hex character: {0:?}")]
+    InvalidCharacter(char),
+
+    #[error("Invalid address checksum")]
+    InvalidChecksum,
+
+    #[error("Empty string")]
+    Empty,
+
+    #[error("Number does not fit in 256 bits")]
+    Overflow,
+
+    #[error("Unsupported radix {0}")]
+    InvalidRadix(u32),
+}
+
+/// Implements `Display`, and serde as a hex string for human-readable formats
+/// (JSON, TOML) and as raw bytes otherwise (bincode).
+///
+/// The type must implement `FromStr<Err = ParseError>`.
+macro_rules! impl_hex_type {
+    ($name:ident) => {
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "0x{}", hex::encode(self.0))
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                if serializer.is_human_readable() {
+                    serializer.collect_str(self)
+                } else {
+                    byte_array::serialize(&self.0, serializer)
+                }
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                if deserializer.is_human_readable() {
+                    let s = String::deserialize(deserializer)?;
+                    s.parse().map_err(de::Error::custom)
+                } else {
+                    byte_array::deserialize(deserializer).map($name)
+                }
+            }
+        }
+    };
+}
 
 /// Block hash (32 bytes)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct BlockHash(pub [u8; 32]);
 
-impl fmt::Display for BlockHash {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "0x{}", hex::encode(self.0))
+impl_hex_type!(BlockHash);
+
+impl FromStr for BlockHash {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode_fixed(s).map(BlockHash)
     }
 }
 
 /// Transaction hash (32 bytes)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct TxHash(pub [u8; 32]);
 
-impl fmt::Display for TxHash {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "0x{}", hex::encode(self.0))
+impl_hex_type!(TxHash);
+
+impl FromStr for TxHash {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode_fixed(s).map(TxHash)
     }
 }
 
 /// Account address (20 bytes, Ethereum-compatible)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Address(pub [u8; 20]);
 
-impl fmt::Display for Address {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "0x{}", hex::encode(self.0))
+impl_hex_type!(Address);
+
+impl FromStr for Address {
+    type Err = ParseError;
+
+    /// Parse an address, validating the EIP-55 checksum if the input is mixed-case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let address = Address(hex::decode_fixed(s)?);
+
+        let digits = hex::strip_prefix(s);
+        let has_lower = digits.bytes().any(|b| b.is_ascii_lowercase());
+        let has_upper = digits.bytes().any(|b| b.is_ascii_uppercase());
+        if has_lower && has_upper && address.to_checksum_string()[2..] != *digits {
+            return Err(ParseError::InvalidChecksum);
+        }
+
+        Ok(address)
+    }
+}
+
+/// Address derivation scheme
+///
+/// The scheme byte is hashed in front of the derivation input so addresses
+/// produced by different schemes can never collide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum AddressScheme {
+    /// Account controlled by a single Ed25519 key
+    Ed25519 = 0x00,
+    /// Contract deployed by a regular transaction (sender + nonce)
+    Contract = 0x01,
+    /// Contract deployed with a caller-chosen salt (create2-style)
+    Create2 = 0x02,
+    /// Native M-of-N multisig account
+    Multisig = 0x03,
+}
+
+impl Address {
+    /// Derive an address from a scheme byte and its inputs
+    ///
+    /// The address is the last 20 bytes of `blake3(scheme || inputs...)`.
+    pub fn derive(scheme: AddressScheme, inputs: &[&[u8]]) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[scheme as u8]);
+        for input in inputs {
+            hasher.update(input);
+        }
+        let hash = hasher.finalize();
+
+        let mut address = [0u8; 20];
+        address.copy_from_slice(&hash.as_bytes()[12..]);
+        Address(address)
+    }
+
+    /// Address controlled by an Ed25519 public key
+    pub fn from_public_key(public_key: &PublicKey) -> Self {
+        Self::derive(AddressScheme::Ed25519, &[&public_key.0])
+    }
+
+    /// Address of a contract deployed by `sender` with the given nonce
+    pub fn contract(sender: &Address, nonce: Nonce) -> Self {
+        Self::derive(AddressScheme::Contract, &[&sender.0, &nonce.to_be_bytes()])
+    }
+
+    /// Address of a contract deployed by `sender` with a salt
+    ///
+    /// Depends only on the sender, the salt and the hash of the init code, so
+    /// the address is known before deployment.
+    pub fn contract_with_salt(sender: &Address, salt: &[u8; 32], init_code: &[u8]) -> Self {
+        let code_hash = blake3::hash(init_code);
+        Self::derive(AddressScheme::Create2, &[&sender.0, salt, code_hash.as_bytes()])
+    }
+
+    /// Address of the native multisig account with the given configuration
+    pub fn multisig(config: &MultisigConfig) -> Self {
+        let signers: Vec<&[u8]> = config.signers.iter().map(|key| &key.0[..]).collect();
+        Self::derive(AddressScheme::Multisig, &[&[config.threshold], &signers.concat()])
+    }
+
+    /// Mixed-case checksummed representation (EIP-55)
+    pub fn to_checksum_string(&self) -> String {
+        let lower = hex::encode(self.0);
+        let hash = Keccak256::digest(lower.as_bytes());
+
+        let mut out = String::with_capacity(42);
+        out.push_str("0x");
+        for (i, c) in lower.chars().enumerate() {
+            let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+            if c.is_ascii_alphabetic() && nibble >= 8 {
+                out.push(c.to_ascii_uppercase());
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+}
+
+/// Logs bloom filter (2048 bits, Ethereum-compatible)
+///
+/// Each log sets three bits for its address and three for each topic, so a
+/// missing bit proves an address or topic is absent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Bloom(pub [u8; 256]);
+
+impl_hex_type!(Bloom);
+
+impl FromStr for Bloom {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode_fixed(s).map(Bloom)
+    }
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom([0; 256])
+    }
+}
+
+impl Bloom {
+    /// Bloom of all addresses and topics in the given logs
+    pub fn from_logs<'a>(logs: impl IntoIterator<Item = &'a Log>) -> Self {
+        let mut bloom = Bloom::default();
+        for log in logs {
+            bloom.accrue_log(log);
+        }
+        bloom
+    }
+
+    /// Bit positions for an input: three 11-bit slices of its keccak hash
+    fn bits(input: &[u8]) -> [(usize, u8); 3] {
+        let hash = Keccak256::digest(input);
+        let mut bits = [(0, 0); 3];
+        for (i, bit) in bits.iter_mut().enumerate() {
+            let index = (((hash[2 * i] as usize) << 8) | hash[2 * i + 1] as usize) & 2047;
+            *bit = (255 - index / 8, 1 << (index % 8));
+        }
+        bits
+    }
+
+    /// Add an address or topic
+    pub fn accrue(&mut self, input: &[u8]) {
+        for (byte, mask) in Self::bits(input) {
+            self.0[byte] |= mask;
+        }
+    }
+
+    /// Add a log's address and topics
+    pub fn accrue_log(&mut self, log: &Log) {
+        self.accrue(&log.address.0);
+        for topic in &log.topics {
+            self.accrue(topic);
+        }
+    }
+
+    /// Merge another bloom into this one
+    pub fn accrue_bloom(&mut self, other: &Bloom) {
+        for (byte, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *byte |= other;
+        }
+    }
+
+    /// Check whether an address or topic may have been added
+    pub fn contains_input(&self, input: &[u8]) -> bool {
+        Self::bits(input)
+            .iter()
+            .all(|&(byte, mask)| self.0[byte] & mask == mask)
     }
 }
 
 /// Balance amount (256-bit unsigned integer)
-pub type Balance = u128;
+pub type Balance = U256;
 
 /// Gas amount
 pub type Gas = u64;
@@ -49,13 +288,188 @@ pub type Timestamp = u64;
 pub type Nonce = u64;
 
 /// Signature (64 bytes for Ed25519)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct Signature(pub [u8; 64]);
 
+impl_hex_type!(Signature);
+
+impl FromStr for Signature {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode_fixed(s).map(Signature)
+    }
+}
+
 /// Public key (32 bytes for Ed25519)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PublicKey(pub [u8; 32]);
 
+impl_hex_type!(PublicKey);
+
+impl FromStr for PublicKey {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode_fixed(s).map(PublicKey)
+    }
+}
+
+impl PublicKey {
+    /// Address controlled by this key
+    pub fn to_address(&self) -> Address {
+        Address::from_public_key(self)
+    }
+
+    /// Verify an Ed25519 signature over a message
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(&self.0) else {
+            return false;
+        };
+        let signature = ed25519_dalek::Signature::from_bytes(&signature.0);
+        key.verify(message, &signature).is_ok()
+    }
+}
+
+/// Storage slots a transaction declares it will access
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessListItem {
+    /// Account being accessed
+    pub address: Address,
+    /// Storage keys accessed within the account
+    pub storage_keys: Vec<[u8; 32]>,
+}
+
+/// Transaction type with its fee parameters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxKind {
+    /// Fixed gas price
+    Legacy {
+        /// Price paid per unit of gas
+        gas_price: Balance,
+    },
+    /// Fixed gas price with a declared access list
+    AccessList {
+        /// Price paid per unit of gas
+        gas_price: Balance,
+        /// Accounts and storage slots accessed
+        access_list: Vec<AccessListItem>,
+    },
+    /// Base fee plus priority fee, capped by a maximum (EIP-1559 style)
+    DynamicFee {
+        /// Maximum total price per unit of gas
+        max_fee_per_gas: Balance,
+        /// Maximum tip per unit of gas paid to the proposer
+        max_priority_fee_per_gas: Balance,
+        /// Accounts and storage slots accessed
+        access_list: Vec<AccessListItem>,
+    },
+}
+
+impl TxKind {
+    /// Envelope version byte
+    pub fn type_id(&self) -> u8 {
+        match self {
+            TxKind::Legacy { .. } => 0,
+            TxKind::AccessList { .. } => 1,
+            TxKind::DynamicFee { .. } => 2,
+        }
+    }
+}
+
+/// Proof that the sender authorized a transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Authorization {
+    /// Signature by the key the sender address is derived from
+    Ed25519 {
+        /// Sender public key (`from` must be derived from it)
+        public_key: PublicKey,
+        /// Signature over the signing hash
+        signature: Signature,
+    },
+    /// Signatures by signers of a native multisig account
+    Multisig {
+        /// Account configuration, required only for the first transaction
+        /// of an account not yet in state; its address must match `from`
+        config: Option<MultisigConfig>,
+        /// Signatures over the signing hash, by increasing signer index
+        signatures: Vec<MultisigSignature>,
+    },
+    /// Opaque data checked by the sender account's validation contract
+    /// (multisig, social recovery, session keys, ...)
+    Contract {
+        /// Input to the validation logic, e.g. signatures over the signing hash
+        data: Vec<u8>,
+    },
+}
+
+/// Maximum number of signers of a native multisig account
+pub const MAX_MULTISIG_SIGNERS: usize = 32;
+
+/// M-of-N control of a native multisig account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisigConfig {
+    /// Number of signatures required
+    pub threshold: u8,
+    /// Signer keys; signatures refer to signers by index into this list
+    pub signers: Vec<PublicKey>,
+}
+
+/// Signature by one signer of a multisig account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultisigSignature {
+    /// Index of the signer in `MultisigConfig::signers`
+    pub signer_index: u8,
+    /// Signature over the signing hash
+    pub signature: Signature,
+}
+
+impl MultisigConfig {
+    /// Check the threshold is reachable and signers are unique
+    pub fn is_valid(&self) -> bool {
+        let unique = self
+            .signers
+            .iter()
+            .enumerate()
+            .all(|(i, key)| !self.signers[..i].contains(key));
+
+        self.threshold >= 1
+            && self.threshold as usize <= self.signers.len()
+            && self.signers.len() <= MAX_MULTISIG_SIGNERS
+            && unique
+    }
+
+    /// Check that at least `threshold` distinct signers signed the message
+    ///
+    /// Signatures must be ordered by strictly increasing signer index, and
+    /// every one of them must be valid.
+    pub fn verify(&self, message: &[u8], signatures: &[MultisigSignature]) -> bool {
+        if !self.is_valid() || signatures.len() < self.threshold as usize {
+            return false;
+        }
+
+        let ordered = signatures
+            .windows(2)
+            .all(|pair| pair[0].signer_index < pair[1].signer_index);
+
+        ordered
+            && signatures.iter().all(|entry| {
+                self.signers
+                    .get(entry.signer_index as usize)
+                    .is_some_and(|key| key.verify(message, &entry.signature))
+            })
+    }
+}
+
+/// Account that pays the fees of a transaction on behalf of its sender
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Paymaster {
+    /// Paymaster contract address
+    pub address: Address,
+    /// Input to the paymaster's validation logic
+    pub data: Vec<u8>,
+}
+
 /// Transaction structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
@@ -69,14 +483,170 @@ pub struct Transaction {
     pub data: Vec<u8>,
     /// Gas limit
     pub gas_limit: Gas,
-    /// Gas price
-    pub gas_price: Balance,
+    /// Transaction type and fee parameters
+    pub kind: TxKind,
     /// Nonce
     pub nonce: Nonce,
-    /// Signature
-    pub signature: Signature,
+    /// Sponsor paying the fees (None if the sender pays)
+    pub paymaster: Option<Paymaster>,
+    /// Sender authorization
+    pub authorization: Authorization,
+}
+
+impl Transaction {
+    /// Calculate transaction hash
+    ///
+    /// This is the [signing hash](Self::signing_hash), so re-encoding the
+    /// authorization or the paymaster data does not yield a new transaction.
+    /// Blocks commit to the full encoding instead.
+    pub fn hash(&self) -> TxHash {
+        TxHash(self.signing_hash())
+    }
+
+    /// Hash covered by the sender and paymaster authorizations
+    ///
+    /// This is the transaction with an empty authorization and empty
+    /// paymaster data, so the sender commits to the paymaster address and the
+    /// paymaster can sign the same hash.
+    pub fn signing_hash(&self) -> [u8; 32] {
+        let mut unsigned = self.clone();
+        unsigned.authorization = Authorization::Contract { data: Vec::new() };
+        if let Some(paymaster) = &mut unsigned.paymaster {
+            paymaster.data.clear();
+        }
+        let encoded = bincode::serialize(&unsigned).unwrap();
+        blake3::hash(&encoded).into()
+    }
+
+    /// Sign the transaction with a single key
+    ///
+    /// `from` is left untouched; use [`Address::from_public_key`] to fill it.
+    pub fn sign(&mut self, signing_key: &SigningKey) {
+        let public_key = PublicKey(signing_key.verifying_key().to_bytes());
+        let signature = signing_key.sign(&self.signing_hash());
+        self.authorization = Authorization::Ed25519 {
+            public_key,
+            signature: Signature(signature.to_bytes()),
+        };
+    }
+
+    /// Add a multisig signature, keeping signatures ordered by signer index
+    ///
+    /// Replaces any non-multisig authorization.
+    pub fn add_multisig_signature(&mut self, signer_index: u8, signing_key: &SigningKey) {
+        let signature = Signature(signing_key.sign(&self.signing_hash()).to_bytes());
+        if !matches!(self.authorization, Authorization::Multisig { .. }) {
+            self.authorization = Authorization::Multisig {
+                config: None,
+                signatures: Vec::new(),
+            };
+        }
+
+        if let Authorization::Multisig { signatures, .. } = &mut self.authorization {
+            signatures.retain(|entry| entry.signer_index != signer_index);
+            let position = signatures.partition_point(|entry| entry.signer_index < signer_index);
+            signatures.insert(position, MultisigSignature { signer_index, signature });
+        }
+    }
+
+    /// Check whether the authorization can be verified without account state
+    pub fn has_key_authorization(&self) -> bool {
+        matches!(self.authorization, Authorization::Ed25519 { .. })
+    }
+
+    /// Check a single-key authorization: `from` is derived from the public
+    /// key and the signature is valid
+    ///
+    /// Contract-validated transactions need account state and are checked by
+    /// the executor instead; they always fail here.
+    pub fn verify_signature(&self) -> bool {
+        match &self.authorization {
+            Authorization::Ed25519 {
+                public_key,
+                signature,
+            } => {
+                Address::from_public_key(public_key) == self.from
+                    && public_key.verify(&self.signing_hash(), signature)
+            }
+            Authorization::Multisig { .. } | Authorization::Contract { .. } => false,
+        }
+    }
+
+    /// Account paying the fees
+    pub fn fee_payer(&self) -> Address {
+        self.paymaster
+            .as_ref()
+            .map(|paymaster| paymaster.address)
+            .unwrap_or(self.from)
+    }
+
+    /// Highest price per unit of gas the sender is willing to pay
+    pub fn max_fee_per_gas(&self) -> Balance {
+        match &self.kind {
+            TxKind::Legacy { gas_price } | TxKind::AccessList { gas_price, .. } => *gas_price,
+            TxKind::DynamicFee { max_fee_per_gas, .. } => *max_fee_per_gas,
+        }
+    }
+
+    /// Highest tip per unit of gas the sender is willing to pay the proposer
+    pub fn max_priority_fee_per_gas(&self) -> Balance {
+        match &self.kind {
+            TxKind::Legacy { gas_price } | TxKind::AccessList { gas_price, .. } => *gas_price,
+            TxKind::DynamicFee {
+                max_priority_fee_per_gas,
+                ..
+            } => *max_priority_fee_per_gas,
+        }
+    }
+
+    /// Price per unit of gas actually paid under the given base fee
+    ///
+    /// Returns `None` if the transaction cannot be included: its fee cap is
+    /// below the base fee, or its priority fee exceeds its fee cap.
+    pub fn effective_gas_price(&self, base_fee: Balance) -> Option<Balance> {
+        match &self.kind {
+            TxKind::Legacy { gas_price } | TxKind::AccessList { gas_price, .. } => {
+                (*gas_price >= base_fee).then_some(*gas_price)
+            }
+            TxKind::DynamicFee {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                ..
+            } => {
+                if max_priority_fee_per_gas > max_fee_per_gas || *max_fee_per_gas < base_fee {
+                    return None;
+                }
+                let price = base_fee.saturating_add(*max_priority_fee_per_gas);
+                Some(price.min(*max_fee_per_gas))
+            }
+        }
+    }
+
+    /// Tip per unit of gas paid to the proposer under the given base fee
+    pub fn priority_fee(&self, base_fee: Balance) -> Option<Balance> {
+        self.effective_gas_price(base_fee).map(|price| price - base_fee)
+    }
+
+    /// Declared access list (empty for legacy transactions)
+    pub fn access_list(&self) -> &[AccessListItem] {
+        match &self.kind {
+            TxKind::Legacy { .. } => &[],
+            TxKind::AccessList { access_list, .. } | TxKind::DynamicFee { access_list, .. } => {
+                access_list
+            }
+        }
+    }
 }
 
+/// Base fee change is bounded to 1/8 of the parent base fee per block
+pub const BASE_FEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
+
+/// Gas limit is this multiple of the gas target
+pub const ELASTICITY_MULTIPLIER: u64 = 2;
+
+/// Gas limit change is bounded to 1/1024 of the parent gas limit per block
+pub const GAS_LIMIT_BOUND_DIVISOR: u64 = 1024;
+
 /// Block header
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockHeader {
@@ -94,10 +664,54 @@ pub struct BlockHeader {
     pub receipts_root: BlockHash,
     /// Block proposer
     pub proposer: Address,
+    /// Bloom of all logs emitted in the block
+    pub logs_bloom: Bloom,
+    /// Maximum gas all transactions in the block may use
+    pub gas_limit: Gas,
+    /// Gas used by all transactions in the block
+    pub gas_used: Gas,
+    /// Minimum price per unit of gas, burned rather than paid to the proposer
+    pub base_fee_per_gas: Balance,
     /// Extra data
     pub extra_data: Vec<u8>,
 }
 
+impl BlockHeader {
+    /// Calculate the hash identifying this block
+    pub fn hash(&self) -> BlockHash {
+        let encoded = bincode::serialize(self).unwrap();
+        let hash = blake3::hash(&encoded);
+        BlockHash(hash.into())
+    }
+
+    /// Gas usage at which the base fee stays constant
+    pub fn gas_target(&self) -> Gas {
+        self.gas_limit / ELASTICITY_MULTIPLIER
+    }
+
+    /// Base fee required of the child of this block
+    ///
+    /// Rises when this block used more than its gas target and falls when it
+    /// used less, by at most 1/8 per block.
+    pub fn next_base_fee(&self) -> Balance {
+        let target = self.gas_target();
+        if target == 0 || self.gas_used == target {
+            return self.base_fee_per_gas;
+        }
+
+        let denominator = Balance::from(target) * Balance::from(BASE_FEE_MAX_CHANGE_DENOMINATOR);
+        if self.gas_used > target {
+            let excess = Balance::from(self.gas_used - target);
+            let delta = (self.base_fee_per_gas.saturating_mul(excess) / denominator).max(Balance::ONE);
+            self.base_fee_per_gas.saturating_add(delta)
+        } else {
+            let shortfall = Balance::from(target - self.gas_used);
+            let delta = self.base_fee_per_gas.saturating_mul(shortfall) / denominator;
+            self.base_fee_per_gas.saturating_sub(delta)
+        }
+    }
+}
+
 /// Complete block
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
@@ -110,9 +724,7 @@ pub struct Block {
 impl Block {
     /// Calculate block hash
     pub fn hash(&self) -> BlockHash {
-        let encoded = bincode::serialize(&self.header).unwrap();
-        let hash = blake3::hash(&encoded);
-        BlockHash(hash.into())
+        self.header.hash()
     }
 }
 
@@ -129,6 +741,8 @@ pub struct Receipt {
     pub success: bool,
     /// Logs emitted
     pub logs: Vec<Log>,
+    /// Bloom of the logs emitted
+    pub logs_bloom: Bloom,
     /// Contract address (if contract creation)
     pub contract_address: Option<Address>,
 }
@@ -155,22 +769,310 @@ pub struct Account {
     pub code_hash: Option<BlockHash>,
     /// Storage root
     pub storage_root: BlockHash,
+    /// Native multisig control (None for single-key and contract accounts)
+    pub multisig: Option<MultisigConfig>,
 }
 
 impl Default for Account {
     fn default() -> Self {
         Self {
-            balance: 0,
+            balance: Balance::ZERO,
             nonce: 0,
             code_hash: None,
             storage_root: BlockHash([0; 32]),
+            multisig: None,
         }
     }
 }
 
 // Helper module for hex encoding
 mod hex {
+    use super::ParseError;
+
     pub fn encode(bytes: impl AsRef<[u8]>) -> String {
         bytes.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
     }
+
+    pub fn strip_prefix(s: &str) -> &str {
+        s.strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s)
+    }
+
+    /// Decode exactly `N` bytes, with or without a `0x` prefix
+    pub fn decode_fixed<const N: usize>(s: &str) -> Result<[u8; N], ParseError> {
+        let digits = strip_prefix(s);
+        if digits.len() != N * 2 {
+            return Err(ParseError::InvalidLength {
+                expected: N * 2,
+                actual: digits.len(),
+            });
+        }
+
+        let mut out = [0u8; N];
+        for (byte, pair) in out.iter_mut().zip(digits.as_bytes().chunks(2)) {
+            *byte = (nibble(pair[0])? << 4) | nibble(pair[1])?;
+        }
+        Ok(out)
+    }
+
+    fn nibble(c: u8) -> Result<u8, ParseError> {
+        match c {
+            b'0'..=b'9' => Ok(c - b'0'),
+            b'a'..=b'f' => Ok(c - b'a' + 10),
+            b'A'..=b'F' => Ok(c - b'A' + 10),
+            _ => Err(ParseError::InvalidCharacter(c as char)),
+        }
+    }
+}
+
+// Fixed-size byte arrays as serde tuples (serde only derives up to 32 elements)
+mod byte_array {
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::ser::SerializeTuple;
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for byte in bytes {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        struct ArrayVisitor<const N: usize>;
+
+        impl<'de, const N: usize> Visitor<'de> for ArrayVisitor<N> {
+            type Value = [u8; N];
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "an array of {} bytes", N)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut out = [0u8; N];
+                for (i, byte) in out.iter_mut().enumerate() {
+                    *byte = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                }
+                Ok(out)
+            }
+        }
+
+        deserializer.deserialize_tuple(N, ArrayVisitor::<N>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed_transaction(key: &SigningKey) -> Transaction {
+        let public_key = PublicKey(key.verifying_key().to_bytes());
+        let mut tx = Transaction {
+            from: Address::from_public_key(&public_key),
+            to: Some(Address([9; 20])),
+            value: Balance::from(1_000u64),
+            data: vec![],
+            gas_limit: 21_000,
+            kind: TxKind::Legacy { gas_price: Balance::ONE },
+            nonce: 0,
+            paymaster: None,
+            authorization: Authorization::Contract { data: vec![] },
+        };
+        tx.sign(key);
+        tx
+    }
+
+    #[test]
+    fn test_address_schemes_do_not_collide() {
+        let key = PublicKey([7; 32]);
+        let sender = key.to_address();
+
+        assert_ne!(sender, Address::contract(&sender, 0));
+        assert_ne!(Address::contract(&sender, 0), Address::contract(&sender, 1));
+        assert_ne!(
+            Address::contract_with_salt(&sender, &[0; 32], b"code"),
+            Address::contract_with_salt(&sender, &[1; 32], b"code"),
+        );
+    }
+
+    #[test]
+    fn test_checksum_formatting() {
+        let address: Address = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".parse().unwrap();
+
+        // EIP-55 reference vector
+        assert_eq!(
+            address.to_checksum_string(),
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        );
+    }
+
+    #[test]
+    fn test_parse_hex_types() {
+        let hash = BlockHash([0xab; 32]);
+        assert_eq!(hash.to_string().parse::<BlockHash>().unwrap(), hash);
+        assert_eq!(hash.to_string()[2..].parse::<BlockHash>().unwrap(), hash);
+
+        assert_eq!(
+            "0x1234".parse::<TxHash>(),
+            Err(ParseError::InvalidLength { expected: 64, actual: 4 })
+        );
+        assert_eq!(
+            format!("0x{}", "zz".repeat(20)).parse::<Address>(),
+            Err(ParseError::InvalidCharacter('z'))
+        );
+    }
+
+    #[test]
+    fn test_parse_address_checksum() {
+        let checksummed = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        let address: Address = checksummed.parse().unwrap();
+
+        assert_eq!(checksummed.to_uppercase()[2..].parse::<Address>().unwrap(), address);
+        assert_eq!(
+            "0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse::<Address>(),
+            Err(ParseError::InvalidChecksum)
+        );
+    }
+
+    #[test]
+    fn test_serde_formats() {
+        let address = Address([0x11; 20]);
+        let json = serde_json::to_string(&address).unwrap();
+        assert_eq!(json, format!("\"0x{}\"", "11".repeat(20)));
+        assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), address);
+
+        let signature = Signature([0x22; 64]);
+        let encoded = bincode::serialize(&signature).unwrap();
+        assert_eq!(encoded, vec![0x22; 64]);
+        assert_eq!(bincode::deserialize::<Signature>(&encoded).unwrap().0, signature.0);
+    }
+
+    #[test]
+    fn test_bloom() {
+        let log = Log {
+            address: Address([5; 20]),
+            topics: vec![[6; 32]],
+            data: vec![],
+        };
+        let bloom = Bloom::from_logs([&log]);
+
+        assert!(bloom.contains_input(&log.address.0));
+        assert!(bloom.contains_input(&[6; 32]));
+        assert!(!bloom.contains_input(&[7; 32]));
+        assert!(!Bloom::default().contains_input(&log.address.0));
+    }
+
+    #[test]
+    fn test_effective_gas_price() {
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let mut tx = signed_transaction(&key);
+        let base_fee = Balance::from(10u64);
+
+        tx.kind = TxKind::DynamicFee {
+            max_fee_per_gas: Balance::from(15u64),
+            max_priority_fee_per_gas: Balance::from(2u64),
+            access_list: vec![],
+        };
+        assert_eq!(tx.effective_gas_price(base_fee), Some(Balance::from(12u64)));
+        assert_eq!(tx.priority_fee(base_fee), Some(Balance::from(2u64)));
+
+        // Tip is capped by the fee cap
+        assert_eq!(tx.effective_gas_price(Balance::from(14u64)), Some(Balance::from(15u64)));
+        assert_eq!(tx.effective_gas_price(Balance::from(16u64)), None);
+
+        tx.kind = TxKind::Legacy { gas_price: Balance::from(9u64) };
+        assert_eq!(tx.effective_gas_price(base_fee), None);
+    }
+
+    #[test]
+    fn test_next_base_fee() {
+        let mut header = BlockHeader {
+            number: 1,
+            parent_hash: BlockHash([0; 32]),
+            timestamp: 1000,
+            state_root: BlockHash([0; 32]),
+            transactions_root: BlockHash([0; 32]),
+            receipts_root: BlockHash([0; 32]),
+            proposer: Address([0; 20]),
+            logs_bloom: Bloom::default(),
+            gas_limit: 30_000_000,
+            gas_used: 15_000_000,
+            base_fee_per_gas: Balance::from(1_000_000_000u64),
+            extra_data: vec![],
+        };
+        assert_eq!(header.next_base_fee(), Balance::from(1_000_000_000u64));
+
+        header.gas_used = 30_000_000;
+        assert_eq!(header.next_base_fee(), Balance::from(1_125_000_000u64));
+
+        header.gas_used = 0;
+        assert_eq!(header.next_base_fee(), Balance::from(875_000_000u64));
+    }
+
+    #[test]
+    fn test_multisig_config() {
+        let keys: Vec<_> = (1..=3u8).map(|i| SigningKey::from_bytes(&[i; 32])).collect();
+        let mut config = MultisigConfig {
+            threshold: 2,
+            signers: keys.iter().map(|k| PublicKey(k.verifying_key().to_bytes())).collect(),
+        };
+        assert!(config.is_valid());
+
+        let sign = |index: u8| MultisigSignature {
+            signer_index: index,
+            signature: Signature(keys[index as usize].sign(b"message").to_bytes()),
+        };
+        assert!(config.verify(b"message", &[sign(0), sign(2)]));
+        assert!(!config.verify(b"message", &[sign(2), sign(0)]));
+        assert!(!config.verify(b"message", &[sign(1), sign(1)]));
+        assert!(!config.verify(b"other", &[sign(0), sign(2)]));
+
+        let address = Address::multisig(&config);
+        config.threshold = 3;
+        assert_ne!(Address::multisig(&config), address);
+
+        config.threshold = 4;
+        assert!(!config.is_valid());
+    }
+
+    #[test]
+    fn test_transaction_signature() {
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let tx = signed_transaction(&key);
+        assert!(tx.verify_signature());
+
+        let mut tampered = tx.clone();
+        tampered.value = Balance::from(2_000u64);
+        assert!(!tampered.verify_signature());
+
+        let mut spoofed = tx.clone();
+        spoofed.from = Address([1; 20]);
+        assert!(!spoofed.verify_signature());
+
+        // Rewriting the paymaster data keeps the hash and the signature
+        let mut sponsored = tx;
+        sponsored.paymaster = Some(Paymaster {
+            address: Address([5; 20]),
+            data: vec![1],
+        });
+        sponsored.sign(&key);
+        let mut relayed = sponsored.clone();
+        relayed.paymaster.as_mut().unwrap().data = vec![2];
+        assert!(relayed.verify_signature());
+        assert_eq!(relayed.hash(), sponsored.hash());
+        assert_ne!(
+            crate::crypto::merkle::transaction_leaf(&relayed),
+            crate::crypto::merkle::transaction_leaf(&sponsored)
+        );
+    }
 }
@@ -0,0 +1,487 @@
+//! 256-bit unsigned integer
+//!
+//! Arithmetic operators panic on overflow like the primitive integer types in
+//! debug builds; use the `checked_*` and `saturating_*` variants for amounts
+//! that come from untrusted input.
+
+use super::{byte_array, ParseError};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Rem, Sub, SubAssign};
+use std::str::FromStr;
+
+/// 256-bit unsigned integer (four little-endian 64-bit limbs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    /// Zero
+    pub const ZERO: Self = U256([0; 4]);
+
+    /// One
+    pub const ONE: Self = U256([1, 0, 0, 0]);
+
+    /// Largest representable value (2^256 - 1)
+    pub const MAX: Self = U256([u64::MAX; 4]);
+
+    /// Create from a `u128` (usable in constants)
+    pub const fn from_u128(value: u128) -> Self {
+        U256([value as u64, (value >> 64) as u64, 0, 0])
+    }
+
+    /// Create from big-endian bytes
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 32 - (i + 1) * 8;
+            let mut chunk = [0u8; 8];
+            chunk.copy_from_slice(&bytes[start..start + 8]);
+            *limb = u64::from_be_bytes(chunk);
+        }
+        U256(limbs)
+    }
+
+    /// Big-endian byte representation
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            let start = 32 - (i + 1) * 8;
+            bytes[start..start + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Value as `u128`, if it fits
+    pub fn to_u128(&self) -> Option<u128> {
+        if self.0[2] != 0 || self.0[3] != 0 {
+            return None;
+        }
+        Some(((self.0[1] as u128) << 64) | self.0[0] as u128)
+    }
+
+    /// Value as `u64`, if it fits
+    pub fn to_u64(&self) -> Option<u64> {
+        if self.0[1..].iter().any(|&limb| limb != 0) {
+            return None;
+        }
+        Some(self.0[0])
+    }
+
+    /// Check for zero
+    pub fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+
+    /// Number of significant bits
+    pub fn bits(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i as u32 + (64 - self.0[i].leading_zeros());
+            }
+        }
+        0
+    }
+
+    fn bit(&self, index: u32) -> bool {
+        (self.0[(index / 64) as usize] >> (index % 64)) & 1 == 1
+    }
+
+    fn shl1(&self) -> Self {
+        let mut out = [0u64; 4];
+        let mut carry = 0;
+        for (i, limb) in self.0.iter().enumerate() {
+            out[i] = (limb << 1) | carry;
+            carry = limb >> 63;
+        }
+        U256(out)
+    }
+
+    /// Addition returning the wrapped result and whether it overflowed
+    pub fn overflowing_add(self, rhs: Self) -> (Self, bool) {
+        let mut out = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in out.iter_mut().enumerate() {
+            let (sum, c1) = self.0[i].overflowing_add(rhs.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        (U256(out), carry)
+    }
+
+    /// Subtraction returning the wrapped result and whether it underflowed
+    pub fn overflowing_sub(self, rhs: Self) -> (Self, bool) {
+        let mut out = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in out.iter_mut().enumerate() {
+            let (diff, b1) = self.0[i].overflowing_sub(rhs.0[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+        (U256(out), borrow)
+    }
+
+    /// Multiplication returning the wrapped result and whether it overflowed
+    pub fn overflowing_mul(self, rhs: Self) -> (Self, bool) {
+        let mut out = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let product = self.0[i] as u128 * rhs.0[j] as u128 + out[i + j] as u128 + carry;
+                out[i + j] = product as u64;
+                carry = product >> 64;
+            }
+            out[i + 4] = carry as u64;
+        }
+        let overflow = out[4..].iter().any(|&limb| limb != 0);
+        (U256([out[0], out[1], out[2], out[3]]), overflow)
+    }
+
+    /// Quotient and remainder, or `None` when dividing by zero
+    pub fn div_rem(self, rhs: Self) -> Option<(Self, Self)> {
+        if rhs.is_zero() {
+            return None;
+        }
+        if self < rhs {
+            return Some((Self::ZERO, self));
+        }
+
+        // Binary long division, one bit at a time
+        let mut quotient = Self::ZERO;
+        let mut remainder = Self::ZERO;
+        for i in (0..self.bits()).rev() {
+            remainder = remainder.shl1();
+            remainder.0[0] |= self.bit(i) as u64;
+            if remainder >= rhs {
+                remainder = remainder.overflowing_sub(rhs).0;
+                quotient.0[(i / 64) as usize] |= 1 << (i % 64);
+            }
+        }
+        Some((quotient, remainder))
+    }
+
+    /// Checked addition
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        match self.overflowing_add(rhs) {
+            (value, false) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Checked subtraction
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        match self.overflowing_sub(rhs) {
+            (value, false) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Checked multiplication
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        match self.overflowing_mul(rhs) {
+            (value, false) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Checked division
+    pub fn checked_div(self, rhs: Self) -> Option<Self> {
+        self.div_rem(rhs).map(|(quotient, _)| quotient)
+    }
+
+    /// Checked remainder
+    pub fn checked_rem(self, rhs: Self) -> Option<Self> {
+        self.div_rem(rhs).map(|(_, remainder)| remainder)
+    }
+
+    /// Saturating addition
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        self.checked_add(rhs).unwrap_or(Self::MAX)
+    }
+
+    /// Saturating subtraction
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        self.checked_sub(rhs).unwrap_or(Self::ZERO)
+    }
+
+    /// Saturating multiplication
+    pub fn saturating_mul(self, rhs: Self) -> Self {
+        self.checked_mul(rhs).unwrap_or(Self::MAX)
+    }
+
+    /// Parse a string of digits in the given radix (2 to 36), ignoring `_`
+    /// separators
+    pub fn from_str_radix(digits: &str, radix: u32) -> Result<Self, ParseError> {
+        if !(2..=36).contains(&radix) {
+            return Err(ParseError::InvalidRadix(radix));
+        }
+        if digits.chars().all(|c| c == '_') {
+            return Err(ParseError::Empty);
+        }
+
+        let base = U256::from(radix);
+        let mut value = Self::ZERO;
+        for c in digits.chars() {
+            if c == '_' {
+                continue;
+            }
+            let digit = c.to_digit(radix).ok_or(ParseError::InvalidCharacter(c))?;
+            value = value
+                .checked_mul(base)
+                .and_then(|v| v.checked_add(U256::from(digit)))
+                .ok_or(ParseError::Overflow)?;
+        }
+        Ok(value)
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+macro_rules! impl_from_uint {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for U256 {
+                fn from(value: $ty) -> Self {
+                    U256::from_u128(value as u128)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_uint!(u8, u16, u32, u64, u128, usize);
+
+impl Add for U256 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        self.checked_add(rhs).expect("attempt to add with overflow")
+    }
+}
+
+impl Sub for U256 {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self.checked_sub(rhs).expect("attempt to subtract with overflow")
+    }
+}
+
+impl Mul for U256 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        self.checked_mul(rhs).expect("attempt to multiply with overflow")
+    }
+}
+
+impl Div for U256 {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        self.checked_div(rhs).expect("attempt to divide by zero")
+    }
+}
+
+impl Rem for U256 {
+    type Output = Self;
+
+    fn rem(self, rhs: Self) -> Self {
+        self.checked_rem(rhs)
+            .expect("attempt to calculate the remainder with a divisor of zero")
+    }
+}
+
+impl AddAssign for U256 {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for U256 {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl MulAssign for U256 {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl Sum for U256 {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a U256> for U256 {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
+
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return f.pad_integral(true, "", "0");
+        }
+
+        // Peel off 19 decimal digits at a time (largest power of ten in a u64)
+        let chunk = U256::from(10_000_000_000_000_000_000u64);
+        let mut chunks = Vec::new();
+        let mut rest = *self;
+        while !rest.is_zero() {
+            let (quotient, remainder) = rest.div_rem(chunk).unwrap();
+            chunks.push(remainder.0[0]);
+            rest = quotient;
+        }
+
+        let mut digits = chunks.pop().unwrap().to_string();
+        for chunk in chunks.iter().rev() {
+            digits.push_str(&format!("{:019}", chunk));
+        }
+        f.pad_integral(true, "", &digits)
+    }
+}
+
+impl fmt::LowerHex for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut digits = String::new();
+        for limb in self.0.iter().rev() {
+            if digits.is_empty() {
+                if *limb != 0 {
+                    digits = format!("{:x}", limb);
+                }
+            } else {
+                digits.push_str(&format!("{:016x}", limb));
+            }
+        }
+        if digits.is_empty() {
+            digits.push('0');
+        }
+        f.pad_integral(true, "0x", &digits)
+    }
+}
+
+impl FromStr for U256 {
+    type Err = ParseError;
+
+    /// Parse a decimal string, or a hex string with a `0x` prefix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => Self::from_str_radix(hex, 16),
+            None => Self::from_str_radix(s, 10),
+        }
+    }
+}
+
+impl Serialize for U256 {
+    /// Decimal string in human-readable formats, 32 big-endian bytes otherwise
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            byte_array::serialize(&self.to_be_bytes(), serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for U256 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct U256Visitor;
+
+        impl de::Visitor<'_> for U256Visitor {
+            type Value = U256;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a decimal or 0x-prefixed hex string, or an unsigned integer")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<U256, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<U256, E> {
+                Ok(U256::from(v))
+            }
+
+            fn visit_u128<E: de::Error>(self, v: u128) -> Result<U256, E> {
+                Ok(U256::from(v))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(U256Visitor)
+        } else {
+            byte_array::deserialize(deserializer).map(U256::from_be_bytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic() {
+        let a = U256::from(u128::MAX);
+        let b = a + U256::ONE;
+        assert_eq!(b.to_u128(), None);
+        assert_eq!(b - U256::ONE, a);
+        assert_eq!((a * a) / a, a);
+        // 2^128 = 2^(3 * 42) * 4, and 2^3 = 1 (mod 7)
+        assert_eq!(b % U256::from(7u64), U256::from(4u64));
+
+        assert_eq!(U256::MAX.checked_add(U256::ONE), None);
+        assert_eq!(U256::ZERO.checked_sub(U256::ONE), None);
+        assert_eq!(U256::MAX.checked_mul(U256::from(2u64)), None);
+        assert_eq!(U256::ONE.checked_div(U256::ZERO), None);
+        assert_eq!(U256::ZERO.saturating_sub(U256::ONE), U256::ZERO);
+    }
+
+    #[test]
+    fn test_string_round_trip() {
+        let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(U256::MAX.to_string(), max);
+        assert_eq!(max.parse::<U256>().unwrap(), U256::MAX);
+        assert_eq!(format!("{:x}", U256::from(255u64)), "ff");
+        assert_eq!("0xff".parse::<U256>().unwrap(), U256::from(255u64));
+        assert_eq!(U256::ZERO.to_string(), "0");
+
+        assert_eq!(format!("{}0", max).parse::<U256>(), Err(ParseError::Overflow));
+        assert_eq!("12a".parse::<U256>(), Err(ParseError::InvalidCharacter('a')));
+        assert_eq!("1_000".parse::<U256>().unwrap(), U256::from(1000u64));
+        assert_eq!("_".parse::<U256>(), Err(ParseError::Empty));
+        assert_eq!("0x__".parse::<U256>(), Err(ParseError::Empty));
+        assert_eq!(U256::from_str_radix("10", 37), Err(ParseError::InvalidRadix(37)));
+        assert_eq!(U256::from_str_radix("10", 1), Err(ParseError::InvalidRadix(1)));
+    }
+
+    #[test]
+    fn test_serde() {
+        let value = U256::from_u128(32_000_000_000_000_000_000);
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, "\"32000000000000000000\"");
+        assert_eq!(serde_json::from_str::<U256>(&json).unwrap(), value);
+        assert_eq!(serde_json::from_str::<U256>("42").unwrap(), U256::from(42u64));
+
+        let encoded = bincode::serialize(&value).unwrap();
+        assert_eq!(encoded.len(), 32);
+        assert_eq!(bincode::deserialize::<U256>(&encoded).unwrap(), value);
+    }
+}
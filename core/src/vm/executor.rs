@@ -0,0 +1,668 @@
+//! Transaction execution (validate, then execute)
+//!
+//! Validation checks the nonce, the sender authorization and who pays the
+//! fees. Smart accounts and paymasters are validated by the logic attached
+//! to their contract code, so a wallet can implement multisig, social
+//! recovery or session keys without a protocol change. A transaction that
+//! fails validation is rejected and cannot be included in a block.
+//!
+//! Execution then charges the fee payer, bumps the sender nonce and applies
+//! the call. The call runs under a journal checkpoint, so a failing call
+//! still pays for its gas but its other effects are reverted; the failure is
+//! recorded in the receipt.
+//!
+//! A successful call emits native logs from [`NATIVE_LOG_ADDRESS`]: one for
+//! the value transfer and one for a contract creation, so transfers can be
+//! found through the log index like any contract event.
+
+use crate::storage::{AccountState, JournaledState, StorageError, StorageResult};
+use crate::types::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Gas charged for every transaction
+pub const TX_BASE_GAS: Gas = 21_000;
+
+/// Gas charged per byte of transaction data
+pub const TX_DATA_BYTE_GAS: Gas = 16;
+
+/// Additional gas charged for contract creation
+pub const CONTRACT_CREATION_GAS: Gas = 32_000;
+
+/// Gas charged per signature of a multisig authorization
+pub const MULTISIG_SIGNATURE_GAS: Gas = 3_000;
+
+/// Emitter of the logs produced by the executor itself
+pub const NATIVE_LOG_ADDRESS: Address = Address([0; 20]);
+
+/// Value transfer: topics are the event, sender and recipient; data is the value
+pub const TRANSFER_EVENT: &str = "Transfer(address,address,uint256)";
+
+/// Contract creation: topics are the event, creator and contract address
+pub const CONTRACT_CREATED_EVENT: &str = "ContractCreated(address,address)";
+
+/// Topic identifying an event by its signature
+pub fn event_topic(signature: &str) -> [u8; 32] {
+    blake3::hash(signature.as_bytes()).into()
+}
+
+/// Address as a log topic (left-padded with zeroes)
+pub fn address_topic(address: &Address) -> [u8; 32] {
+    let mut topic = [0; 32];
+    topic[12..].copy_from_slice(&address.0);
+    topic
+}
+
+/// Transaction validation errors (the transaction cannot be included)
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionError {
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    #[error("Invalid nonce: expected {expected}, got {actual}")]
+    InvalidNonce { expected: Nonce, actual: Nonce },
+
+    #[error("Fee cap below base fee")]
+    FeeTooLow,
+
+    #[error("Gas limit {limit} below required {required}")]
+    GasLimitTooLow { limit: Gas, required: Gas },
+
+    #[error("Insufficient balance")]
+    InsufficientBalance,
+
+    #[error("No validation logic for account {0}")]
+    NoValidator(Address),
+
+    #[error("Validation failed: {0}")]
+    ValidationFailed(String),
+
+    #[error("Paymaster rejected transaction: {0}")]
+    PaymasterRejected(String),
+
+    #[error("State unavailable: {0}")]
+    State(String),
+}
+
+impl From<StorageError> for ExecutionError {
+    fn from(error: StorageError) -> Self {
+        ExecutionError::State(error.to_string())
+    }
+}
+
+/// Result type for execution operations
+pub type ExecutionResult<T> = Result<T, ExecutionError>;
+
+/// Block-level parameters for execution
+#[derive(Debug, Clone)]
+pub struct BlockContext {
+    /// Number of the block being built or imported
+    pub number: BlockNumber,
+    /// Base fee of the block
+    pub base_fee: Balance,
+    /// Proposer receiving priority fees
+    pub proposer: Address,
+}
+
+/// Validation logic attached to contract code (smart accounts, paymasters)
+///
+/// Returns the gas consumed by validation, which is charged to the fee payer.
+pub trait AccountValidator: Send + Sync {
+    /// Check that `account` authorizes `tx`, given the authorization data
+    fn validate_transaction(
+        &self,
+        account: &Address,
+        tx: &Transaction,
+        data: &[u8],
+    ) -> Result<Gas, String>;
+
+    /// Check that `paymaster` agrees to pay the fees of `tx`
+    fn validate_paymaster(
+        &self,
+        _paymaster: &Address,
+        _tx: &Transaction,
+        _data: &[u8],
+    ) -> Result<Gas, String> {
+        Err("Account is not a paymaster".to_string())
+    }
+}
+
+/// Outcome of the validation phase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatedTransaction {
+    /// Price paid per unit of gas
+    pub gas_price: Balance,
+    /// Intrinsic gas plus gas used by validation logic
+    pub gas_used: Gas,
+    /// Multisig configuration to store in a sender account not yet in state
+    pub new_multisig: Option<MultisigConfig>,
+}
+
+/// Transaction executor
+pub struct TransactionExecutor {
+    validators: HashMap<BlockHash, Arc<dyn AccountValidator>>,
+}
+
+impl TransactionExecutor {
+    /// Create executor with no validation logic registered
+    pub fn new() -> Self {
+        Self {
+            validators: HashMap::new(),
+        }
+    }
+
+    /// Register validation logic for accounts whose code has the given hash
+    pub fn register_validator(&mut self, code_hash: BlockHash, validator: Arc<dyn AccountValidator>) {
+        self.validators.insert(code_hash, validator);
+    }
+
+    fn validator_for<S: AccountState + ?Sized>(
+        &self,
+        state: &S,
+        address: &Address,
+    ) -> ExecutionResult<&Arc<dyn AccountValidator>> {
+        state
+            .get_account(address)?
+            .and_then(|account| account.code_hash)
+            .and_then(|code_hash| self.validators.get(&code_hash))
+            .ok_or(ExecutionError::NoValidator(*address))
+    }
+
+    /// Gas charged before any validation or execution
+    pub fn intrinsic_gas(tx: &Transaction) -> Gas {
+        let mut gas = TX_BASE_GAS + tx.data.len() as Gas * TX_DATA_BYTE_GAS;
+        if tx.to.is_none() {
+            gas += CONTRACT_CREATION_GAS;
+        }
+        gas
+    }
+
+    /// Validation phase: no state is modified
+    pub fn validate<S: AccountState + ?Sized>(
+        &self,
+        state: &S,
+        tx: &Transaction,
+        context: &BlockContext,
+    ) -> ExecutionResult<ValidatedTransaction> {
+        let sender = state.get_account(&tx.from)?.unwrap_or_default();
+        if tx.nonce != sender.nonce {
+            return Err(ExecutionError::InvalidNonce {
+                expected: sender.nonce,
+                actual: tx.nonce,
+            });
+        }
+
+        let mut new_multisig = None;
+        let mut validation_gas = match &tx.authorization {
+            Authorization::Ed25519 { .. } => {
+                if !tx.verify_signature() {
+                    return Err(ExecutionError::InvalidSignature);
+                }
+                0
+            }
+            Authorization::Multisig { config, signatures } => {
+                // A stored configuration wins; a provided one must match the address
+                let config = match (&sender.multisig, config) {
+                    (Some(stored), _) => stored,
+                    (None, Some(provided))
+                        if sender.code_hash.is_none() && Address::multisig(provided) == tx.from =>
+                    {
+                        new_multisig = Some(provided.clone());
+                        provided
+                    }
+                    _ => return Err(ExecutionError::InvalidSignature),
+                };
+
+                if !config.verify(&tx.signing_hash(), signatures) {
+                    return Err(ExecutionError::InvalidSignature);
+                }
+                signatures.len() as Gas * MULTISIG_SIGNATURE_GAS
+            }
+            Authorization::Contract { data } => self
+                .validator_for(state, &tx.from)?
+                .validate_transaction(&tx.from, tx, data)
+                .map_err(ExecutionError::ValidationFailed)?,
+        };
+
+        if let Some(paymaster) = &tx.paymaster {
+            validation_gas += self
+                .validator_for(state, &paymaster.address)?
+                .validate_paymaster(&paymaster.address, tx, &paymaster.data)
+                .map_err(ExecutionError::PaymasterRejected)?;
+        }
+
+        let gas_price = tx
+            .effective_gas_price(context.base_fee)
+            .ok_or(ExecutionError::FeeTooLow)?;
+
+        let required = Self::intrinsic_gas(tx).saturating_add(validation_gas);
+        if tx.gas_limit < required {
+            return Err(ExecutionError::GasLimitTooLow {
+                limit: tx.gas_limit,
+                required,
+            });
+        }
+
+        // The payer must cover the full gas limit up front, and the sender the value
+        let max_fee = Balance::from(tx.gas_limit)
+            .checked_mul(gas_price)
+            .ok_or(ExecutionError::InsufficientBalance)?;
+        let payer = tx.fee_payer();
+        let (payer_needs, sender_needs) = if payer == tx.from {
+            let total = max_fee
+                .checked_add(tx.value)
+                .ok_or(ExecutionError::InsufficientBalance)?;
+            (total, total)
+        } else {
+            (max_fee, tx.value)
+        };
+
+        let payer_balance = state.get_account(&payer)?.unwrap_or_default().balance;
+        if payer_balance < payer_needs || sender.balance < sender_needs {
+            return Err(ExecutionError::InsufficientBalance);
+        }
+
+        Ok(ValidatedTransaction {
+            gas_price,
+            gas_used: required,
+            new_multisig,
+        })
+    }
+
+    /// Validate and execute a transaction, returning its receipt
+    pub fn execute<S: AccountState + ?Sized>(
+        &self,
+        state: &mut S,
+        tx: &Transaction,
+        context: &BlockContext,
+    ) -> ExecutionResult<Receipt> {
+        let validated = self.validate(state, tx, context)?;
+        let payer = tx.fee_payer();
+        let mut state = JournaledState::new(state);
+
+        // Charge the full gas limit and bump the nonce; these stick even if the call fails
+        let max_fee = Balance::from(tx.gas_limit) * validated.gas_price;
+        update_account(&mut state, &payer, |account| account.balance -= max_fee)?;
+        update_account(&mut state, &tx.from, |account| {
+            account.nonce += 1;
+            if let Some(config) = validated.new_multisig.clone() {
+                account.multisig = Some(config);
+            }
+        })?;
+
+        state.checkpoint();
+        let (success, contract_address) = apply_call(&mut state, tx)?;
+        if success {
+            state.commit_checkpoint();
+        } else {
+            state.revert_checkpoint();
+        }
+
+        // Refund unused gas; the base fee is burned and the tip goes to the proposer
+        let gas_used = validated.gas_used;
+        let refund = Balance::from(tx.gas_limit - gas_used) * validated.gas_price;
+        update_account(&mut state, &payer, |account| account.balance += refund)?;
+
+        let tip = Balance::from(gas_used) * (validated.gas_price - context.base_fee);
+        if !tip.is_zero() {
+            update_account(&mut state, &context.proposer, |account| account.balance += tip)?;
+        }
+        state.flush();
+
+        let logs = if success {
+            native_logs(tx, contract_address)
+        } else {
+            vec![]
+        };
+        Ok(Receipt {
+            tx_hash: tx.hash(),
+            block_number: context.number,
+            gas_used,
+            success,
+            logs_bloom: Bloom::from_logs(&logs),
+            logs,
+            contract_address,
+        })
+    }
+}
+
+impl Default for TransactionExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn update_account<S: AccountState + ?Sized>(
+    state: &mut S,
+    address: &Address,
+    update: impl FnOnce(&mut Account),
+) -> StorageResult<()> {
+    let mut account = state.get_account(address)?.unwrap_or_default();
+    update(&mut account);
+    state.set_account(*address, account);
+    Ok(())
+}
+
+/// Apply the value transfer or contract creation of a transaction
+fn apply_call<S: AccountState + ?Sized>(
+    state: &mut S,
+    tx: &Transaction,
+) -> StorageResult<(bool, Option<Address>)> {
+    match tx.to {
+        Some(to) => {
+            transfer(state, &tx.from, &to, tx.value)?;
+            Ok((true, None))
+        }
+        None => {
+            let address = Address::contract(&tx.from, tx.nonce);
+            transfer(state, &tx.from, &address, tx.value)?;
+
+            let mut account = state.get_account(&address)?.unwrap_or_default();
+            if account.code_hash.is_some() {
+                return Ok((false, None));
+            }
+            account.code_hash = Some(state.insert_code(tx.data.clone()));
+            state.set_account(address, account);
+            Ok((true, Some(address)))
+        }
+    }
+}
+
+/// Logs recording the effects of a successful call
+fn native_logs(tx: &Transaction, contract_address: Option<Address>) -> Vec<Log> {
+    let mut logs = vec![];
+    let Some(recipient) = tx.to.or(contract_address) else {
+        return logs;
+    };
+    if let Some(contract) = contract_address {
+        logs.push(Log {
+            address: NATIVE_LOG_ADDRESS,
+            topics: vec![
+                event_topic(CONTRACT_CREATED_EVENT),
+                address_topic(&tx.from),
+                address_topic(&contract),
+            ],
+            data: vec![],
+        });
+    }
+    if !tx.value.is_zero() {
+        logs.push(Log {
+            address: NATIVE_LOG_ADDRESS,
+            topics: vec![
+                event_topic(TRANSFER_EVENT),
+                address_topic(&tx.from),
+                address_topic(&recipient),
+            ],
+            data: tx.value.to_be_bytes().to_vec(),
+        });
+    }
+    logs
+}
+
+fn transfer<S: AccountState + ?Sized>(
+    state: &mut S,
+    from: &Address,
+    to: &Address,
+    value: Balance,
+) -> StorageResult<()> {
+    if value.is_zero() {
+        return Ok(());
+    }
+    update_account(state, from, |account| account.balance -= value)?;
+    update_account(state, to, |account| account.balance += value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::code_hash;
+    use ed25519_dalek::SigningKey;
+
+    impl AccountState for HashMap<Address, Account> {
+        fn get_account(&self, address: &Address) -> StorageResult<Option<Account>> {
+            Ok(self.get(address).cloned())
+        }
+
+        fn set_account(&mut self, address: Address, account: Account) {
+            self.insert(address, account);
+        }
+
+        fn get_storage(&self, _: &Address, _: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn set_storage(&mut self, _: Address, _: Vec<u8>, _: Option<Vec<u8>>) {
+            unreachable!("transfers do not touch contract storage")
+        }
+
+        fn get_code(&self, _: &BlockHash) -> StorageResult<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn insert_code(&mut self, code: Vec<u8>) -> BlockHash {
+            code_hash(&code)
+        }
+    }
+
+    /// Accepts authorization data equal to a fixed password; pays for anyone
+    struct PasswordValidator(&'static [u8]);
+
+    impl AccountValidator for PasswordValidator {
+        fn validate_transaction(&self, _: &Address, _: &Transaction, data: &[u8]) -> Result<Gas, String> {
+            if data == self.0 {
+                Ok(5_000)
+            } else {
+                Err("Wrong password".to_string())
+            }
+        }
+
+        fn validate_paymaster(&self, _: &Address, _: &Transaction, _: &[u8]) -> Result<Gas, String> {
+            Ok(1_000)
+        }
+    }
+
+    const WALLET_CODE: BlockHash = BlockHash([0xaa; 32]);
+
+    fn context() -> BlockContext {
+        BlockContext {
+            number: 1,
+            base_fee: Balance::from(10u64),
+            proposer: Address([0xff; 20]),
+        }
+    }
+
+    fn funded(balance: u64, code_hash: Option<BlockHash>) -> Account {
+        Account {
+            balance: Balance::from(balance),
+            code_hash,
+            ..Default::default()
+        }
+    }
+
+    fn transaction(from: Address, authorization: Authorization) -> Transaction {
+        Transaction {
+            from,
+            to: Some(Address([2; 20])),
+            value: Balance::from(100u64),
+            data: vec![],
+            gas_limit: 50_000,
+            kind: TxKind::DynamicFee {
+                max_fee_per_gas: Balance::from(20u64),
+                max_priority_fee_per_gas: Balance::from(2u64),
+                access_list: vec![],
+            },
+            nonce: 0,
+            paymaster: None,
+            authorization,
+        }
+    }
+
+    fn executor() -> TransactionExecutor {
+        let mut executor = TransactionExecutor::new();
+        executor.register_validator(WALLET_CODE, Arc::new(PasswordValidator(b"secret")));
+        executor
+    }
+
+    #[test]
+    fn test_key_signed_transfer() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let sender = PublicKey(key.verifying_key().to_bytes()).to_address();
+        let mut state = HashMap::from([(sender, funded(1_000_000, None))]);
+
+        let mut tx = transaction(sender, Authorization::Contract { data: vec![] });
+        tx.sign(&key);
+
+        let receipt = executor().execute(&mut state, &tx, &context()).unwrap();
+        assert!(receipt.success);
+        assert_eq!(receipt.gas_used, TX_BASE_GAS);
+
+        // 21_000 gas at 12 per unit, plus the value
+        assert_eq!(state[&sender].balance, Balance::from(1_000_000u64 - 252_000 - 100));
+        assert_eq!(state[&sender].nonce, 1);
+        assert_eq!(state[&Address([2; 20])].balance, Balance::from(100u64));
+        assert_eq!(state[&context().proposer].balance, Balance::from(42_000u64));
+
+        // The transfer is logged and indexed in the bloom
+        assert_eq!(receipt.logs.len(), 1);
+        let log = &receipt.logs[0];
+        assert_eq!(log.address, NATIVE_LOG_ADDRESS);
+        assert_eq!(
+            log.topics,
+            vec![
+                event_topic(TRANSFER_EVENT),
+                address_topic(&sender),
+                address_topic(&Address([2; 20])),
+            ]
+        );
+        assert_eq!(log.data, Balance::from(100u64).to_be_bytes().to_vec());
+        assert_eq!(receipt.logs_bloom, Bloom::from_logs(&receipt.logs));
+
+        // Replaying fails the nonce check
+        assert_eq!(
+            executor().execute(&mut state, &tx, &context()).unwrap_err(),
+            ExecutionError::InvalidNonce { expected: 1, actual: 0 }
+        );
+    }
+
+    #[test]
+    fn test_failed_call_only_charges_gas() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let sender = PublicKey(key.verifying_key().to_bytes()).to_address();
+        let existing = Address::contract(&sender, 0);
+        let mut state = HashMap::from([
+            (sender, funded(10_000_000, None)),
+            (existing, funded(0, Some(WALLET_CODE))),
+        ]);
+
+        // Creating a contract over existing code fails after the value moved
+        let mut tx = transaction(sender, Authorization::Contract { data: vec![] });
+        tx.to = None;
+        tx.gas_limit = 100_000;
+        tx.sign(&key);
+
+        let receipt = executor().execute(&mut state, &tx, &context()).unwrap();
+        assert!(!receipt.success);
+        assert_eq!(receipt.contract_address, None);
+        assert!(receipt.logs.is_empty());
+        assert_eq!(receipt.logs_bloom, Bloom::default());
+
+        let fee = Balance::from(receipt.gas_used) * Balance::from(12u64);
+        assert_eq!(state[&sender].balance, Balance::from(10_000_000u64) - fee);
+        assert_eq!(state[&sender].nonce, 1);
+        assert_eq!(state[&existing].balance, Balance::ZERO);
+        assert_eq!(state[&existing].code_hash, Some(WALLET_CODE));
+    }
+
+    #[test]
+    fn test_smart_account_validation() {
+        let wallet = Address([7; 20]);
+        let mut state = HashMap::from([(wallet, funded(1_000_000, Some(WALLET_CODE)))]);
+
+        let tx = transaction(wallet, Authorization::Contract { data: b"wrong".to_vec() });
+        assert_eq!(
+            executor().execute(&mut state, &tx, &context()).unwrap_err(),
+            ExecutionError::ValidationFailed("Wrong password".to_string())
+        );
+
+        let tx = transaction(wallet, Authorization::Contract { data: b"secret".to_vec() });
+        let receipt = executor().execute(&mut state, &tx, &context()).unwrap();
+        assert_eq!(receipt.gas_used, TX_BASE_GAS + 5_000);
+        assert_eq!(state[&wallet].nonce, 1);
+
+        // An account without validation logic cannot use contract authorization
+        let plain = Address([8; 20]);
+        state.insert(plain, funded(1_000_000, None));
+        let tx = transaction(plain, Authorization::Contract { data: b"secret".to_vec() });
+        assert_eq!(
+            executor().validate(&state, &tx, &context()),
+            Err(ExecutionError::NoValidator(plain))
+        );
+    }
+
+    #[test]
+    fn test_native_multisig() {
+        let keys: Vec<_> = (1..=3u8).map(|i| SigningKey::from_bytes(&[i; 32])).collect();
+        let config = MultisigConfig {
+            threshold: 2,
+            signers: keys.iter().map(|k| PublicKey(k.verifying_key().to_bytes())).collect(),
+        };
+        let treasury = Address::multisig(&config);
+        let mut state = HashMap::from([(treasury, funded(1_000_000, None))]);
+
+        // One signature is below the threshold
+        let mut tx = transaction(treasury, Authorization::Contract { data: vec![] });
+        tx.add_multisig_signature(2, &keys[2]);
+        if let Authorization::Multisig { config: provided, .. } = &mut tx.authorization {
+            *provided = Some(config.clone());
+        }
+        assert_eq!(
+            executor().validate(&state, &tx, &context()),
+            Err(ExecutionError::InvalidSignature)
+        );
+
+        // The first transaction installs the configuration in state
+        tx.add_multisig_signature(0, &keys[0]);
+        let receipt = executor().execute(&mut state, &tx, &context()).unwrap();
+        assert_eq!(receipt.gas_used, TX_BASE_GAS + 2 * MULTISIG_SIGNATURE_GAS);
+        assert_eq!(state[&treasury].multisig, Some(config));
+
+        // Later transactions only carry signatures
+        let mut tx = transaction(treasury, Authorization::Contract { data: vec![] });
+        tx.nonce = 1;
+        tx.add_multisig_signature(1, &keys[1]);
+        tx.add_multisig_signature(0, &keys[0]);
+        assert!(executor().execute(&mut state, &tx, &context()).is_ok());
+
+        // A key outside the signer set does not count
+        let mut tx = transaction(treasury, Authorization::Contract { data: vec![] });
+        tx.nonce = 2;
+        tx.add_multisig_signature(0, &keys[0]);
+        tx.add_multisig_signature(1, &SigningKey::from_bytes(&[9; 32]));
+        assert_eq!(
+            executor().validate(&state, &tx, &context()),
+            Err(ExecutionError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_paymaster_pays_fees() {
+        let wallet = Address([7; 20]);
+        let sponsor = Address([9; 20]);
+        let mut state = HashMap::from([
+            (wallet, funded(100, Some(WALLET_CODE))),
+            (sponsor, funded(1_000_000, Some(WALLET_CODE))),
+        ]);
+
+        let mut tx = transaction(wallet, Authorization::Contract { data: b"secret".to_vec() });
+        tx.paymaster = Some(Paymaster {
+            address: sponsor,
+            data: vec![],
+        });
+
+        let receipt = executor().execute(&mut state, &tx, &context()).unwrap();
+        let fee = Balance::from(receipt.gas_used) * Balance::from(12u64);
+        assert_eq!(state[&wallet].balance, Balance::ZERO);
+        assert_eq!(state[&sponsor].balance, Balance::from(1_000_000u64) - fee);
+    }
+}
@@ -3,6 +3,7 @@
 pub mod wasm_engine;
 pub mod evm_compat;
 pub mod parallel_executor;
+pub mod executor;
 
 // Placeholder
 pub use wasm_engine::*;
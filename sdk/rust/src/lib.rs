@@ -1,6 +1,9 @@
 //! QuantumChain Rust SDK
 
 pub use quantum_core::types::*;
+pub use quantum_core::crypto::merkle;
+
+pub mod proof;
 
 /// SDK client for interacting with QuantumChain
 pub struct Client {
@@ -18,7 +21,7 @@ impl Client {
     /// Get account balance
     pub async fn get_balance(&self, _address: &Address) -> Result<Balance, String> {
         // TODO: Implement RPC call
-        Ok(0)
+        Ok(Balance::ZERO)
     }
     
     /// Send transaction
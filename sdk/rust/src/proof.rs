@@ -0,0 +1,53 @@
+//! Standalone verification of state proofs
+//!
+//! Checks balances and storage against a state root taken from a trusted
+//! block header, without trusting the RPC node that served the proof.
+
+use quantum_core::types::*;
+use std::fmt;
+
+pub use quantum_core::storage::{AccountProof, ProofLeaf, StorageProof, TrieProof};
+
+/// Proof does not match the state root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidProof;
+
+impl fmt::Display for InvalidProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Proof does not match state root")
+    }
+}
+
+impl std::error::Error for InvalidProof {}
+
+/// Verify an account proof, returning the account (None if absent)
+pub fn verify_account(
+    state_root: &BlockHash,
+    proof: &AccountProof,
+) -> Result<Option<Account>, InvalidProof> {
+    if proof.verify(state_root) {
+        Ok(proof.account.clone())
+    } else {
+        Err(InvalidProof)
+    }
+}
+
+/// Verify an account proof, returning its balance (zero if absent)
+pub fn verify_balance(
+    state_root: &BlockHash,
+    proof: &AccountProof,
+) -> Result<Balance, InvalidProof> {
+    Ok(verify_account(state_root, proof)?.map_or(Balance::ZERO, |a| a.balance))
+}
+
+/// Verify a storage proof, returning the slot value (None if empty)
+pub fn verify_storage(
+    state_root: &BlockHash,
+    proof: &StorageProof,
+) -> Result<Option<Vec<u8>>, InvalidProof> {
+    if proof.verify(state_root) {
+        Ok(proof.value.clone())
+    } else {
+        Err(InvalidProof)
+    }
+}